@@ -0,0 +1,267 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+
+use cov_viz_ds::CoverageData;
+use exp_viz::{
+    apply_numeric_filter, filter_coverage_data, filter_coverage_data_grouped,
+    filter_coverage_data_indexed, filter_coverage_data_resolutions, merge_filtered_data,
+    prefilter_categorical, CoverageDataIndex, Filter, FilterIntervals, GroupByFacet,
+    SyntheticCoverageDataBuilder,
+};
+
+// Keeps track of how much memory is allocated so the benchmarks can report their peak memory
+// use. The bookkeeping is two atomic operations per allocation, which is small next to the
+// allocation itself.
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK_ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+fn add_allocated(size: usize) {
+    let allocated = ALLOCATED.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK_ALLOCATED.fetch_max(allocated, Ordering::Relaxed);
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            add_allocated(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            if new_size > layout.size() {
+                add_allocated(new_size - layout.size());
+            } else {
+                ALLOCATED.fetch_sub(layout.size() - new_size, Ordering::Relaxed);
+            }
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+// Prints the most memory `f` had allocated at once, on top of what was already allocated
+fn report_peak_memory<T>(name: &str, f: impl FnOnce() -> T) {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    PEAK_ALLOCATED.store(before, Ordering::Relaxed);
+    let result = f();
+    let peak = PEAK_ALLOCATED.load(Ordering::Relaxed) - before;
+    drop(result);
+    println!("{}: peak memory {:.1} MB", name, peak as f64 / 1_000_000.0);
+}
+
+fn coverage_data(seed: u64) -> CoverageData {
+    SyntheticCoverageDataBuilder::new()
+        .seed(seed)
+        .observations(1_000_000)
+        .features(50_000)
+        .categorical_facet("Library", 4)
+        .build()
+}
+
+fn facet_value_id(data: &CoverageData, facet_name: &str, value_name: &str) -> u64 {
+    let facet = data.facets.iter().find(|f| f.name == facet_name).unwrap();
+    *facet
+        .values
+        .as_ref()
+        .unwrap()
+        .iter()
+        .find(|(_, name)| *name == value_name)
+        .unwrap()
+        .0
+}
+
+fn filter_benchmarks(c: &mut Criterion) {
+    let data = coverage_data(0);
+
+    report_peak_memory("no filter", || {
+        filter_coverage_data(&Filter::new(), &data, None).unwrap()
+    });
+    c.bench_function("no filter", |b| {
+        b.iter(|| filter_coverage_data(&Filter::new(), &data, None).unwrap())
+    });
+
+    let mut categorical = Filter::new();
+    categorical
+        .categorical_facets
+        .insert(facet_value_id(&data, "Direction", "Enriched Only"));
+    categorical
+        .categorical_facets
+        .insert(facet_value_id(&data, "Library", "Library 0"));
+    c.bench_function("categorical", |b| {
+        b.iter(|| filter_coverage_data(&categorical, &data, None).unwrap())
+    });
+
+    let mut numeric = Filter::new();
+    numeric.numeric_intervals = Some(FilterIntervals {
+        effect: (-2.0, 2.0),
+        sig: (5.0, 15.0),
+        ..FilterIntervals::new()
+    });
+    c.bench_function("numeric", |b| {
+        b.iter(|| filter_coverage_data(&numeric, &data, None).unwrap())
+    });
+
+    // Changing only the numeric intervals of a categorical filter, in one pass and with the
+    // categorical part done ahead of time
+    let mut categorical_numeric = Filter::new();
+    categorical_numeric.categorical_facets = categorical.categorical_facets.clone();
+    categorical_numeric.numeric_intervals = numeric.numeric_intervals;
+    c.bench_function("categorical + numeric", |b| {
+        b.iter(|| filter_coverage_data(&categorical_numeric, &data, None).unwrap())
+    });
+
+    let selection = prefilter_categorical(&categorical, &data, None).unwrap();
+    let intervals = numeric.numeric_intervals.unwrap();
+    c.bench_function("numeric after prefilter", |b| {
+        b.iter(|| apply_numeric_filter(&selection, &data, &intervals).unwrap())
+    });
+
+    // The most common interactive filter: a lower bound on significance
+    let mut significance = Filter::new();
+    significance.numeric_intervals = Some(FilterIntervals {
+        effect: (f32::MIN, f32::MAX),
+        sig: (10.0, f64::MAX),
+        ..FilterIntervals::new()
+    });
+    let index = CoverageDataIndex::build(&data);
+    c.bench_function("significance", |b| {
+        b.iter(|| filter_coverage_data(&significance, &data, None).unwrap())
+    });
+    c.bench_function("significance, indexed", |b| {
+        b.iter(|| filter_coverage_data_indexed(&significance, &data, &index, None).unwrap())
+    });
+
+    let mut chrom = Filter::new();
+    chrom.chrom = Some(0);
+    c.bench_function("chromosome", |b| {
+        b.iter(|| filter_coverage_data(&chrom, &data, None).unwrap())
+    });
+    c.bench_function("chromosome, indexed", |b| {
+        b.iter(|| filter_coverage_data_indexed(&chrom, &data, &index, None).unwrap())
+    });
+    let mut chrom_numeric = chrom.clone();
+    chrom_numeric.numeric_intervals = numeric.numeric_intervals;
+    c.bench_function("chromosome + numeric", |b| {
+        b.iter(|| filter_coverage_data(&chrom_numeric, &data, None).unwrap())
+    });
+
+    // One result per library, in one pass and one call per library
+    let library = GroupByFacet::Name("Library".to_string());
+    c.bench_function("grouped by library", |b| {
+        b.iter(|| filter_coverage_data_grouped(&Filter::new(), &data, &library, None).unwrap())
+    });
+    let library_filters: Vec<Filter> = (0..4)
+        .map(|i| {
+            let mut filter = Filter::new();
+            filter.categorical_facets.insert(facet_value_id(
+                &data,
+                "Library",
+                &format!("Library {}", i),
+            ));
+            filter
+        })
+        .collect();
+    c.bench_function("one filter per library", |b| {
+        b.iter(|| {
+            library_filters
+                .iter()
+                .map(|filter| filter_coverage_data(filter, &data, None).unwrap())
+                .collect::<Vec<_>>()
+        })
+    });
+
+    // An overview and the full resolution, in one pass and one pass each
+    let resolutions = [data.bucket_size * 10, data.bucket_size];
+    c.bench_function("two resolutions, one pass", |b| {
+        b.iter(|| {
+            filter_coverage_data_resolutions(&Filter::new(), &data, None, &resolutions).unwrap()
+        })
+    });
+    c.bench_function("two resolutions, two passes", |b| {
+        b.iter(|| {
+            resolutions
+                .iter()
+                .map(|resolution| {
+                    filter_coverage_data_resolutions(&Filter::new(), &data, None, &[*resolution])
+                        .unwrap()
+                })
+                .collect::<Vec<_>>()
+        })
+    });
+}
+
+// Filtering without a filter at small, medium, and large observation counts, which are
+// bucketed sequentially, in a few chunks, and in many chunks, respectively
+fn size_benchmarks(c: &mut Criterion) {
+    let mut group = c.benchmark_group("observations");
+    for observation_count in [1_000, 100_000, 10_000_000] {
+        let data = SyntheticCoverageDataBuilder::new()
+            .seed(0)
+            .observations(observation_count)
+            .features(50_000)
+            .build();
+        // The large data set takes seconds per iteration
+        group.sample_size(if observation_count > 1_000_000 {
+            10
+        } else {
+            100
+        });
+        group.throughput(Throughput::Elements(observation_count as u64));
+        report_peak_memory(
+            &format!("no filter, {} observations", observation_count),
+            || filter_coverage_data(&Filter::new(), &data, None).unwrap(),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("no filter", observation_count),
+            &data,
+            |b, data| b.iter(|| filter_coverage_data(&Filter::new(), data, None).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+fn merge_benchmarks(c: &mut Criterion) {
+    let results: Vec<_> = (0..8)
+        .map(|seed| filter_coverage_data(&Filter::new(), &coverage_data(seed), None).unwrap())
+        .collect();
+    let chromosome_list: Vec<String> = results[0]
+        .chromosomes
+        .iter()
+        .map(|c| c.chrom.clone())
+        .collect();
+
+    let (merge_results, merge_chromosome_list) = (results.clone(), chromosome_list.clone());
+    report_peak_memory("merge 8", || {
+        merge_filtered_data(merge_results, merge_chromosome_list).unwrap()
+    });
+    c.bench_function("merge 8", |b| {
+        b.iter_batched(
+            || (results.clone(), chromosome_list.clone()),
+            |(results, chromosome_list)| merge_filtered_data(results, chromosome_list).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group!(
+    benches,
+    filter_benchmarks,
+    size_benchmarks,
+    merge_benchmarks
+);
+criterion_main!(benches);
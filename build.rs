@@ -0,0 +1,17 @@
+// Regenerates the C header, include/exp_viz.h, from src/capi.rs when building with the
+// capi feature
+fn main() {
+    #[cfg(feature = "capi")]
+    generate_header();
+}
+
+#[cfg(feature = "capi")]
+fn generate_header() {
+    println!("cargo:rerun-if-changed=src/capi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    cbindgen::generate(&crate_dir)
+        .expect("Unable to generate the C header")
+        .write_to_file(std::path::Path::new(&crate_dir).join("include/exp_viz.h"));
+}
@@ -0,0 +1,30 @@
+// Filters and merges synthetic coverage data using only the Rust API.
+//
+// cargo run --example filter_merge --features synthetic
+
+use exp_viz::{filter_coverage_data, merge_filtered_data, Filter, SyntheticCoverageDataBuilder};
+
+fn main() {
+    let results: Vec<_> = (0..4)
+        .map(|seed| {
+            let data = SyntheticCoverageDataBuilder::new()
+                .seed(seed)
+                .observations(10_000)
+                .build();
+            filter_coverage_data(&Filter::new(), &data, None).unwrap()
+        })
+        .collect();
+
+    let chromosome_list: Vec<String> = results[0]
+        .chromosomes
+        .iter()
+        .map(|c| c.chrom.clone())
+        .collect();
+    let reo_counts: Vec<u64> = results.iter().map(|r| r.reo_count).collect();
+    let merged = merge_filtered_data(results, chromosome_list).unwrap();
+
+    println!("REO counts: {:?}", reo_counts);
+    println!("Merged REO count: {}", merged.reo_count);
+    println!("Merged sources: {}", merged.sources.len());
+    println!("Merged targets: {}", merged.targets.len());
+}
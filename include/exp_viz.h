@@ -0,0 +1,90 @@
+/* C interface to exp_viz, built with the `capi` feature. See src/capi.rs. */
+
+#ifndef EXP_VIZ_H
+#define EXP_VIZ_H
+
+/* Generated by cbindgen from src/capi.rs when building with the capi feature. Don't edit it by hand. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+/*
+ Loaded coverage data. Safe to filter from multiple threads at once. Handles from
+ exp_viz_clone_handle share the same data, which is freed along with the last handle to it.
+ */
+typedef struct CoverageHandle CoverageHandle;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ Loads the coverage data at `path`. Returns null on failure and, if `error` isn't null, sets
+ `*error` to a message that must be freed with exp_viz_free_string.
+ */
+CoverageHandle *exp_viz_load(const char *path, char **error);
+
+/*
+ Filters the coverage data using the JSON encoded filter, e.g.
+ {"chrom": 3, "categorical_facets": [1, 2], "numeric_intervals": {"effect": [-1, 1], "sig": [2, 10]}}
+ Every field is optional. Chromosomes can also be selected by name, e.g. {"chrom_name": "chr4"}.
+
+ On success, returns 0 and sets `*out_buf` and `*out_len` to the bincode serialized
+ FilteredData, which must be freed with exp_viz_free_bytes. On failure returns non-zero and,
+ if `error` isn't null, sets `*error` (free it with exp_viz_free_string).
+ */
+int32_t exp_viz_filter(const CoverageHandle *handle,
+                       const char *filter_json,
+                       uint8_t **out_buf,
+                       size_t *out_len,
+                       char **error);
+
+/*
+ Merges `count` filter results, e.g., from exp_viz_filter. `bufs[i]` is a bincode
+ serialized FilteredData that's `lens[i]` bytes long. `chromosomes_json` is the JSON encoded
+ list of the chromosomes to include, in order, e.g. ["chr1", "chr2", "chrX"].
+
+ On success, returns 0 and sets `*out_buf` and `*out_len` to the bincode serialized merged
+ FilteredData, which must be freed with exp_viz_free_bytes. On failure returns non-zero and,
+ if `error` isn't null, sets `*error` (free it with exp_viz_free_string).
+ */
+int32_t exp_viz_merge(const uint8_t *const *bufs,
+                      const size_t *lens,
+                      size_t count,
+                      const char *chromosomes_json,
+                      uint8_t **out_buf,
+                      size_t *out_len,
+                      char **error);
+
+/*
+ An estimate of how much memory (in bytes) the loaded coverage data uses. Returns 0 if
+ `handle` is null.
+ */
+size_t exp_viz_mem_usage(const CoverageHandle *handle);
+
+/*
+ A new handle to the same data as `handle`, without copying the data. Free it with
+ exp_viz_free. Returns null if `handle` is null.
+ */
+CoverageHandle *exp_viz_clone_handle(const CoverageHandle *handle);
+
+/*
+ Frees a handle from exp_viz_load or exp_viz_clone_handle
+ */
+void exp_viz_free(CoverageHandle *handle);
+
+/*
+ Frees the results of exp_viz_filter and exp_viz_merge
+ */
+void exp_viz_free_bytes(uint8_t *buf, size_t len);
+
+/*
+ Frees an error message
+ */
+void exp_viz_free_string(char *s);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* EXP_VIZ_H */
@@ -0,0 +1,258 @@
+use rayon::prelude::*;
+use roaring::RoaringTreemap;
+use rustc_hash::FxHashMap;
+use serde::Serialize;
+
+use crate::error::FilterError;
+use crate::filter::ObservationFilter;
+use crate::filter_data_structures::*;
+use cov_viz_ds::{CoverageData, DbID, ExperimentFeatureData, ObservationData};
+
+// Which feature of an observation to aggregate by
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeatureRole {
+    Source,
+    Target,
+}
+
+impl FeatureRole {
+    // The observation's (feature, associated feature) for this role
+    fn features(&self, observation: &ObservationData) -> Option<(DbID, Option<DbID>)> {
+        match self {
+            FeatureRole::Source => Some((observation.source_id, observation.target_id)),
+            FeatureRole::Target => observation
+                .target_id
+                .map(|target_id| (target_id, Some(observation.source_id))),
+        }
+    }
+}
+
+// Statistics of the filtered observations of a single feature
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeatureAggregate {
+    pub count: u64,
+    pub max_log10_sig: f64,
+    pub max_abs_effect: f32,
+    // The features on the other side of the observations, e.g., the sources of a target
+    pub associated_features: RoaringTreemap,
+}
+
+impl FeatureAggregate {
+    fn new() -> Self {
+        FeatureAggregate {
+            count: 0,
+            max_log10_sig: f64::NEG_INFINITY,
+            max_abs_effect: 0.0,
+            associated_features: RoaringTreemap::new(),
+        }
+    }
+
+    fn merge(&mut self, other: FeatureAggregate) {
+        self.count += other.count;
+        self.max_log10_sig = self.max_log10_sig.max(other.max_log10_sig);
+        self.max_abs_effect = max_abs_effect(self.max_abs_effect, other.max_abs_effect);
+        self.associated_features |= other.associated_features;
+    }
+}
+
+// A FeatureAggregate in the form used by the API
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FeatureAggregateRow {
+    pub feature_id: DbID,
+    pub count: u64,
+    pub max_log10_sig: f64,
+    pub max_abs_effect: f32,
+    pub associated_features: Vec<DbID>,
+}
+
+// Folds the observations that pass the filter into per-feature statistics, keyed by the
+// source or target feature id. When the filter is restricted to a chromosome, only features
+// on that chromosome are included.
+pub fn aggregate_by_feature(
+    filters: &Filter,
+    data: &CoverageData,
+    included_features: Option<&ExperimentFeatureData>,
+    kind: FeatureRole,
+) -> Result<FxHashMap<DbID, FeatureAggregate>, FilterError> {
+    let chrom = filters.chromosome_index(&data.chromosomes)?;
+    let predicate = ObservationFilter::new(filters, data, included_features);
+    let on_chromosome = |feature_id: DbID| match chrom {
+        Some(chrom) => data
+            .feature_buckets
+            .get(&feature_id)
+            .is_some_and(|bucket| bucket.chrom == chrom),
+        None => true,
+    };
+
+    Ok(predicate
+        .observations(data)
+        .filter(|observation| predicate.matches(observation))
+        .filter_map(|observation| {
+            kind.features(observation)
+                .filter(|(feature_id, _)| on_chromosome(*feature_id))
+                .map(|features| (observation, features))
+        })
+        .fold(
+            FxHashMap::default,
+            |mut aggregates: FxHashMap<DbID, FeatureAggregate>,
+             (observation, (feature_id, associated_id))| {
+                let aggregate = aggregates
+                    .entry(feature_id)
+                    .or_insert_with(FeatureAggregate::new);
+                aggregate.count += 1;
+                aggregate.max_log10_sig = aggregate
+                    .max_log10_sig
+                    .max(predicate.significance(observation));
+                aggregate.max_abs_effect =
+                    max_abs_effect(aggregate.max_abs_effect, observation.effect_size);
+                if let Some(associated_id) = associated_id {
+                    aggregate.associated_features.insert(associated_id);
+                }
+                aggregates
+            },
+        )
+        .reduce(FxHashMap::default, |mut aggregates1, aggregates2| {
+            for (feature_id, aggregate) in aggregates2 {
+                match aggregates1.get_mut(&feature_id) {
+                    Some(aggregate1) => aggregate1.merge(aggregate),
+                    None => {
+                        aggregates1.insert(feature_id, aggregate);
+                    }
+                }
+            }
+            aggregates1
+        }))
+}
+
+// The aggregates as rows, most significant first (ties are broken by feature id). If there's
+// a limit only the first `limit` are returned.
+pub fn top_features(
+    aggregates: &FxHashMap<DbID, FeatureAggregate>,
+    limit: Option<usize>,
+) -> Vec<FeatureAggregateRow> {
+    let mut features: Vec<(&DbID, &FeatureAggregate)> = aggregates.iter().collect();
+    features.par_sort_unstable_by(|(id1, aggregate1), (id2, aggregate2)| {
+        aggregate2
+            .max_log10_sig
+            .total_cmp(&aggregate1.max_log10_sig)
+            .then(id1.cmp(id2))
+    });
+    if let Some(limit) = limit {
+        features.truncate(limit);
+    }
+
+    features
+        .into_iter()
+        .map(|(feature_id, aggregate)| FeatureAggregateRow {
+            feature_id: *feature_id,
+            count: aggregate.count,
+            max_log10_sig: aggregate.max_log10_sig,
+            max_abs_effect: aggregate.max_abs_effect,
+            associated_features: aggregate.associated_features.iter().collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::{coverage_data, facet_value_id};
+
+    // The aggregate of the target's observations that `matches`, computed one by one
+    fn manual_aggregate(
+        data: &CoverageData,
+        target_id: DbID,
+        matches: impl Fn(&ObservationData) -> bool,
+    ) -> FeatureAggregate {
+        let mut aggregate = FeatureAggregate::new();
+        for observation in data
+            .significant_observations
+            .iter()
+            .chain(data.nonsignificant_observations.iter())
+            .filter(|o| o.target_id == Some(target_id) && matches(o))
+        {
+            aggregate.count += 1;
+            aggregate.max_log10_sig = aggregate
+                .max_log10_sig
+                .max(observation.neg_log_significance);
+            aggregate.max_abs_effect =
+                max_abs_effect(aggregate.max_abs_effect, observation.effect_size);
+            aggregate.associated_features.insert(observation.source_id);
+        }
+        aggregate
+    }
+
+    #[test]
+    fn test_target_aggregate() {
+        let data = coverage_data(17);
+        // The target with the most observations
+        let mut counts: FxHashMap<DbID, u64> = FxHashMap::default();
+        for observation in &data.significant_observations {
+            if let Some(target_id) = observation.target_id {
+                *counts.entry(target_id).or_default() += 1;
+            }
+        }
+        let target_id = *counts
+            .iter()
+            .max_by_key(|(id, count)| (**count, **id))
+            .unwrap()
+            .0;
+
+        let aggregates =
+            aggregate_by_feature(&Filter::new(), &data, None, FeatureRole::Target).unwrap();
+        let expected = manual_aggregate(&data, target_id, |_| true);
+        assert!(expected.count > 1);
+        assert_eq!(aggregates[&target_id], expected);
+
+        // Only the observations from one library
+        let library = facet_value_id(&data, "Library", "Library 1");
+        let filters = Filter::new().categorical_facets([library]);
+        let aggregates = aggregate_by_feature(&filters, &data, None, FeatureRole::Target).unwrap();
+        let expected = manual_aggregate(&data, target_id, |o| o.facet_value_ids.contains(&library));
+        assert_eq!(
+            aggregates.get(&target_id).cloned(),
+            (expected.count > 0).then_some(expected)
+        );
+        assert!(aggregates.values().all(|aggregate| aggregate.count > 0));
+    }
+
+    #[test]
+    fn test_chromosome_filter() {
+        let data = coverage_data(17);
+        let filters = Filter::new().chrom(3);
+        for kind in [FeatureRole::Source, FeatureRole::Target] {
+            let all = aggregate_by_feature(&Filter::new(), &data, None, kind).unwrap();
+            let on_chromosome = aggregate_by_feature(&filters, &data, None, kind).unwrap();
+            assert!(!on_chromosome.is_empty());
+            for (feature_id, aggregate) in &all {
+                if data.feature_buckets[feature_id].chrom == 3 {
+                    assert_eq!(on_chromosome.get(feature_id), Some(aggregate));
+                } else {
+                    assert!(!on_chromosome.contains_key(feature_id));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_top_features() {
+        let data = coverage_data(17);
+        let aggregates =
+            aggregate_by_feature(&Filter::new(), &data, None, FeatureRole::Source).unwrap();
+        let rows = top_features(&aggregates, None);
+        assert_eq!(rows.len(), aggregates.len());
+        assert!(rows
+            .windows(2)
+            .all(|w| w[0].max_log10_sig >= w[1].max_log10_sig));
+        let top = top_features(&aggregates, Some(5));
+        assert_eq!(top, rows[..5]);
+        let row = &rows[0];
+        assert_eq!(
+            row.associated_features,
+            aggregates[&row.feature_id]
+                .associated_features
+                .iter()
+                .collect::<Vec<DbID>>()
+        );
+    }
+}
@@ -0,0 +1,215 @@
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::filter_data_structures::*;
+
+// A named region of a chromosome, e.g., a gene. `start` and `end` are 1-based and
+// inclusive, like FilteredBucket.start.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NamedRegion {
+    pub name: String,
+    pub chrom: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+// Adds the regions each bucket overlaps to the bucket's annotations. Region names are added
+// to `data.annotation_names` once each, so regions with the same name (e.g., the exons of a
+// gene) share an index. Regions on chromosomes that aren't in the data are ignored, and so
+// are regions that end before they start.
+pub fn annotate_filtered_data(data: &mut FilteredData, regions: &[NamedRegion]) {
+    let mut name_indices: FxHashMap<String, u32> = data
+        .annotation_names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (name.clone(), i as u32))
+        .collect();
+
+    // (start, end, name index) of each chromosome's regions
+    let mut chrom_regions: FxHashMap<String, Vec<(u64, u64, u32)>> = data
+        .chromosomes
+        .iter()
+        .map(|c| (c.chrom.clone(), Vec::new()))
+        .collect();
+    for region in regions {
+        let chrom_regions = match chrom_regions.get_mut(&region.chrom) {
+            Some(chrom_regions) if region.start <= region.end => chrom_regions,
+            _ => continue,
+        };
+        let name_index = *name_indices.entry(region.name.clone()).or_insert_with(|| {
+            data.annotation_names.push(region.name.clone());
+            (data.annotation_names.len() - 1) as u32
+        });
+        chrom_regions.push((region.start, region.end, name_index));
+    }
+    for regions in chrom_regions.values_mut() {
+        regions.sort_unstable();
+    }
+
+    for chromosome in &mut data.chromosomes {
+        if let Some(regions) = chrom_regions.get(&chromosome.chrom) {
+            annotate_buckets(
+                &mut chromosome.source_intervals,
+                regions,
+                chromosome.bucket_size,
+            );
+            annotate_buckets(
+                &mut chromosome.target_intervals,
+                regions,
+                chromosome.bucket_size,
+            );
+        }
+    }
+}
+
+// Sweeps over the buckets and the regions (sorted by start) together. Buckets are in order
+// and all the same width, so once a region ends before a bucket starts it can't overlap any
+// of the buckets after it either.
+fn annotate_buckets(buckets: &mut [FilteredBucket], regions: &[(u64, u64, u32)], bucket_size: u32) {
+    let mut next_region = 0;
+    // (end, name index) of the regions that started before the current bucket ends
+    let mut open_regions: Vec<(u64, u32)> = Vec::new();
+    for bucket in buckets {
+        let bucket_end = bucket.start + bucket_size as u64 - 1;
+        while let Some((start, end, name_index)) = regions.get(next_region) {
+            if *start > bucket_end {
+                break;
+            }
+            open_regions.push((*end, *name_index));
+            next_region += 1;
+        }
+        open_regions.retain(|(end, _)| *end >= bucket.start);
+
+        if !open_regions.is_empty() {
+            bucket
+                .annotations
+                .extend(open_regions.iter().map(|(_, name_index)| *name_index));
+            bucket.annotations.sort_unstable();
+            bucket.annotations.dedup();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::filter_coverage_data;
+    use crate::merge::merge_filtered_data;
+    use crate::test_data::coverage_data;
+
+    fn regions(seed: u64) -> Vec<NamedRegion> {
+        let region = |name: &str, chrom: &str, start: u64, end: u64| NamedRegion {
+            name: name.to_string(),
+            chrom: chrom.to_string(),
+            start,
+            end,
+        };
+        let mut regions: Vec<NamedRegion> = (0..60)
+            .map(|i| {
+                let start = (i * 7_919 + seed * 131) % 50_000 + 1;
+                region(
+                    &format!("gene{}", i % 20),
+                    // chr5 isn't in the data
+                    &format!("chr{}", i % 5 + 1),
+                    start,
+                    start + (i * 104_729) % 5_000,
+                )
+            })
+            .collect();
+        // Regions that end on a bucket's first base or start on its last one
+        regions.push(region("edge", "chr1", 1, 1_001));
+        regions.push(region("edge", "chr2", 2_000, 2_000));
+        regions.push(region("backwards", "chr1", 10, 5));
+        regions
+    }
+
+    // The names of the regions each bucket overlaps, found by checking every region
+    fn naive_annotations(data: &FilteredData, regions: &[NamedRegion]) -> Vec<Vec<String>> {
+        let mut annotations = Vec::new();
+        for chromosome in &data.chromosomes {
+            for bucket in chromosome
+                .source_intervals
+                .iter()
+                .chain(&chromosome.target_intervals)
+            {
+                let end = bucket.start + chromosome.bucket_size as u64 - 1;
+                let mut names: Vec<String> = regions
+                    .iter()
+                    .filter(|r| r.chrom == chromosome.chrom && r.start <= r.end)
+                    .filter(|r| r.start <= end && r.end >= bucket.start)
+                    .map(|r| r.name.clone())
+                    .collect();
+                names.sort_unstable();
+                names.dedup();
+                annotations.push(names);
+            }
+        }
+        annotations
+    }
+
+    fn annotation_names(data: &FilteredData) -> Vec<Vec<String>> {
+        let mut annotations = Vec::new();
+        for chromosome in &data.chromosomes {
+            for bucket in chromosome
+                .source_intervals
+                .iter()
+                .chain(&chromosome.target_intervals)
+            {
+                let mut names: Vec<String> = bucket
+                    .annotations
+                    .iter()
+                    .map(|i| data.annotation_names[*i as usize].clone())
+                    .collect();
+                names.sort_unstable();
+                annotations.push(names);
+            }
+        }
+        annotations
+    }
+
+    #[test]
+    fn test_annotations_match_naive_overlaps() {
+        let mut data = filter_coverage_data(&Filter::new(), &coverage_data(11), None).unwrap();
+        let regions = regions(0);
+        annotate_filtered_data(&mut data, &regions);
+
+        let annotations = annotation_names(&data);
+        assert_eq!(annotations, naive_annotations(&data, &regions));
+        assert!(annotations.iter().any(|names| names.len() > 1));
+        assert!(annotations
+            .iter()
+            .any(|names| names.contains(&"edge".to_string())));
+
+        // Each name is listed once, and names of regions that were ignored aren't listed
+        let mut names = data.annotation_names.clone();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), data.annotation_names.len());
+        assert!(!data.annotation_names.contains(&"backwards".to_string()));
+        for chromosome in &data.chromosomes {
+            for bucket in &chromosome.source_intervals {
+                assert!(bucket.annotations.windows(2).all(|w| w[0] < w[1]));
+            }
+        }
+    }
+
+    #[test]
+    fn test_merged_annotations() {
+        let mut data1 = filter_coverage_data(&Filter::new(), &coverage_data(11), None).unwrap();
+        let mut data2 = filter_coverage_data(&Filter::new(), &coverage_data(12), None).unwrap();
+        // The same regions in the opposite order, so the names are listed in a different order
+        let regions = regions(1);
+        let reversed: Vec<NamedRegion> = regions.iter().rev().cloned().collect();
+        annotate_filtered_data(&mut data1, &regions);
+        annotate_filtered_data(&mut data2, &reversed);
+        assert_ne!(data1.annotation_names, data2.annotation_names);
+
+        let chromosome_list: Vec<String> =
+            data1.chromosomes.iter().map(|c| c.chrom.clone()).collect();
+        let merged = merge_filtered_data(vec![data1, data2], chromosome_list).unwrap();
+        assert_eq!(
+            annotation_names(&merged),
+            naive_annotations(&merged, &regions)
+        );
+    }
+}
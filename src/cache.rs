@@ -0,0 +1,358 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use rustc_hash::FxHashMap;
+
+use crate::error::FilterError;
+use crate::feature_sets::feature_data_to_bytes;
+use crate::filter::filter_coverage_data;
+use crate::filter_data_structures::*;
+use cov_viz_ds::{CoverageData, DbID, ExperimentFeatureData};
+
+#[derive(Clone, Copy, Debug)]
+pub enum CacheCapacity {
+    // The maximum number of cached results
+    Entries(usize),
+    // The (approximate) maximum number of bytes used by cached results
+    Bytes(usize),
+}
+
+// Everything that determines the result of a filter call, in a canonical form.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct CacheKey {
+    dataset: String,
+    chrom: Option<u8>,
+    // Sorted, so the order the facet values were added to the filter doesn't matter
+    categorical_facets: Vec<DbID>,
+    // The bit patterns of the effect size and significance intervals
+    numeric_intervals: Option<(u32, u32, u64, u64)>,
+    sig_ceiling: Option<u64>,
+    sig_threshold: Option<u64>,
+    dedup_reos: bool,
+    window_step: Option<u32>,
+    min_pair_support: Option<u32>,
+    pair_support_excludes_unpaired: bool,
+    // The feature cap and the bit pattern of the sample rate
+    preview: Option<(Option<u64>, Option<u64>)>,
+    output_sides: OutputSides,
+    omit_empty_chromosomes: bool,
+    feature_match_mode: FeatureMatchMode,
+    max_associated_buckets_per_bucket: Option<usize>,
+    max_buckets_per_chromosome: Option<usize>,
+    // The serialized feature sets. A hash of them would be smaller, but two different feature
+    // sets with the same hash would share a cache entry.
+    included_features: Option<Vec<u8>>,
+}
+
+impl CacheKey {
+    fn new(
+        dataset: &str,
+        chrom: Option<u8>,
+        filters: &Filter,
+        included_features: Option<&ExperimentFeatureData>,
+    ) -> Self {
+        let mut categorical_facets: Vec<DbID> =
+            filters.categorical_facets.iter().cloned().collect();
+        categorical_facets.sort_unstable();
+
+        CacheKey {
+            dataset: dataset.to_string(),
+            chrom,
+            categorical_facets,
+            numeric_intervals: filters.numeric_intervals.map(|intervals| {
+                (
+                    intervals.effect.0.to_bits(),
+                    intervals.effect.1.to_bits(),
+                    intervals.sig.0.to_bits(),
+                    intervals.sig.1.to_bits(),
+                )
+            }),
+            sig_ceiling: filters.sig_ceiling.map(f64::to_bits),
+            sig_threshold: filters.sig_threshold.map(f64::to_bits),
+            dedup_reos: filters.dedup_reos,
+            window_step: filters.window_step,
+            min_pair_support: filters.min_pair_support,
+            pair_support_excludes_unpaired: filters.pair_support_excludes_unpaired,
+            preview: filters
+                .preview
+                .map(|preview| (preview.feature_cap, preview.sample_rate.map(f64::to_bits))),
+            output_sides: filters.output_sides,
+            omit_empty_chromosomes: filters.omit_empty_chromosomes,
+            feature_match_mode: filters.feature_match_mode,
+            max_associated_buckets_per_bucket: filters.max_associated_buckets_per_bucket,
+            max_buckets_per_chromosome: filters.max_buckets_per_chromosome,
+            included_features: included_features.map(feature_data_to_bytes),
+        }
+    }
+}
+
+struct CacheEntry {
+    data: Arc<FilteredData>,
+    size: usize,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: FxHashMap<CacheKey, CacheEntry>,
+    // Cache keys ordered by when they were last used, oldest first
+    recency: BTreeMap<u64, CacheKey>,
+    clock: u64,
+    total_size: usize,
+}
+
+impl CacheState {
+    fn touch(&mut self, key: &CacheKey) -> Option<Arc<FilteredData>> {
+        self.clock += 1;
+        let clock = self.clock;
+        let entry = self.entries.get_mut(key)?;
+        let key = self.recency.remove(&entry.last_used).unwrap();
+        entry.last_used = clock;
+        self.recency.insert(clock, key);
+        Some(Arc::clone(&entry.data))
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some((_, key)) = self.recency.pop_first() {
+            if let Some(entry) = self.entries.remove(&key) {
+                self.total_size -= entry.size;
+            }
+        }
+    }
+}
+
+// A least-recently-used cache of filter results. Results are shared as Arcs, so a cache hit
+// doesn't copy any data. The cache can be shared between threads.
+pub struct FilterCache {
+    capacity: CacheCapacity,
+    state: Mutex<CacheState>,
+}
+
+impl FilterCache {
+    pub fn new(capacity: CacheCapacity) -> Self {
+        FilterCache {
+            capacity,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        *self.state.lock().unwrap() = CacheState::default();
+    }
+
+    fn get(&self, key: &CacheKey) -> Option<Arc<FilteredData>> {
+        self.state.lock().unwrap().touch(key)
+    }
+
+    fn insert(&self, key: CacheKey, data: Arc<FilteredData>) -> Arc<FilteredData> {
+        let mut state = self.state.lock().unwrap();
+
+        // Another thread may have cached the same result while this one was filtering
+        if let Some(cached) = state.touch(&key) {
+            return cached;
+        }
+
+        // The serialized feature sets in the key can be as big as the result itself
+        let size = data.mem_usage() + key.included_features.as_ref().map_or(0, Vec::len);
+        match self.capacity {
+            CacheCapacity::Entries(max_entries) => {
+                if max_entries == 0 {
+                    return data;
+                }
+                while state.entries.len() >= max_entries {
+                    state.evict_oldest();
+                }
+            }
+            CacheCapacity::Bytes(max_bytes) => {
+                if size > max_bytes {
+                    return data;
+                }
+                while state.total_size + size > max_bytes {
+                    state.evict_oldest();
+                }
+            }
+        }
+
+        state.clock += 1;
+        let clock = state.clock;
+        state.recency.insert(clock, key.clone());
+        state.total_size += size;
+        state.entries.insert(
+            key,
+            CacheEntry {
+                data: Arc::clone(&data),
+                size,
+                last_used: clock,
+            },
+        );
+
+        data
+    }
+}
+
+// filter_coverage_data, but results are looked up in (and added to) `cache`.
+// `dataset` must uniquely identify `data` among all the data sets that share the cache.
+//
+// The cache isn't locked while filtering, so concurrent misses for the same filter
+// will each do the work; only the first result is kept. Errors aren't cached.
+pub fn filter_coverage_data_cached(
+    cache: &FilterCache,
+    dataset: &str,
+    filters: &Filter,
+    data: &CoverageData,
+    included_features: Option<&ExperimentFeatureData>,
+) -> Result<Arc<FilteredData>, FilterError> {
+    // Filters that refer to the chromosome by name and by index share a cache entry
+    let chrom = filters.chromosome_index(&data.chromosomes)?;
+    let key = CacheKey::new(dataset, chrom, filters, included_features);
+    if let Some(cached) = cache.get(&key) {
+        return Ok(cached);
+    }
+
+    let result = Arc::new(filter_coverage_data(filters, data, included_features)?);
+    Ok(cache.insert(key, result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::{coverage_data, facet_value_id};
+    use roaring::RoaringTreemap;
+
+    fn library_filter(data: &CoverageData, library: usize) -> Filter {
+        let mut filters = Filter::new();
+        filters.categorical_facets = [facet_value_id(
+            data,
+            "Library",
+            &format!("Library {}", library),
+        )]
+        .into_iter()
+        .collect();
+        filters
+    }
+
+    #[test]
+    fn test_hit_and_miss() {
+        let data = coverage_data(1);
+        let cache = FilterCache::new(CacheCapacity::Entries(10));
+        let filters = library_filter(&data, 0);
+
+        let first = filter_coverage_data_cached(&cache, "data", &filters, &data, None).unwrap();
+        let second = filter_coverage_data_cached(&cache, "data", &filters, &data, None).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(cache.len(), 1);
+
+        // The same facets, added in a different order
+        let mut facets_first = Filter::new();
+        let mut facets_reversed = Filter::new();
+        let facets = [
+            facet_value_id(&data, "Direction", "Enriched Only"),
+            facet_value_id(&data, "Library", "Library 1"),
+        ];
+        facets_first.categorical_facets.extend(facets);
+        facets_reversed
+            .categorical_facets
+            .extend(facets.iter().rev());
+        let first =
+            filter_coverage_data_cached(&cache, "data", &facets_first, &data, None).unwrap();
+        let second =
+            filter_coverage_data_cached(&cache, "data", &facets_reversed, &data, None).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(cache.len(), 2);
+
+        // A different filter, data set, or set of included features misses
+        let other_filter =
+            filter_coverage_data_cached(&cache, "data", &library_filter(&data, 1), &data, None)
+                .unwrap();
+        assert!(!Arc::ptr_eq(&first, &other_filter));
+        let other_dataset =
+            filter_coverage_data_cached(&cache, "other", &facets_first, &data, None).unwrap();
+        assert!(!Arc::ptr_eq(&first, &other_dataset));
+        let features = |sources: &[u64]| ExperimentFeatureData {
+            sources: sources.iter().copied().collect::<RoaringTreemap>(),
+            targets: RoaringTreemap::new(),
+        };
+        let (features1, features2) = (features(&[1, 2, 3]), features(&[1, 2, 4]));
+        let with_features1 =
+            filter_coverage_data_cached(&cache, "data", &facets_first, &data, Some(&features1))
+                .unwrap();
+        let with_features2 =
+            filter_coverage_data_cached(&cache, "data", &facets_first, &data, Some(&features2))
+                .unwrap();
+        assert!(!Arc::ptr_eq(&with_features1, &with_features2));
+        assert_eq!(cache.len(), 6);
+
+        // Errors aren't cached
+        let mut missing_chrom = Filter::new();
+        missing_chrom.chrom_name = Some("chrZ".to_string());
+        assert!(filter_coverage_data_cached(&cache, "data", &missing_chrom, &data, None).is_err());
+        assert_eq!(cache.len(), 6);
+    }
+
+    #[test]
+    fn test_least_recently_used_entry_is_evicted() {
+        let data = coverage_data(2);
+        let cache = FilterCache::new(CacheCapacity::Entries(2));
+        let cached = |library| {
+            filter_coverage_data_cached(
+                &cache,
+                "data",
+                &library_filter(&data, library),
+                &data,
+                None,
+            )
+            .unwrap()
+        };
+
+        let library0 = cached(0);
+        let library1 = cached(1);
+        // Library 0 is now more recently used than library 1
+        assert!(Arc::ptr_eq(&library0, &cached(0)));
+        cached(2);
+        assert_eq!(cache.len(), 2);
+        assert!(Arc::ptr_eq(&library0, &cached(0)));
+        assert!(!Arc::ptr_eq(&library1, &cached(1)));
+    }
+
+    #[test]
+    fn test_byte_capacity() {
+        let data = coverage_data(3);
+        let library_results: Vec<_> = (0..3)
+            .map(|library| {
+                filter_coverage_data(&library_filter(&data, library), &data, None).unwrap()
+            })
+            .collect();
+        let largest = library_results.iter().map(|r| r.mem_usage()).max().unwrap();
+        let smallest = library_results.iter().map(|r| r.mem_usage()).min().unwrap();
+
+        // Room for one result, but not two
+        let cache = FilterCache::new(CacheCapacity::Bytes(largest + smallest - 1));
+        for library in 0..3 {
+            filter_coverage_data_cached(
+                &cache,
+                "data",
+                &library_filter(&data, library),
+                &data,
+                None,
+            )
+            .unwrap();
+            assert_eq!(cache.len(), 1);
+        }
+
+        // Results that don't fit at all aren't cached
+        let cache = FilterCache::new(CacheCapacity::Bytes(smallest - 1));
+        let filters = library_filter(&data, 0);
+        let first = filter_coverage_data_cached(&cache, "data", &filters, &data, None).unwrap();
+        let second = filter_coverage_data_cached(&cache, "data", &filters, &data, None).unwrap();
+        assert!(cache.is_empty());
+        assert!(!Arc::ptr_eq(&first, &second));
+    }
+}
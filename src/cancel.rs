@@ -0,0 +1,200 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use rayon::prelude::*;
+
+use crate::error::FilterError;
+use crate::filter::{
+    bucket_matching_observations, build_filtered_data, canonical_observations_in,
+    empty_chromosomes, DataFacets, ObservationFilter,
+};
+use crate::filter_data_structures::*;
+use cov_viz_ds::{CoverageData, ExperimentFeatureData};
+
+// Lets another thread stop a filter call that's no longer needed. Clones share the same
+// state, so cancelling any clone cancels them all.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+// filter_coverage_data, but it returns FilterError::Cancelled soon after `cancel` is
+// cancelled. The token is checked between phases and for every observation, including in the
+// REO deduplication and pair support passes, so once it's cancelled the rest of the
+// observations are skipped without being checked against the filter.
+pub fn filter_coverage_data_cancellable(
+    filters: &Filter,
+    data: &CoverageData,
+    included_features: Option<&ExperimentFeatureData>,
+    cancel: &CancelToken,
+) -> Result<FilteredData, FilterError> {
+    filter_coverage_data_until(filters, data, included_features, || cancel.is_cancelled())
+}
+
+// filter_coverage_data_cancellable, but cancelled once `cancelled` returns true. It's called
+// the same number of times for the same arguments, so tests can cancel at an exact point.
+fn filter_coverage_data_until<S>(
+    filters: &Filter,
+    data: &CoverageData,
+    included_features: Option<&ExperimentFeatureData>,
+    cancelled: S,
+) -> Result<FilteredData, FilterError>
+where
+    S: Fn() -> bool + Sync,
+{
+    let check = || {
+        if cancelled() {
+            Err(FilterError::Cancelled)
+        } else {
+            Ok(())
+        }
+    };
+
+    check()?;
+    let chrom = filters.chromosome_index(&data.chromosomes)?;
+    let window_step = filters.checked_window_step(data.bucket_size)?;
+    let mut predicate =
+        ObservationFilter::from_facets(filters, &DataFacets::new(data), included_features);
+    check()?;
+
+    predicate.prepare_observations_until(
+        filters,
+        data,
+        chrom,
+        || {
+            Arc::new(canonical_observations_in(
+                data.significant_observations
+                    .par_iter()
+                    .chain(data.nonsignificant_observations.par_iter())
+                    .filter(|_| !cancelled()),
+            ))
+        },
+        &cancelled,
+    );
+    check()?;
+
+    let bucket_results = bucket_matching_observations(
+        predicate.observations(data),
+        &predicate,
+        |observation| !cancelled() && predicate.matches_non_numeric(observation),
+        &data.feature_buckets,
+    );
+    check()?;
+
+    let filtered_data = build_filtered_data(
+        data,
+        &predicate,
+        bucket_results,
+        empty_chromosomes(chrom, data),
+        window_step,
+    );
+    check()?;
+
+    Ok(filtered_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+    use crate::filter::filter_coverage_data;
+    use crate::synthetic::SyntheticCoverageDataBuilder;
+    use crate::test_data::{assert_same_results, coverage_data};
+
+    #[test]
+    fn test_not_cancelled() {
+        let data = coverage_data(7);
+        let filters = Filter::new().dedup_reos(true).min_pair_support(2);
+        assert_same_results(
+            &filter_coverage_data_cancellable(&filters, &data, None, &CancelToken::new()).unwrap(),
+            &filter_coverage_data(&filters, &data, None).unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_cancelled_before_call() {
+        let data = coverage_data(7);
+        let cancel = CancelToken::new();
+        cancel.clone().cancel();
+        assert!(cancel.is_cancelled());
+        for filters in [
+            Filter::new(),
+            Filter::new().dedup_reos(true).min_pair_support(2),
+        ] {
+            assert_eq!(
+                filter_coverage_data_cancellable(&filters, &data, None, &cancel).unwrap_err(),
+                FilterError::Cancelled
+            );
+        }
+    }
+
+    #[test]
+    fn test_cancelled_from_another_thread() {
+        let data = coverage_data(7);
+        let cancel = CancelToken::new();
+        let canceller = cancel.clone();
+        std::thread::spawn(move || canceller.cancel())
+            .join()
+            .unwrap();
+        assert_eq!(
+            filter_coverage_data_cancellable(&Filter::new(), &data, None, &cancel).unwrap_err(),
+            FilterError::Cancelled
+        );
+    }
+
+    #[test]
+    fn test_cancelled_during_passes() {
+        let data = SyntheticCoverageDataBuilder::new()
+            .seed(7)
+            .chromosomes(4)
+            .buckets_per_chromosome(50)
+            .features(2_000)
+            .observations(40_000)
+            .build();
+        let filters = Filter::new().dedup_reos(true).min_pair_support(2);
+
+        // Cancelled after the first `limit` checks
+        let checks = AtomicUsize::new(0);
+        let filter = |limit: usize| {
+            checks.store(0, Ordering::Relaxed);
+            filter_coverage_data_until(&filters, &data, None, || {
+                checks.fetch_add(1, Ordering::Relaxed) >= limit
+            })
+        };
+
+        assert_same_results(
+            &filter(usize::MAX).unwrap(),
+            &filter_coverage_data(&filters, &data, None).unwrap(),
+        );
+        let total_checks = checks.load(Ordering::Relaxed);
+        // The dedup pass alone checks every observation
+        assert!(
+            total_checks
+                > data.significant_observations.len() + data.nonsignificant_observations.len()
+        );
+
+        // Cancelling at points spread through all the passes, and right before the last check
+        for limit in (0..total_checks)
+            .step_by(total_checks / 8)
+            .chain([total_checks - 1])
+        {
+            assert_eq!(filter(limit).unwrap_err(), FilterError::Cancelled);
+        }
+        assert!(filter(total_checks).is_ok());
+    }
+}
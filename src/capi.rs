@@ -0,0 +1,252 @@
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::ptr;
+use std::sync::Arc;
+
+use crate::filter::filter_coverage_data;
+use crate::filter_data_structures::*;
+use crate::memory::coverage_data_mem_usage;
+use crate::merge::merge_filtered_data;
+use cov_viz_ds::CoverageData;
+
+// C bindings for loading coverage data, filtering it, and merging the results. include/exp_viz.h is generated from
+// this file's doc comments (see build.rs). To build the shared library, run
+// `cargo rustc --release --features capi --crate-type cdylib`.
+//
+// Every function catches panics, so they never unwind into C. Errors are reported by returning
+// a null pointer or non-zero status and, when `error` isn't null, setting `*error` to a message
+// that must be freed with exp_viz_free_string.
+//
+// A CoverageHandle is immutable once loaded, so it can be used to filter from any number of
+// threads at once. It must not be freed while it's in use. exp_viz_clone_handle makes another
+// handle to the same data, e.g., one per thread, so each can be freed on its own; the data is
+// freed along with the last handle to it.
+
+/// Loaded coverage data. Safe to filter from multiple threads at once. Handles from
+/// exp_viz_clone_handle share the same data, which is freed along with the last handle to it.
+pub struct CoverageHandle {
+    data: Arc<CoverageData>,
+}
+
+// Filtering only ever reads the data, so handles can be shared with and sent to other threads
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<CoverageHandle>();
+};
+
+unsafe fn set_error(error: *mut *mut c_char, message: &str) {
+    if error.is_null() {
+        return;
+    }
+    // Messages can't contain NULs, but if one does, the message is cut off there
+    let message = message.split('\0').next().unwrap_or_default();
+    *error = CString::new(message).unwrap_or_default().into_raw();
+}
+
+unsafe fn c_str<'a>(s: *const c_char, name: &str) -> Result<&'a str, String> {
+    if s.is_null() {
+        return Err(format!("{} is null", name));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|e| format!("{} isn't valid UTF-8: {}", name, e))
+}
+
+fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => format!("panic: {}", message),
+        None => match panic.downcast_ref::<String>() {
+            Some(message) => format!("panic: {}", message),
+            None => "panic".to_string(),
+        },
+    }
+}
+
+fn load(path: &str) -> Result<CoverageData, String> {
+    CoverageData::deserialize(&PathBuf::from(path)).map_err(|e| format!("{}: {}", path, e))
+}
+
+/// Loads the coverage data at `path`. Returns null on failure and, if `error` isn't null, sets
+/// `*error` to a message that must be freed with exp_viz_free_string.
+#[no_mangle]
+pub unsafe extern "C" fn exp_viz_load(
+    path: *const c_char,
+    error: *mut *mut c_char,
+) -> *mut CoverageHandle {
+    let result = catch_unwind(|| load(c_str(path, "path")?));
+
+    match result {
+        Ok(Ok(data)) => Box::into_raw(Box::new(CoverageHandle {
+            data: Arc::new(data),
+        })),
+        Ok(Err(message)) => {
+            set_error(error, &message);
+            ptr::null_mut()
+        }
+        Err(panic) => {
+            set_error(error, &panic_message(panic));
+            ptr::null_mut()
+        }
+    }
+}
+
+// Hands serialized results (or the error) to the caller the way exp_viz_filter and
+// exp_viz_merge document. Returns the status code.
+unsafe fn set_output(
+    result: std::thread::Result<Result<Vec<u8>, String>>,
+    out_buf: *mut *mut u8,
+    out_len: *mut usize,
+    error: *mut *mut c_char,
+) -> i32 {
+    match result {
+        Ok(Ok(bytes)) => {
+            let bytes = bytes.into_boxed_slice();
+            *out_len = bytes.len();
+            *out_buf = Box::into_raw(bytes) as *mut u8;
+            0
+        }
+        Ok(Err(message)) => {
+            set_error(error, &message);
+            1
+        }
+        Err(panic) => {
+            set_error(error, &panic_message(panic));
+            1
+        }
+    }
+}
+
+fn filter(data: &CoverageData, filter_json: &str) -> Result<Vec<u8>, String> {
+    let filters: Filter =
+        serde_json::from_str(filter_json).map_err(|e| format!("Invalid filter: {}", e))?;
+    let filtered_data = filter_coverage_data(&filters, data, None).map_err(|e| e.to_string())?;
+    bincode::serialize(&filtered_data).map_err(|e| e.to_string())
+}
+
+/// Filters the coverage data using the JSON encoded filter, e.g.
+/// {"chrom": 3, "categorical_facets": [1, 2], "numeric_intervals": {"effect": [-1, 1], "sig": [2, 10]}}
+/// Every field is optional. Chromosomes can also be selected by name, e.g. {"chrom_name": "chr4"}.
+///
+/// On success, returns 0 and sets `*out_buf` and `*out_len` to the bincode serialized
+/// FilteredData, which must be freed with exp_viz_free_bytes. On failure returns non-zero and,
+/// if `error` isn't null, sets `*error` (free it with exp_viz_free_string).
+#[no_mangle]
+pub unsafe extern "C" fn exp_viz_filter(
+    handle: *const CoverageHandle,
+    filter_json: *const c_char,
+    out_buf: *mut *mut u8,
+    out_len: *mut usize,
+    error: *mut *mut c_char,
+) -> i32 {
+    if handle.is_null() || out_buf.is_null() || out_len.is_null() {
+        set_error(error, "handle, out_buf, and out_len must not be null");
+        return 1;
+    }
+
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        filter(&(*handle).data, c_str(filter_json, "filter_json")?)
+    }));
+    set_output(result, out_buf, out_len, error)
+}
+
+fn merge(results: &[&[u8]], chromosomes_json: &str) -> Result<Vec<u8>, String> {
+    let chromosome_list: Vec<String> = serde_json::from_str(chromosomes_json)
+        .map_err(|e| format!("Invalid chromosome list: {}", e))?;
+    let result_data = results
+        .iter()
+        .enumerate()
+        .map(|(i, bytes)| {
+            bincode::deserialize(bytes).map_err(|e| format!("Invalid result {}: {}", i, e))
+        })
+        .collect::<Result<Vec<FilteredData>, String>>()?;
+    let merged = merge_filtered_data(result_data, chromosome_list).map_err(|e| e.to_string())?;
+    bincode::serialize(&merged).map_err(|e| e.to_string())
+}
+
+/// Merges `count` filter results, e.g., from exp_viz_filter. `bufs[i]` is a bincode
+/// serialized FilteredData that's `lens[i]` bytes long. `chromosomes_json` is the JSON encoded
+/// list of the chromosomes to include, in order, e.g. ["chr1", "chr2", "chrX"].
+///
+/// On success, returns 0 and sets `*out_buf` and `*out_len` to the bincode serialized merged
+/// FilteredData, which must be freed with exp_viz_free_bytes. On failure returns non-zero and,
+/// if `error` isn't null, sets `*error` (free it with exp_viz_free_string).
+#[no_mangle]
+pub unsafe extern "C" fn exp_viz_merge(
+    bufs: *const *const u8,
+    lens: *const usize,
+    count: usize,
+    chromosomes_json: *const c_char,
+    out_buf: *mut *mut u8,
+    out_len: *mut usize,
+    error: *mut *mut c_char,
+) -> i32 {
+    if (count > 0 && (bufs.is_null() || lens.is_null())) || out_buf.is_null() || out_len.is_null() {
+        set_error(error, "bufs, lens, out_buf, and out_len must not be null");
+        return 1;
+    }
+
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        let mut results = Vec::with_capacity(count);
+        for i in 0..count {
+            let (buf, len) = (*bufs.add(i), *lens.add(i));
+            if buf.is_null() {
+                return Err(format!("bufs[{}] is null", i));
+            }
+            results.push(std::slice::from_raw_parts(buf, len));
+        }
+        merge(&results, c_str(chromosomes_json, "chromosomes_json")?)
+    }));
+    set_output(result, out_buf, out_len, error)
+}
+
+/// An estimate of how much memory (in bytes) the loaded coverage data uses. Returns 0 if
+/// `handle` is null.
+#[no_mangle]
+pub unsafe extern "C" fn exp_viz_mem_usage(handle: *const CoverageHandle) -> usize {
+    if handle.is_null() {
+        return 0;
+    }
+    catch_unwind(AssertUnwindSafe(|| {
+        coverage_data_mem_usage(&(*handle).data)
+    }))
+    .unwrap_or(0)
+}
+
+/// A new handle to the same data as `handle`, without copying the data. Free it with
+/// exp_viz_free. Returns null if `handle` is null.
+#[no_mangle]
+pub unsafe extern "C" fn exp_viz_clone_handle(
+    handle: *const CoverageHandle,
+) -> *mut CoverageHandle {
+    if handle.is_null() {
+        return ptr::null_mut();
+    }
+    Box::into_raw(Box::new(CoverageHandle {
+        data: Arc::clone(&(*handle).data),
+    }))
+}
+
+/// Frees a handle from exp_viz_load or exp_viz_clone_handle
+#[no_mangle]
+pub unsafe extern "C" fn exp_viz_free(handle: *mut CoverageHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Frees the results of exp_viz_filter and exp_viz_merge
+#[no_mangle]
+pub unsafe extern "C" fn exp_viz_free_bytes(buf: *mut u8, len: usize) {
+    if !buf.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(buf, len)));
+    }
+}
+
+/// Frees an error message
+#[no_mangle]
+pub unsafe extern "C" fn exp_viz_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
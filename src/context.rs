@@ -0,0 +1,156 @@
+use std::sync::{Arc, OnceLock};
+
+use crate::error::FilterError;
+use crate::filter::{
+    bucket_matching_observations, build_filtered_data_with, canonical_observations,
+    empty_chromosomes, BucketScratch, CanonicalObservations, DataFacets, ObservationFilter,
+};
+use crate::filter_data_structures::*;
+use crate::summary::{coverage_summary, CoverageSummary};
+use cov_viz_ds::{CoverageData, ExperimentFeatureData};
+
+// Holds everything about a CoverageData that filtering needs but that doesn't depend on
+// the filter, so repeated filter calls against the same data (e.g., while a user drags a
+// slider) don't have to recompute it, along with buffers that are reused from call to call.
+// A context can be reused for any number of filters, one call at a time; use one context
+// per thread to filter from several threads at once.
+pub struct FilterContext<'a> {
+    data: &'a CoverageData,
+    facets: DataFacets,
+    // Every chromosome in the data set, without any intervals
+    chromosomes: Vec<FilteredChromosome>,
+    // Computed the first time it's asked for
+    summary: OnceLock<CoverageSummary>,
+    // Computed the first time a filter deduplicates REOs
+    canonical_observations: Option<Arc<CanonicalObservations>>,
+    scratch: BucketScratch,
+}
+
+impl<'a> FilterContext<'a> {
+    pub fn new(data: &'a CoverageData) -> Self {
+        FilterContext {
+            data,
+            facets: DataFacets::new(data),
+            chromosomes: empty_chromosomes(None, data),
+            summary: OnceLock::new(),
+            canonical_observations: None,
+            scratch: BucketScratch::default(),
+        }
+    }
+
+    // The context's data's coverage_summary
+    pub fn summary(&self) -> &CoverageSummary {
+        self.summary.get_or_init(|| coverage_summary(self.data))
+    }
+}
+
+// Produces the same results as filter_coverage_data on the context's data
+pub fn filter_coverage_data_with_context(
+    context: &mut FilterContext,
+    filters: &Filter,
+    included_features: Option<&ExperimentFeatureData>,
+) -> Result<FilteredData, FilterError> {
+    let data = context.data;
+    let chrom = filters.chromosome_index(&data.chromosomes)?;
+    let window_step = filters.checked_window_step(data.bucket_size)?;
+    let mut predicate = ObservationFilter::from_facets(filters, &context.facets, included_features);
+    let canonical = &mut context.canonical_observations;
+    predicate.prepare_observations(filters, data, chrom, || {
+        canonical
+            .get_or_insert_with(|| Arc::new(canonical_observations(data)))
+            .clone()
+    });
+
+    let bucket_results = bucket_matching_observations(
+        predicate.observations(data),
+        &predicate,
+        |observation| predicate.matches_non_numeric(observation),
+        &data.feature_buckets,
+    );
+
+    let chromosomes = context
+        .chromosomes
+        .iter()
+        .filter(|c| chrom.is_none() || chrom == Some(c.index))
+        .cloned()
+        .collect();
+
+    Ok(build_filtered_data_with(
+        data,
+        &predicate,
+        bucket_results,
+        chromosomes,
+        window_step,
+        data.bucket_size,
+        &data.feature_buckets,
+        &mut context.scratch,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::filter_coverage_data;
+    use crate::test_data::{assert_same_results, coverage_data, facet_value_id};
+
+    #[test]
+    fn test_context_matches_stateless_filtering() {
+        let mut data = coverage_data(1);
+        // Observe some REOs a second time, with a different source
+        let duplicates: Vec<_> = data
+            .significant_observations
+            .iter()
+            .step_by(10)
+            .map(|observation| {
+                let mut duplicate = observation.clone();
+                duplicate.source_id = (duplicate.source_id + 1) % 500;
+                duplicate
+            })
+            .collect();
+        data.significant_observations.extend(duplicates);
+
+        let mut categorical = Filter::new();
+        categorical.categorical_facets = [facet_value_id(&data, "Library", "Library 2")]
+            .into_iter()
+            .collect();
+        let mut numeric = Filter::new();
+        numeric.numeric_intervals = Some(FilterIntervals {
+            effect: (-1.0, 3.0),
+            sig: (2.0, 12.0),
+            ..FilterIntervals::new()
+        });
+        let mut dedup = categorical.clone();
+        dedup.dedup_reos = true;
+        let mut pair_support = numeric.clone();
+        pair_support.dedup_reos = true;
+        pair_support.min_pair_support = Some(2);
+        let mut chrom = numeric.clone();
+        chrom.chrom = Some(2);
+        let mut window = dedup.clone();
+        window.window_step = Some(data.bucket_size / 4);
+        let mut targets = chrom.clone();
+        targets.output_sides = OutputSides::TargetsOnly;
+
+        let mut context = FilterContext::new(&data);
+        // Twice through the filters, so each one runs on a context the others have used
+        for filters in [
+            Filter::new(),
+            categorical,
+            numeric,
+            dedup,
+            pair_support,
+            chrom,
+            window,
+            targets,
+        ]
+        .iter()
+        .cycle()
+        .take(16)
+        {
+            assert_same_results(
+                &filter_coverage_data_with_context(&mut context, filters, None).unwrap(),
+                &filter_coverage_data(filters, &data, None).unwrap(),
+            );
+        }
+    }
+}
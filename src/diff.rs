@@ -0,0 +1,242 @@
+use std::cmp::Ordering;
+
+use serde::Serialize;
+
+use crate::error::MergeError;
+use crate::filter_data_structures::*;
+
+// A bucket in either or both of two filtered data sets. Values from a data set that
+// doesn't have the bucket are 0.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DiffBucket {
+    pub start: u64,
+    pub count_a: usize,
+    pub count_b: usize,
+    // count_b - count_a
+    pub delta_count: i64,
+    pub max_sig_a: f64,
+    pub max_sig_b: f64,
+    pub max_abs_effect_a: f32,
+    pub max_abs_effect_b: f32,
+}
+
+impl DiffBucket {
+    fn new(a: Option<&FilteredBucket>, b: Option<&FilteredBucket>) -> Self {
+        let start = a.or(b).map(|bucket| bucket.start).unwrap_or_default();
+        let count_a = a.map_or(0, |bucket| bucket.count);
+        let count_b = b.map_or(0, |bucket| bucket.count);
+        DiffBucket {
+            start,
+            count_a,
+            count_b,
+            delta_count: count_b as i64 - count_a as i64,
+            max_sig_a: a.map_or(0.0, |bucket| bucket.max_log10_sig),
+            max_sig_b: b.map_or(0.0, |bucket| bucket.max_log10_sig),
+            max_abs_effect_a: a.map_or(0.0, |bucket| bucket.max_abs_effect),
+            max_abs_effect_b: b.map_or(0.0, |bucket| bucket.max_abs_effect),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DiffChromosome {
+    pub chrom: String,
+    pub index: u8,
+    pub source_intervals: Vec<DiffBucket>,
+    pub target_intervals: Vec<DiffBucket>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FilteredDiff {
+    pub bucket_size: u32,
+    pub chromosomes: Vec<DiffChromosome>,
+    // Features in b but not a
+    pub sources_gained: u64,
+    pub targets_gained: u64,
+    // Features in a but not b
+    pub sources_lost: u64,
+    pub targets_lost: u64,
+}
+
+// Pairs up the buckets of two (sorted) interval lists by start
+fn diff_intervals(a: &[FilteredBucket], b: &[FilteredBucket]) -> Vec<DiffBucket> {
+    let mut diff = Vec::with_capacity(a.len().max(b.len()));
+    let mut a = a.iter().peekable();
+    let mut b = b.iter().peekable();
+    loop {
+        let bucket = match (a.peek(), b.peek()) {
+            (Some(bucket_a), Some(bucket_b)) => match bucket_a.start.cmp(&bucket_b.start) {
+                Ordering::Less => DiffBucket::new(a.next(), None),
+                Ordering::Greater => DiffBucket::new(None, b.next()),
+                Ordering::Equal => DiffBucket::new(a.next(), b.next()),
+            },
+            (Some(_), None) => DiffBucket::new(a.next(), None),
+            (None, Some(_)) => DiffBucket::new(None, b.next()),
+            (None, None) => break,
+        };
+        diff.push(bucket);
+    }
+    diff
+}
+
+// Compares two filtered data sets bucket by bucket. The data sets must have the same bucket
+// size, window step, and chromosomes.
+pub fn diff_filtered_data(a: &FilteredData, b: &FilteredData) -> Result<FilteredDiff, MergeError> {
+    if a.bucket_size != b.bucket_size {
+        return Err(MergeError::BucketSizeMismatch(a.bucket_size, b.bucket_size));
+    }
+    if a.window_step != b.window_step {
+        return Err(MergeError::WindowStepMismatch(a.window_step, b.window_step));
+    }
+    if a.chromosomes.len() != b.chromosomes.len() {
+        return Err(MergeError::ChromosomeMismatch);
+    }
+
+    let chromosomes = a
+        .chromosomes
+        .iter()
+        .map(|chromosome_a| {
+            let chromosome_b = b
+                .chromosomes
+                .iter()
+                .find(|c| c.chrom == chromosome_a.chrom && c.index == chromosome_a.index)
+                .ok_or(MergeError::ChromosomeMismatch)?;
+            Ok(DiffChromosome {
+                chrom: chromosome_a.chrom.clone(),
+                index: chromosome_a.index,
+                source_intervals: diff_intervals(
+                    &chromosome_a.source_intervals,
+                    &chromosome_b.source_intervals,
+                ),
+                target_intervals: diff_intervals(
+                    &chromosome_a.target_intervals,
+                    &chromosome_b.target_intervals,
+                ),
+            })
+        })
+        .collect::<Result<Vec<_>, MergeError>>()?;
+
+    Ok(FilteredDiff {
+        bucket_size: a.bucket_size,
+        chromosomes,
+        sources_gained: b.sources.difference_len(&a.sources),
+        targets_gained: b.targets.difference_len(&a.targets),
+        sources_lost: a.sources.difference_len(&b.sources),
+        targets_lost: a.targets.difference_len(&b.targets),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::filter_coverage_data;
+    use crate::rebucket::rebucket_filtered_data;
+    use crate::test_data::coverage_data;
+
+    fn filtered() -> FilteredData {
+        filter_coverage_data(&Filter::new(), &coverage_data(14), None).unwrap()
+    }
+
+    fn buckets(diff: &FilteredDiff) -> impl Iterator<Item = &DiffBucket> {
+        diff.chromosomes
+            .iter()
+            .flat_map(|c| c.source_intervals.iter().chain(c.target_intervals.iter()))
+    }
+
+    #[test]
+    fn test_identical_data() {
+        let data = filtered();
+        let diff = diff_filtered_data(&data, &data).unwrap();
+        assert_eq!(diff.chromosomes.len(), data.chromosomes.len());
+        assert!(buckets(&diff).count() > 0);
+        for bucket in buckets(&diff) {
+            assert_eq!(bucket.delta_count, 0);
+            assert_eq!(bucket.count_a, bucket.count_b);
+            assert_eq!(bucket.max_sig_a, bucket.max_sig_b);
+            assert_eq!(bucket.max_abs_effect_a, bucket.max_abs_effect_b);
+        }
+        assert_eq!(
+            (
+                diff.sources_gained,
+                diff.targets_gained,
+                diff.sources_lost,
+                diff.targets_lost
+            ),
+            (0, 0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn test_disjoint_data() {
+        // a has every other bucket, and b has the rest
+        let data = filtered();
+        let mut a = data.clone();
+        let mut b = data.clone();
+        for (keep_even, data) in [(true, &mut a), (false, &mut b)] {
+            for chromosome in &mut data.chromosomes {
+                for intervals in [
+                    &mut chromosome.source_intervals,
+                    &mut chromosome.target_intervals,
+                ] {
+                    let mut i = 0;
+                    intervals.retain(|_| {
+                        i += 1;
+                        (i % 2 == 1) == keep_even
+                    });
+                }
+            }
+        }
+        a.sources = [1, 2].into_iter().collect();
+        b.sources = [3].into_iter().collect();
+        a.targets = [4].into_iter().collect();
+        b.targets = [4, 5, 6].into_iter().collect();
+
+        let diff = diff_filtered_data(&a, &b).unwrap();
+        let mut starts = Vec::new();
+        for bucket in buckets(&diff) {
+            if bucket.count_a > 0 {
+                assert_eq!(bucket.count_b, 0);
+                assert_eq!(bucket.max_sig_b, 0.0);
+                assert_eq!(bucket.delta_count, -(bucket.count_a as i64));
+            } else {
+                assert!(bucket.count_b > 0);
+                assert_eq!(bucket.max_sig_a, 0.0);
+                assert_eq!(bucket.delta_count, bucket.count_b as i64);
+            }
+            starts.push(bucket.start);
+        }
+        let expected_starts: Vec<u64> = data
+            .chromosomes
+            .iter()
+            .flat_map(|c| c.source_intervals.iter().chain(c.target_intervals.iter()))
+            .map(|bucket| bucket.start)
+            .collect();
+        assert_eq!(starts, expected_starts);
+        assert_eq!(
+            (
+                diff.sources_gained,
+                diff.targets_gained,
+                diff.sources_lost,
+                diff.targets_lost
+            ),
+            (1, 2, 2, 0)
+        );
+    }
+
+    #[test]
+    fn test_mismatched_data() {
+        let data = filtered();
+        let coarse = rebucket_filtered_data(&data, 10_000).unwrap();
+        assert_eq!(
+            diff_filtered_data(&data, &coarse).unwrap_err(),
+            MergeError::BucketSizeMismatch(1_000, 10_000)
+        );
+
+        let mut fewer_chromosomes = data.clone();
+        fewer_chromosomes.chromosomes.pop();
+        assert_eq!(
+            diff_filtered_data(&data, &fewer_chromosomes).unwrap_err(),
+            MergeError::ChromosomeMismatch
+        );
+    }
+}
@@ -0,0 +1,209 @@
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::error::FilterError;
+use crate::filter::ObservationFilter;
+use crate::filter_data_structures::*;
+use cov_viz_ds::{BucketLoc, CoverageData, DbID, ExperimentFeatureData, ObservationData};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BucketKind {
+    Source,
+    Target,
+}
+
+// The details of a single observation, for showing what's behind a bucket
+#[derive(Clone, Debug, Serialize)]
+pub struct ObservationDetail {
+    pub reo_id: DbID,
+    pub source_id: DbID,
+    pub target_id: Option<DbID>,
+    pub effect_size: f32,
+    // Clamped the same way as the bucket's max_log10_sig
+    pub neg_log_significance: f64,
+    pub facet_value_ids: Vec<DbID>,
+}
+
+impl ObservationDetail {
+    pub(crate) fn new(observation: &ObservationData, neg_log_significance: f64) -> Self {
+        ObservationDetail {
+            reo_id: observation.reo_id,
+            source_id: observation.source_id,
+            target_id: observation.target_id,
+            effect_size: observation.effect_size,
+            neg_log_significance,
+            facet_value_ids: observation.facet_value_ids.clone(),
+        }
+    }
+}
+
+// The observations that filter_coverage_data puts in `bucket`, as either a source or target
+// bucket. They're sorted by significance, most significant first (ties are broken by REO id),
+// and if there's a limit only the first `limit` are returned.
+pub fn query_bucket_observations(
+    filters: &Filter,
+    data: &CoverageData,
+    included_features: Option<&ExperimentFeatureData>,
+    bucket: BucketLoc,
+    kind: BucketKind,
+    limit: Option<usize>,
+) -> Result<Vec<ObservationDetail>, FilterError> {
+    // Buckets on other chromosomes aren't part of the filter results
+    if let Some(chrom) = filters.chromosome_index(&data.chromosomes)? {
+        if chrom != bucket.chrom {
+            return Ok(Vec::new());
+        }
+    }
+
+    let predicate = ObservationFilter::new(filters, data, included_features);
+    let in_bucket = |observation: &ObservationData| {
+        let feature_id = match kind {
+            BucketKind::Source => Some(observation.source_id),
+            BucketKind::Target => observation.target_id,
+        };
+        feature_id.and_then(|id| data.feature_buckets.get(&id)) == Some(&bucket)
+    };
+
+    let mut observations: Vec<ObservationDetail> = predicate
+        .observations(data)
+        .filter(|observation| in_bucket(observation) && predicate.matches(observation))
+        .map(|observation| ObservationDetail::new(observation, predicate.significance(observation)))
+        .collect();
+
+    observations.par_sort_unstable_by(|a, b| {
+        b.neg_log_significance
+            .total_cmp(&a.neg_log_significance)
+            .then(a.reo_id.cmp(&b.reo_id))
+            .then(a.source_id.cmp(&b.source_id))
+            .then(a.target_id.cmp(&b.target_id))
+    });
+    if let Some(limit) = limit {
+        observations.truncate(limit);
+    }
+
+    Ok(observations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feature_sets::feature_data_from_ids;
+    use crate::filter::filter_coverage_data;
+    use crate::test_data::{coverage_data, facet_value_id};
+    use rustc_hash::FxHashSet;
+
+    // Checks that the observations behind each of the chromosome's buckets add up to the bucket
+    fn assert_buckets_match(
+        filters: &Filter,
+        data: &CoverageData,
+        included_features: Option<&ExperimentFeatureData>,
+    ) {
+        let result = filter_coverage_data(filters, data, included_features).unwrap();
+        let chromosome = &result.chromosomes[1];
+        for (kind, intervals) in [
+            (BucketKind::Source, &chromosome.source_intervals),
+            (BucketKind::Target, &chromosome.target_intervals),
+        ] {
+            assert!(!intervals.is_empty());
+            for bucket in intervals {
+                let loc = BucketLoc {
+                    chrom: chromosome.index,
+                    idx: ((bucket.start - 1) / data.bucket_size as u64) as u32,
+                };
+                let observations =
+                    query_bucket_observations(filters, data, included_features, loc, kind, None)
+                        .unwrap();
+                let features: FxHashSet<DbID> = observations
+                    .iter()
+                    .map(|o| match kind {
+                        BucketKind::Source => o.source_id,
+                        BucketKind::Target => o.target_id.unwrap(),
+                    })
+                    .collect();
+                assert_eq!(features.len(), bucket.count);
+                assert_eq!(observations[0].neg_log_significance, bucket.max_log10_sig);
+                let max_abs_effect = observations
+                    .iter()
+                    .map(|o| o.effect_size)
+                    .reduce(crate::filter_data_structures::max_abs_effect)
+                    .unwrap();
+                assert_eq!(max_abs_effect, bucket.max_abs_effect);
+                let sig_sum = observations.iter().fold(0.0, |sum, o| {
+                    add_sig_sums(
+                        sum,
+                        sig_sum_from_fixed(sig_sum_to_fixed(o.neg_log_significance)),
+                    )
+                });
+                assert_eq!(sig_sum, bucket.sig_sum);
+            }
+        }
+    }
+
+    #[test]
+    fn test_observations_match_buckets() {
+        let data = coverage_data(13);
+        let mut filters = Filter::new();
+        assert_buckets_match(&filters, &data, None);
+
+        filters.categorical_facets = [facet_value_id(&data, "Library", "Library 0")]
+            .into_iter()
+            .collect();
+        filters.numeric_intervals = Some(FilterIntervals {
+            effect: (-3.0, 4.0),
+            ..FilterIntervals::new()
+        });
+        assert_buckets_match(&filters, &data, None);
+
+        let included_features = feature_data_from_ids(0..250, 100..400);
+        assert_buckets_match(&filters, &data, Some(&included_features));
+    }
+
+    #[test]
+    fn test_sorting_and_limit() {
+        let data = coverage_data(13);
+        let result = filter_coverage_data(&Filter::new(), &data, None).unwrap();
+        let bucket = result.chromosomes[0]
+            .source_intervals
+            .iter()
+            .max_by_key(|bucket| bucket.count)
+            .unwrap();
+        let loc = BucketLoc {
+            chrom: 0,
+            idx: ((bucket.start - 1) / data.bucket_size as u64) as u32,
+        };
+
+        let all =
+            query_bucket_observations(&Filter::new(), &data, None, loc, BucketKind::Source, None)
+                .unwrap();
+        assert!(all.len() > 3);
+        assert!(all
+            .windows(2)
+            .all(|w| w[0].neg_log_significance >= w[1].neg_log_significance));
+        let limited = query_bucket_observations(
+            &Filter::new(),
+            &data,
+            None,
+            loc,
+            BucketKind::Source,
+            Some(3),
+        )
+        .unwrap();
+        let reo_ids = |observations: &[ObservationDetail]| -> Vec<DbID> {
+            observations.iter().map(|o| o.reo_id).collect()
+        };
+        assert_eq!(reo_ids(&limited), reo_ids(&all[..3]));
+
+        // The bucket isn't on the filter's chromosome
+        let other_chromosome = Filter::new().chrom(1);
+        assert!(query_bucket_observations(
+            &other_chromosome,
+            &data,
+            None,
+            loc,
+            BucketKind::Source,
+            None
+        )
+        .unwrap()
+        .is_empty());
+    }
+}
@@ -0,0 +1,162 @@
+use std::fmt;
+
+use cov_viz_ds::DbID;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FilterError {
+    // Filter.chrom_name doesn't match any chromosome in the data
+    UnknownChromosome(String),
+    // Filter.chrom and Filter.chrom_name are both set, but refer to different chromosomes
+    ConflictingChromosome { chrom: u8, chrom_name: String },
+    // Filter.window_step is 0 or doesn't evenly divide the data's bucket size
+    InvalidWindowStep { bucket_size: u32, window_step: u32 },
+    // The filter's CancelToken was cancelled before it finished
+    Cancelled,
+    // A CategoricalSelection was used with a different data set than it was made from
+    SelectionMismatch,
+    // A requested resolution isn't a (non-zero) multiple of the data's bucket size
+    InvalidResolution { bucket_size: u32, resolution: u32 },
+    // The facet to group by isn't one of the data's categorical facets
+    UnknownFacet(String),
+    // An observation being grouped doesn't have exactly one value of the group-by facet
+    UngroupableObservation { reo_id: DbID, values: usize },
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FilterError::UnknownChromosome(name) => write!(f, "Unknown chromosome: {}", name),
+            FilterError::ConflictingChromosome { chrom, chrom_name } => write!(
+                f,
+                "Chromosome index {} and chromosome name {} refer to different chromosomes",
+                chrom, chrom_name
+            ),
+            FilterError::InvalidWindowStep {
+                bucket_size,
+                window_step,
+            } => write!(
+                f,
+                "Window step {} doesn't evenly divide bucket size {}",
+                window_step, bucket_size
+            ),
+            FilterError::Cancelled => write!(f, "Filter cancelled"),
+            FilterError::SelectionMismatch => {
+                write!(f, "Selection was made from a different data set")
+            }
+            FilterError::InvalidResolution {
+                bucket_size,
+                resolution,
+            } => write!(
+                f,
+                "Resolution {} isn't a multiple of bucket size {}",
+                resolution, bucket_size
+            ),
+            FilterError::UnknownFacet(facet) => write!(f, "Unknown categorical facet: {}", facet),
+            FilterError::UngroupableObservation { reo_id, values } => write!(
+                f,
+                "An observation of REO {} has {} values of the group-by facet rather than 1",
+                reo_id, values
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RebucketError {
+    // The new bucket size isn't a (non-zero) multiple of the current one
+    NotAMultiple {
+        bucket_size: u32,
+        target_bucket_size: u32,
+    },
+    // Overlapping windows can't be combined into larger buckets
+    WindowedData,
+}
+
+impl fmt::Display for RebucketError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RebucketError::NotAMultiple {
+                bucket_size,
+                target_bucket_size,
+            } => write!(
+                f,
+                "Bucket size {} isn't a multiple of bucket size {}",
+                target_bucket_size, bucket_size
+            ),
+            RebucketError::WindowedData => write!(f, "Can't rebucket sliding window data"),
+        }
+    }
+}
+
+impl std::error::Error for RebucketError {}
+
+// Filtered data sets that can't be combined
+#[derive(Clone, Debug, PartialEq)]
+pub enum MergeError {
+    BucketSizeMismatch(u32, u32),
+    // The data sets don't have the same chromosomes
+    ChromosomeMismatch,
+    // The data sets use different sliding window steps (or only some use windows)
+    WindowStepMismatch(Option<u32>, Option<u32>),
+    // There was nothing to merge
+    NoData,
+    // One of the data sets couldn't be filtered
+    Filter(FilterError),
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MergeError::BucketSizeMismatch(bucket_size1, bucket_size2) => write!(
+                f,
+                "Bucket sizes don't match: {} and {}",
+                bucket_size1, bucket_size2
+            ),
+            MergeError::ChromosomeMismatch => write!(f, "Chromosomes don't match"),
+            MergeError::WindowStepMismatch(window_step1, window_step2) => write!(
+                f,
+                "Window steps don't match: {:?} and {:?}",
+                window_step1, window_step2
+            ),
+            MergeError::NoData => write!(f, "No data to merge"),
+            MergeError::Filter(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for MergeError {}
+
+impl From<FilterError> for MergeError {
+    fn from(error: FilterError) -> Self {
+        MergeError::Filter(error)
+    }
+}
+
+// Bytes that aren't feature sets written by feature_data_to_bytes
+#[derive(Clone, Debug, PartialEq)]
+pub enum FeatureDataError {
+    // The bytes end before the end of the feature sets
+    Truncated,
+    // One of the feature sets isn't a valid roaring bitmap
+    InvalidBitmap(String),
+    // There are bytes after the feature sets
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for FeatureDataError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FeatureDataError::Truncated => write!(f, "Feature data is truncated"),
+            FeatureDataError::InvalidBitmap(error) => {
+                write!(f, "Invalid feature set bitmap: {}", error)
+            }
+            FeatureDataError::TrailingBytes(count) => {
+                write!(f, "{} unexpected bytes after feature data", count)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FeatureDataError {}
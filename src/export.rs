@@ -0,0 +1,242 @@
+use std::cmp::Ordering;
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::drilldown::ObservationDetail;
+use crate::error::FilterError;
+use crate::filter::ObservationFilter;
+use crate::filter_data_structures::*;
+use cov_viz_ds::{CoverageData, ExperimentFeatureData, ObservationData};
+
+// The order of exported observations. Ties are broken by REO id, source id, target id, and
+// then the rest of the observation's values, so observations are always in the same order
+// and pages never overlap or skip observations.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ObservationSort {
+    // Most significant first
+    #[default]
+    SignificanceDesc,
+    // Largest absolute effect size first
+    AbsEffectDesc,
+    ReoId,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PageSpec {
+    // The number of observations to skip
+    pub offset: usize,
+    // The most observations a page can have
+    pub limit: usize,
+    #[serde(default)]
+    pub sort: ObservationSort,
+}
+
+impl PageSpec {
+    pub fn new(offset: usize, limit: usize) -> Self {
+        PageSpec {
+            offset,
+            limit,
+            sort: ObservationSort::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ObservationPage {
+    pub rows: Vec<ObservationDetail>,
+    pub offset: usize,
+    // The number of observations across all pages
+    pub total: u64,
+}
+
+fn compare(
+    sort: ObservationSort,
+    (observation1, sig1): &(&ObservationData, f64),
+    (observation2, sig2): &(&ObservationData, f64),
+) -> Ordering {
+    let order = match sort {
+        ObservationSort::SignificanceDesc => sig2.total_cmp(sig1),
+        ObservationSort::AbsEffectDesc => observation2
+            .effect_size
+            .abs()
+            .total_cmp(&observation1.effect_size.abs()),
+        ObservationSort::ReoId => Ordering::Equal,
+    };
+    order
+        .then(observation1.reo_id.cmp(&observation2.reo_id))
+        .then(observation1.source_id.cmp(&observation2.source_id))
+        .then(observation1.target_id.cmp(&observation2.target_id))
+        .then(sig2.total_cmp(sig1))
+        .then(
+            observation1
+                .effect_size
+                .total_cmp(&observation2.effect_size),
+        )
+        .then(
+            observation1
+                .facet_value_ids
+                .cmp(&observation2.facet_value_ids),
+        )
+}
+
+// One page of the observations that pass the filter, i.e., the ones filter_coverage_data
+// buckets. When the filter is restricted to a chromosome, only
+// observations with a source or target on it are included. Pages past the end are empty.
+pub fn export_filtered_observations(
+    filters: &Filter,
+    data: &CoverageData,
+    included_features: Option<&ExperimentFeatureData>,
+    page: PageSpec,
+) -> Result<ObservationPage, FilterError> {
+    let chrom = filters.chromosome_index(&data.chromosomes)?;
+    let predicate = ObservationFilter::new(filters, data, included_features);
+    let on_chromosome = |observation: &ObservationData| match chrom {
+        Some(chrom) => std::iter::once(observation.source_id)
+            .chain(observation.target_id)
+            .any(|id| {
+                data.feature_buckets
+                    .get(&id)
+                    .is_some_and(|bucket| bucket.chrom == chrom)
+            }),
+        None => true,
+    };
+
+    let mut observations: Vec<(&ObservationData, f64)> = predicate
+        .observations(data)
+        .filter(|observation| predicate.matches(observation) && on_chromosome(observation))
+        .map(|observation| (observation, predicate.significance(observation)))
+        .collect();
+    let total = observations.len() as u64;
+
+    // Only the observations up to the end of the page have to be sorted
+    let end = page.offset.saturating_add(page.limit);
+    if end < observations.len() {
+        observations.select_nth_unstable_by(end, |a, b| compare(page.sort, a, b));
+        observations.truncate(end);
+    }
+    observations.par_sort_unstable_by(|a, b| compare(page.sort, a, b));
+
+    let rows = observations
+        .iter()
+        .skip(page.offset)
+        .map(|(observation, sig)| ObservationDetail::new(observation, *sig))
+        .collect();
+
+    Ok(ObservationPage {
+        rows,
+        offset: page.offset,
+        total,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::{add_nan_values, coverage_data, facet_value_id};
+    use cov_viz_ds::DbID;
+
+    fn key(row: &ObservationDetail) -> (DbID, DbID, Option<DbID>, u32, u64) {
+        (
+            row.reo_id,
+            row.source_id,
+            row.target_id,
+            row.effect_size.to_bits(),
+            row.neg_log_significance.to_bits(),
+        )
+    }
+
+    #[test]
+    fn test_pages_cover_every_observation_once() {
+        let mut data = coverage_data(13);
+        add_nan_values(&mut data, 41, 43);
+        let library = facet_value_id(&data, "Library", "Library 2");
+        let filters = Filter::new().categorical_facets([library]);
+
+        // Counted by hand
+        let expected = data
+            .significant_observations
+            .iter()
+            .chain(&data.nonsignificant_observations)
+            .filter(|o| o.facet_value_ids.contains(&library))
+            .filter(|o| !o.effect_size.is_nan() && !o.neg_log_significance.is_nan())
+            .count();
+
+        for sort in [
+            ObservationSort::SignificanceDesc,
+            ObservationSort::AbsEffectDesc,
+            ObservationSort::ReoId,
+        ] {
+            let page = |offset, limit| {
+                export_filtered_observations(
+                    &filters,
+                    &data,
+                    None,
+                    PageSpec {
+                        offset,
+                        limit,
+                        sort,
+                    },
+                )
+                .unwrap()
+            };
+            let everything = page(0, usize::MAX);
+            assert_eq!(everything.total as usize, expected);
+            assert_eq!(everything.rows.len(), expected);
+
+            let mut paged = Vec::new();
+            for offset in (0..expected).step_by(97) {
+                let page = page(offset, 97);
+                assert_eq!(page.offset, offset);
+                assert_eq!(page.total as usize, expected);
+                paged.extend(page.rows);
+            }
+            let keys: Vec<_> = paged.iter().map(key).collect();
+            assert_eq!(keys, everything.rows.iter().map(key).collect::<Vec<_>>());
+
+            // The rows are in order, and there are no duplicates
+            for rows in everything.rows.windows(2) {
+                let ordered = match sort {
+                    ObservationSort::SignificanceDesc => {
+                        rows[0].neg_log_significance >= rows[1].neg_log_significance
+                    }
+                    ObservationSort::AbsEffectDesc => {
+                        rows[0].effect_size.abs() >= rows[1].effect_size.abs()
+                    }
+                    ObservationSort::ReoId => rows[0].reo_id <= rows[1].reo_id,
+                };
+                assert!(ordered);
+                assert_ne!(key(&rows[0]), key(&rows[1]));
+            }
+
+            assert!(page(expected, 10).rows.is_empty());
+            assert!(page(expected + 10, 10).rows.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_chromosome_export() {
+        let data = coverage_data(13);
+        let chrom = 2;
+        let page = export_filtered_observations(
+            &Filter::new().chrom(chrom),
+            &data,
+            None,
+            PageSpec::new(0, usize::MAX),
+        )
+        .unwrap();
+        let on_chromosome = |id: &DbID| data.feature_buckets[id].chrom == chrom;
+        let expected = data
+            .significant_observations
+            .iter()
+            .chain(&data.nonsignificant_observations)
+            .filter(|o| on_chromosome(&o.source_id) || o.target_id.iter().any(on_chromosome))
+            .count();
+        assert_eq!(page.total as usize, expected);
+        assert!(page.total > 0);
+        assert!(page.rows.iter().all(|row| {
+            on_chromosome(&row.source_id) || row.target_id.iter().any(on_chromosome)
+        }));
+    }
+}
@@ -0,0 +1,119 @@
+use serde::Serialize;
+
+use cov_viz_ds::{CoverageData, DbID, Facet};
+
+// What the filter UI needs to know about a facet
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FacetInfo {
+    pub id: DbID,
+    pub name: String,
+    pub description: String,
+    pub facet_type: String,
+    pub range: Option<(f32, f32)>,
+    pub range64: Option<(f64, f64)>,
+    // Sorted by value id
+    pub values: Vec<(DbID, String)>,
+    // The facet has no values and no range, so there's nothing to filter on
+    pub empty: bool,
+}
+
+impl FacetInfo {
+    fn new(facet: &Facet) -> Self {
+        let mut values: Vec<(DbID, String)> = facet
+            .values
+            .iter()
+            .flatten()
+            .map(|(id, name)| (*id, name.clone()))
+            .collect();
+        values.sort_unstable();
+        let range = facet.range.map(|range| (range.0, range.1));
+        let range64 = facet.range64.map(|range| (range.0, range.1));
+
+        FacetInfo {
+            id: facet.id,
+            name: facet.name.clone(),
+            description: facet.description.clone(),
+            facet_type: facet.facet_type.clone(),
+            empty: values.is_empty() && range.is_none() && range64.is_none(),
+            range,
+            range64,
+            values,
+        }
+    }
+}
+
+// The data set's facets, in the order they're stored in
+pub fn facet_info(data: &CoverageData) -> Vec<FacetInfo> {
+    data.facets.iter().map(FacetInfo::new).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::coverage_data;
+
+    #[test]
+    fn test_facet_info() {
+        // One categorical and two numeric facets
+        let mut data = coverage_data(1);
+        data.facets.retain(|facet| facet.name != "Direction");
+        let info = facet_info(&data);
+        let shape: Vec<(&str, &str, usize, bool, bool, bool)> = info
+            .iter()
+            .map(|facet| {
+                (
+                    facet.name.as_str(),
+                    facet.facet_type.as_str(),
+                    facet.values.len(),
+                    facet.range.is_some(),
+                    facet.range64.is_some(),
+                    facet.empty,
+                )
+            })
+            .collect();
+        assert_eq!(
+            shape,
+            vec![
+                ("Library", "FacetType.CATEGORICAL", 3, false, false, false),
+                ("Effect Size", "FacetType.NUMERIC", 0, true, false, false),
+                ("Significance", "FacetType.NUMERIC", 0, false, true, false),
+            ]
+        );
+        let names: Vec<&str> = info[0].values.iter().map(|(_, n)| n.as_str()).collect();
+        assert_eq!(names, vec!["Library 0", "Library 1", "Library 2"]);
+        assert!(info[0].values.windows(2).all(|w| w[0].0 < w[1].0));
+        let range = data.facets[1].range.unwrap();
+        assert_eq!(info[1].range, Some((range.0, range.1)));
+        let range64 = data.facets[2].range64.unwrap();
+        assert_eq!(info[2].range64, Some((range64.0, range64.1)));
+
+        let json = serde_json::to_value(&info[1]).unwrap();
+        let mut keys: Vec<&str> = json
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(|k| k.as_str())
+            .collect();
+        keys.sort_unstable();
+        assert_eq!(
+            keys,
+            vec![
+                "description",
+                "empty",
+                "facet_type",
+                "id",
+                "name",
+                "range",
+                "range64",
+                "values"
+            ]
+        );
+
+        // Facets without values or ranges are flagged
+        data.facets[0].values = Some(Default::default());
+        data.facets[1].range = None;
+        data.facets[2].values = None;
+        let empty: Vec<bool> = facet_info(&data).iter().map(|f| f.empty).collect();
+        assert_eq!(empty, vec![true, true, false]);
+    }
+}
@@ -0,0 +1,130 @@
+use std::convert::TryInto;
+
+use roaring::RoaringTreemap;
+
+use crate::error::FeatureDataError;
+use cov_viz_ds::ExperimentFeatureData;
+
+// Helpers for building and storing the feature sets passed to the filter functions as
+// `included_features`
+
+pub fn feature_data_from_ids<S, T>(sources: S, targets: T) -> ExperimentFeatureData
+where
+    S: IntoIterator<Item = u64>,
+    T: IntoIterator<Item = u64>,
+{
+    ExperimentFeatureData {
+        sources: sources.into_iter().collect(),
+        targets: targets.into_iter().collect(),
+    }
+}
+
+// The number of (source, target) features
+pub fn feature_data_len(feature_data: &ExperimentFeatureData) -> (u64, u64) {
+    (feature_data.sources.len(), feature_data.targets.len())
+}
+
+// The feature sets as bytes: the sources and then the targets, each in roaring's serialization
+// format, preceded by its length in bytes as a little-endian u64
+pub fn feature_data_to_bytes(feature_data: &ExperimentFeatureData) -> Vec<u8> {
+    let sources_size = feature_data.sources.serialized_size();
+    let targets_size = feature_data.targets.serialized_size();
+    let mut bytes = Vec::with_capacity(2 * 8 + sources_size + targets_size);
+    for (treemap, size) in [
+        (&feature_data.sources, sources_size),
+        (&feature_data.targets, targets_size),
+    ] {
+        bytes.extend_from_slice(&(size as u64).to_le_bytes());
+        // Writing to a Vec can't fail
+        treemap.serialize_into(&mut bytes).unwrap();
+    }
+    bytes
+}
+
+// Reads one length-prefixed treemap from the start of `bytes`, and returns it along with
+// the rest of the bytes
+fn read_treemap(bytes: &[u8]) -> Result<(RoaringTreemap, &[u8]), FeatureDataError> {
+    if bytes.len() < 8 {
+        return Err(FeatureDataError::Truncated);
+    }
+    let (size, rest) = bytes.split_at(8);
+    let size = u64::from_le_bytes(size.try_into().unwrap());
+    if size > rest.len() as u64 {
+        return Err(FeatureDataError::Truncated);
+    }
+    let (mut treemap_bytes, rest) = rest.split_at(size as usize);
+    let treemap = RoaringTreemap::deserialize_from(&mut treemap_bytes)
+        .map_err(|e| FeatureDataError::InvalidBitmap(e.to_string()))?;
+    if !treemap_bytes.is_empty() {
+        return Err(FeatureDataError::InvalidBitmap(format!(
+            "{} bytes left over",
+            treemap_bytes.len()
+        )));
+    }
+    Ok((treemap, rest))
+}
+
+// The inverse of feature_data_to_bytes
+pub fn feature_data_from_bytes(bytes: &[u8]) -> Result<ExperimentFeatureData, FeatureDataError> {
+    let (sources, rest) = read_treemap(bytes)?;
+    let (targets, rest) = read_treemap(rest)?;
+    if !rest.is_empty() {
+        return Err(FeatureDataError::TrailingBytes(rest.len()));
+    }
+    Ok(ExperimentFeatureData { sources, targets })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let feature_data = feature_data_from_ids([1, 5, 5, 1 << 40], 100..200);
+        assert_eq!(feature_data_len(&feature_data), (3, 100));
+
+        let from_bytes = feature_data_from_bytes(&feature_data_to_bytes(&feature_data)).unwrap();
+        assert_eq!(from_bytes.sources, feature_data.sources);
+        assert_eq!(from_bytes.targets, feature_data.targets);
+    }
+
+    #[test]
+    fn test_empty_sets() {
+        let feature_data = feature_data_from_ids([], [7]);
+        assert_eq!(feature_data_len(&feature_data), (0, 1));
+        let from_bytes = feature_data_from_bytes(&feature_data_to_bytes(&feature_data)).unwrap();
+        assert!(from_bytes.sources.is_empty());
+        assert_eq!(from_bytes.targets, feature_data.targets);
+
+        let empty = feature_data_from_ids([], []);
+        assert_eq!(feature_data_len(&empty), (0, 0));
+        let from_bytes = feature_data_from_bytes(&feature_data_to_bytes(&empty)).unwrap();
+        assert_eq!(feature_data_len(&from_bytes), (0, 0));
+    }
+
+    #[test]
+    fn test_invalid_bytes() {
+        let bytes = feature_data_to_bytes(&feature_data_from_ids([1, 2, 3], [4, 5]));
+        assert_eq!(
+            feature_data_from_bytes(&bytes[..bytes.len() - 1]).err(),
+            Some(FeatureDataError::Truncated)
+        );
+        assert_eq!(
+            feature_data_from_bytes(&bytes[..4]).err(),
+            Some(FeatureDataError::Truncated)
+        );
+
+        let mut trailing = bytes.clone();
+        trailing.extend_from_slice(&[0, 0]);
+        assert_eq!(
+            feature_data_from_bytes(&trailing).err(),
+            Some(FeatureDataError::TrailingBytes(2))
+        );
+
+        // A length prefix that doesn't match the bitmap's size
+        let mut bad_length = bytes;
+        let sources_size = u64::from_le_bytes(bad_length[..8].try_into().unwrap());
+        bad_length[..8].copy_from_slice(&(sources_size + 1).to_le_bytes());
+        assert!(feature_data_from_bytes(&bad_length).is_err());
+    }
+}
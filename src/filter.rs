@@ -1,20 +1,67 @@
+use std::collections::hash_map::Entry;
+use std::sync::Arc;
+
 use rayon::prelude::*;
+use rayon::ThreadPool;
 use roaring::RoaringTreemap;
 use rustc_hash::{FxHashMap, FxHashSet};
 
+use crate::error::FilterError;
 use crate::filter_data_structures::*;
+use crate::validate::{EFFECT_SIZE_FACET, SIGNIFICANCE_FACET};
 use cov_viz_ds::{
     BucketLoc, CoverageData, DbID, ExperimentFeatureData, FacetRange, FacetRange64, ObservationData,
 };
 
 #[derive(Debug, Clone)]
-struct BucketData {
+pub(crate) struct BucketData {
     feature_ids: RoaringTreemap,
     associated_features: RoaringTreemap,
     min_effect: f32,
     max_effect: f32,
+    // The negative effect size closest to 0 and the smallest positive effect size. They're
+    // only meaningful when min_effect < 0 and max_effect > 0, respectively.
+    max_negative_effect: f32,
+    min_positive_effect: f32,
     min_sig: f64,
     max_sig: f64,
+    // In fixed point (see sig_sum_to_fixed)
+    sig_sum: i128,
+    over_threshold_count: u64,
+    // Set when features were left out because of a preview's feature cap
+    truncated: bool,
+    // Set when associated features were left out because of a preview's feature cap
+    associated_truncated: bool,
+}
+
+// Intermediate filter results
+#[derive(Default)]
+pub(crate) struct BucketResults {
+    pub reos: RoaringTreemap,
+    pub source_buckets: FxHashMap<BucketLoc, BucketData>,
+    pub target_buckets: FxHashMap<BucketLoc, BucketData>,
+    // The number of observations that passed the rest of the filter but were dropped because
+    // their effect size or significance is NaN
+    pub dropped_invalid: u64,
+}
+
+impl BucketResults {
+    // Adds an observation that passed the filter
+    pub(crate) fn add(
+        &mut self,
+        observation: &ObservationData,
+        predicate: &ObservationFilter,
+        feature_buckets: &FxHashMap<DbID, BucketLoc>,
+    ) {
+        self.reos.insert(observation.reo_id);
+        update_buckets(
+            observation,
+            predicate,
+            &mut self.source_buckets,
+            &mut self.target_buckets,
+            feature_buckets,
+        );
+    }
 }
 
 fn is_disjoint(a: &Vec<DbID>, b: &Vec<DbID>) -> bool {
@@ -29,6 +76,19 @@ fn is_disjoint(a: &Vec<DbID>, b: &Vec<DbID>) -> bool {
     true
 }
 
+// Drops all but the `feature_cap` smallest ids. Keeping the smallest ids, rather than the
+// first ones seen, means the result doesn't depend on how the work was split up.
+// Returns whether any ids were dropped.
+fn cap_features(feature_ids: &mut RoaringTreemap, feature_cap: u64) -> bool {
+    match feature_ids.select(feature_cap) {
+        Some(first_dropped) => {
+            feature_ids.remove_range(first_dropped..);
+            true
+        }
+        None => false,
+    }
+}
+
 fn add_data_to_bucket(
     id: DbID,
     associated_feature: Option<DbID>,
@@ -36,24 +96,52 @@ fn add_data_to_bucket(
     effect_size: f32,
     buckets: &mut FxHashMap<BucketLoc, BucketData>,
     bucket_locs: &FxHashMap<DbID, BucketLoc>,
+    predicate: &ObservationFilter,
 ) {
-    let feature_loc = bucket_locs.get(&id);
-    if feature_loc.is_none() {
+    let feature_loc = match bucket_locs.get(&id) {
+        Some(feature_loc) => feature_loc,
+        None => return,
+    };
+    if predicate.skipped_chromosomes.contains(&feature_loc.chrom) {
         return;
     }
 
+    let feature_cap = predicate.preview.feature_cap;
+    let sig_sum = sig_sum_to_fixed(obs_sig);
+    let over_threshold_count = match predicate.sig_threshold {
+        Some(sig_threshold) if obs_sig > sig_threshold => 1,
+        _ => 0,
+    };
     buckets
-        .entry(*feature_loc.unwrap())
+        .entry(*feature_loc)
         .and_modify(|bucket_data| {
             bucket_data.feature_ids.insert(id);
             if let Some(af) = associated_feature {
                 bucket_data.associated_features.insert(af);
             }
+            // Capping is only needed once a bucket goes over the cap, which len() (unlike
+            // select) can tell cheaply
+            if let Some(feature_cap) = feature_cap {
+                if bucket_data.feature_ids.len() > feature_cap {
+                    bucket_data.truncated |=
+                        cap_features(&mut bucket_data.feature_ids, feature_cap);
+                }
+                if bucket_data.associated_features.len() > feature_cap {
+                    bucket_data.associated_truncated |=
+                        cap_features(&mut bucket_data.associated_features, feature_cap);
+                }
+            }
             bucket_data.min_effect = effect_size.min(bucket_data.min_effect);
             bucket_data.max_effect = effect_size.max(bucket_data.max_effect);
+            bucket_data.max_negative_effect =
+                merge_max_negative(bucket_data.max_negative_effect, effect_size);
+            bucket_data.min_positive_effect =
+                merge_min_positive(bucket_data.min_positive_effect, effect_size);
 
             bucket_data.min_sig = obs_sig.min(bucket_data.min_sig);
             bucket_data.max_sig = obs_sig.max(bucket_data.max_sig);
+            bucket_data.sig_sum = bucket_data.sig_sum.saturating_add(sig_sum);
+            bucket_data.over_threshold_count += over_threshold_count;
         })
         .or_insert(BucketData {
             feature_ids: RoaringTreemap::from([id]),
@@ -64,422 +152,1283 @@ fn add_data_to_bucket(
             },
             min_effect: effect_size,
             max_effect: effect_size,
+            max_negative_effect: effect_size,
+            min_positive_effect: effect_size,
             min_sig: obs_sig,
             max_sig: obs_sig,
+            sig_sum,
+            over_threshold_count,
+            truncated: false,
+            associated_truncated: false,
         });
 }
 
 fn update_buckets(
     observation: &ObservationData,
+    predicate: &ObservationFilter,
     source_buckets: &mut FxHashMap<BucketLoc, BucketData>,
     target_buckets: &mut FxHashMap<BucketLoc, BucketData>,
     features: &FxHashMap<DbID, BucketLoc>,
 ) {
-    add_data_to_bucket(
-        observation.source_id,
-        observation.target_id,
-        observation.neg_log_significance,
-        observation.effect_size,
-        source_buckets,
-        features,
-    );
+    // -0.0 and 0.0 are equal, so which one a bucket's min or max ends up with would depend
+    // on the order the observations are seen in. Adding 0.0 turns -0.0 into 0.0.
+    let obs_sig = predicate.significance(observation) + 0.0;
+    let effect_size = observation.effect_size + 0.0;
+
+    if predicate.output_sides.includes_sources() {
+        add_data_to_bucket(
+            observation.source_id,
+            observation.target_id,
+            obs_sig,
+            effect_size,
+            source_buckets,
+            features,
+            predicate,
+        );
+    }
 
+    if !predicate.output_sides.includes_targets() {
+        return;
+    }
     if let Some(id) = observation.target_id {
         add_data_to_bucket(
             id,
             Some(observation.source_id),
-            observation.neg_log_significance,
-            observation.effect_size,
+            obs_sig,
+            effect_size,
             target_buckets,
             features,
+            predicate,
         );
     };
 }
 
 fn update_bucket_map(
     bucket_list1: &mut FxHashMap<BucketLoc, BucketData>,
-    bucket_list2: &FxHashMap<BucketLoc, BucketData>,
+    bucket_list2: FxHashMap<BucketLoc, BucketData>,
 ) {
     for (loc, data2) in bucket_list2 {
-        bucket_list1
-            .entry(*loc)
-            .and_modify(|bucket_data| {
-                bucket_data.feature_ids.extend(&data2.feature_ids);
-                bucket_data
-                    .associated_features
-                    .extend(&data2.associated_features);
-                bucket_data.min_effect = data2.min_effect.min(bucket_data.min_effect);
-                bucket_data.max_effect = data2.max_effect.max(bucket_data.max_effect);
-
-                bucket_data.min_sig = data2.min_sig.min(bucket_data.min_sig);
-                bucket_data.max_sig = data2.max_sig.max(bucket_data.max_sig);
-            })
-            .or_insert(data2.clone());
+        match bucket_list1.entry(loc) {
+            Entry::Occupied(mut entry) => merge_bucket_data(entry.get_mut(), data2),
+            Entry::Vacant(entry) => {
+                entry.insert(data2);
+            }
+        }
     }
 }
 
-fn gen_filtered_data(
-    buckets: FxHashMap<BucketLoc, BucketData>,
-    chrom: Option<u8>,
-    feature_count: &mut RoaringTreemap,
-    min_effect: &mut f32,
-    max_effect: &mut f32,
-    min_sig: &mut f64,
-    max_sig: &mut f64,
-    intervals: &mut Vec<&mut Vec<FilteredBucket>>,
+// The negative effect size closest to 0 of two values that are only meaningful when they're
+// negative
+fn merge_max_negative(effect1: f32, effect2: f32) -> f32 {
+    match (effect1 < 0.0, effect2 < 0.0) {
+        (true, true) => effect1.max(effect2),
+        (false, true) => effect2,
+        _ => effect1,
+    }
+}
+
+// The smallest positive effect size of two values that are only meaningful when they're
+// positive
+fn merge_min_positive(effect1: f32, effect2: f32) -> f32 {
+    match (effect1 > 0.0, effect2 > 0.0) {
+        (true, true) => effect1.min(effect2),
+        (false, true) => effect2,
+        _ => effect1,
+    }
+}
+
+fn merge_bucket_data(bucket_data: &mut BucketData, data2: BucketData) {
+    bucket_data.feature_ids |= data2.feature_ids;
+    bucket_data.associated_features |= data2.associated_features;
+    bucket_data.min_effect = data2.min_effect.min(bucket_data.min_effect);
+    bucket_data.max_effect = data2.max_effect.max(bucket_data.max_effect);
+    bucket_data.max_negative_effect =
+        merge_max_negative(bucket_data.max_negative_effect, data2.max_negative_effect);
+    bucket_data.min_positive_effect =
+        merge_min_positive(bucket_data.min_positive_effect, data2.min_positive_effect);
+
+    bucket_data.min_sig = data2.min_sig.min(bucket_data.min_sig);
+    bucket_data.max_sig = data2.max_sig.max(bucket_data.max_sig);
+    bucket_data.sig_sum = bucket_data.sig_sum.saturating_add(data2.sig_sum);
+    bucket_data.over_threshold_count += data2.over_threshold_count;
+    // Capped feature sets are capped again once they've been turned into FilteredBuckets
+    bucket_data.truncated |= data2.truncated;
+    bucket_data.associated_truncated |= data2.associated_truncated;
+}
+
+// Turns a chromosome's buckets into overlapping windows. Window k starts at k * window_step
+// and is bucket_size wide, so it overlaps the bucket it starts in and, unless it starts on a
+// bucket boundary, the next one. Features are only located to a bucket, so a window includes
+// everything in each bucket it overlaps.
+fn window_buckets(
+    buckets: Vec<(u32, BucketData)>,
     bucket_size: u32,
-    features: &FxHashMap<DbID, BucketLoc>,
-) {
-    let mut ordered_buckets: Vec<_> = buckets
-        .into_iter()
-        .filter(|(bucket_loc, _)| chrom.is_none() || bucket_loc.chrom == chrom.unwrap())
-        .collect();
-    ordered_buckets.sort_by(|(loc1, _), (loc2, _)| loc1.cmp(loc2));
-    for (bucket_loc, bucket_data) in ordered_buckets {
-        feature_count.extend(&bucket_data.feature_ids);
-        *min_effect = min_effect.min(bucket_data.min_effect);
-        *max_effect = max_effect.max(bucket_data.max_effect);
-        *min_sig = min_sig.min(bucket_data.min_sig);
-        *max_sig = max_sig.max(bucket_data.max_sig);
-
-        let chrom = if chrom.is_none() {
-            bucket_loc.chrom as usize
-        } else {
-            0
-        };
+    window_step: u32,
+) -> Vec<(u32, BucketData)> {
+    let ratio = (bucket_size / window_step) as u64;
+    let mut windows: FxHashMap<u32, BucketData> = FxHashMap::default();
+    for (bucket_idx, bucket_data) in buckets {
+        let bucket_idx = bucket_idx as u64;
+        let first_window = (bucket_idx * ratio).saturating_sub(ratio - 1);
+        let last_window = (bucket_idx + 1) * ratio - 1;
+        for window_idx in first_window..=last_window {
+            let window_idx = u32::try_from(window_idx).expect("window index overflowed");
+            match windows.entry(window_idx) {
+                Entry::Occupied(mut entry) => {
+                    merge_bucket_data(entry.get_mut(), bucket_data.clone())
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(bucket_data.clone());
+                }
+            }
+        }
+    }
+    windows.into_iter().collect()
+}
 
-        intervals[chrom].push(FilteredBucket {
-            start: bucket_size * bucket_loc.idx + 1,
-            count: bucket_data.feature_ids.len() as usize,
-            // buckets are stored as a list where the chromosome indexes and bucket indexes alternate.
-            // This cuts down on how much data get sent over the wire.
-            associated_buckets: bucket_data
-                .associated_features
-                .iter()
-                .fold(FxHashSet::<&BucketLoc>::default(), |mut acc, id| {
-                    if let Some(bucket) = features.get(&id) {
-                        acc.insert(bucket);
-                    }
-                    acc
-                })
-                .iter()
-                .fold(Vec::new(), |mut acc, bucket| {
-                    acc.push(bucket.chrom as u32);
-                    acc.push(bucket.idx);
-                    acc
-                }),
-            max_log10_sig: bucket_data.max_sig,
-            max_abs_effect: if bucket_data.max_effect > bucket_data.min_effect.abs() {
-                bucket_data.max_effect
-            } else {
-                bucket_data.min_effect
-            },
+// Below this many observations it's faster to build the buckets on a single thread than to
+// pay for allocating and merging a set of bucket maps per chunk.
+const SEQUENTIAL_BUCKETING_THRESHOLD: usize = 10_000;
+// Chunks smaller than this spend more time allocating and merging bucket maps than bucketing.
+// Above this, rayon splits the work adaptively so threads that finish early can steal work
+// from the others (observations with targets cost about twice as much as those without).
+const MIN_BUCKETING_CHUNK_SIZE: usize = 4_096;
+
+// The smallest chunk of `len` observations to bucket on one thread
+pub(crate) fn bucketing_chunk_size(len: usize) -> usize {
+    if len < SEQUENTIAL_BUCKETING_THRESHOLD {
+        len.max(1)
+    } else {
+        MIN_BUCKETING_CHUNK_SIZE
+    }
+}
+
+// Checks each observation against the filter and merges the ones that pass into an
+// intermediate set of data structures that will then be turned into FilteredData.
+// Filtering and bucketing happen in the same pass, so the passing observations never
+// have to be collected.
+//
+// `matches` checks the non-numeric parts of the filter; the numeric intervals and REO
+// deduplication are checked here using `predicate`. Observations with NaN values are
+// counted, but never bucketed.
+pub(crate) fn bucket_matching_observations<'a, I, F>(
+    observations: I,
+    predicate: &ObservationFilter,
+    matches: F,
+    feature_buckets: &FxHashMap<DbID, BucketLoc>,
+) -> BucketResults
+where
+    I: IndexedParallelIterator<Item = &'a ObservationData>,
+    F: Fn(&ObservationData) -> bool + Sync,
+{
+    bucket_matching_observations_with(
+        observations,
+        predicate,
+        matches,
+        feature_buckets,
+        merge_bucket_results,
+    )
+}
+
+// bucket_matching_observations, but the per-chunk results are combined with `merge`
+pub(crate) fn bucket_matching_observations_with<'a, I, F, M>(
+    observations: I,
+    predicate: &ObservationFilter,
+    matches: F,
+    feature_buckets: &FxHashMap<DbID, BucketLoc>,
+    merge: M,
+) -> BucketResults
+where
+    I: IndexedParallelIterator<Item = &'a ObservationData>,
+    F: Fn(&ObservationData) -> bool + Sync,
+    M: Fn(BucketResults, BucketResults) -> BucketResults + Sync + Send,
+{
+    let min_chunk_size = bucketing_chunk_size(observations.len());
+    observations
+        .with_min_len(min_chunk_size)
+        .filter(|observation| {
+            predicate.matches_dedup(observation)
+                && predicate.matches_sample(observation)
+                && matches(observation)
+        })
+        .fold(BucketResults::default, |mut results, observation| {
+            if has_invalid_values(observation) {
+                results.dropped_invalid += 1;
+            } else if predicate.matches_numeric(observation)
+                && predicate.matches_pair_support(observation)
+            {
+                results.add(observation, predicate, feature_buckets);
+            }
+            results
         })
+        // Merge bucket collections together pairwise
+        .reduce(BucketResults::default, merge)
+}
+
+// Merges two sets of intermediate bucket data. The smaller bucket maps are merged
+// into the larger ones so as few entries as possible have to be moved.
+pub(crate) fn merge_bucket_results(
+    mut results1: BucketResults,
+    mut results2: BucketResults,
+) -> BucketResults {
+    results1.reos |= results2.reos;
+    results1.dropped_invalid += results2.dropped_invalid;
+
+    if results1.source_buckets.len() < results2.source_buckets.len() {
+        std::mem::swap(&mut results1.source_buckets, &mut results2.source_buckets);
+    }
+    update_bucket_map(&mut results1.source_buckets, results2.source_buckets);
+
+    if results1.target_buckets.len() < results2.target_buckets.len() {
+        std::mem::swap(&mut results1.target_buckets, &mut results2.target_buckets);
     }
+    update_bucket_map(&mut results1.target_buckets, results2.target_buckets);
+
+    results1
 }
 
-pub fn filter_coverage_data(
-    filters: &Filter,
-    data: &CoverageData,
-    included_features: Option<&ExperimentFeatureData>,
-) -> FilteredData {
-    let bucket_size = data.bucket_size;
-    let feature_buckets = &data.feature_buckets;
+// Combines every `factor` consecutive buckets into one bucket, as if the buckets were
+// `factor` times as large
+pub(crate) fn coarsen_bucket_results(results: &BucketResults, factor: u32) -> BucketResults {
+    fn coarsen(
+        buckets: &FxHashMap<BucketLoc, BucketData>,
+        factor: u32,
+    ) -> FxHashMap<BucketLoc, BucketData> {
+        let mut coarse_buckets: FxHashMap<BucketLoc, BucketData> = FxHashMap::default();
+        for (loc, bucket_data) in buckets {
+            let coarse_loc = BucketLoc {
+                chrom: loc.chrom,
+                idx: loc.idx / factor,
+            };
+            match coarse_buckets.entry(coarse_loc) {
+                Entry::Occupied(mut entry) => {
+                    merge_bucket_data(entry.get_mut(), bucket_data.clone())
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(bucket_data.clone());
+                }
+            }
+        }
+        coarse_buckets
+    }
 
-    //
-    // Get Numeric Facet Info
-    //
-    let skip_cont_facet_check = filters.numeric_intervals.is_none();
+    BucketResults {
+        reos: results.reos.clone(),
+        source_buckets: coarsen(&results.source_buckets, factor),
+        target_buckets: coarsen(&results.target_buckets, factor),
+        dropped_invalid: results.dropped_invalid,
+    }
+}
 
-    let effect_size_interval = match &filters.numeric_intervals {
-        Some(c) => FacetRange(c.effect.0, c.effect.1),
-        None => data
-            .facets
-            .iter()
-            .find(|f| f.name == "Effect Size")
-            .unwrap()
-            .range
-            .unwrap(),
-    };
-    let sig_interval = match &filters.numeric_intervals {
-        Some(c) => FacetRange64(c.sig.0, c.sig.1),
-        None => data
-            .facets
-            .iter()
-            .find(|f| f.name == "Significance")
-            .unwrap()
-            .range64
-            .unwrap(),
-    };
+// Summary statistics for a set of buckets
+struct BucketStats {
+    feature_ids: RoaringTreemap,
+    min_effect: f32,
+    max_effect: f32,
+    max_negative_effect: f32,
+    min_positive_effect: f32,
+    min_sig: f64,
+    max_sig: f64,
+    // Buckets on chromosomes that aren't in the data set
+    dropped_buckets: u64,
+}
 
-    //
-    // Get Categorical Facet Info
-    //
+impl BucketStats {
+    fn new() -> Self {
+        BucketStats {
+            feature_ids: RoaringTreemap::new(),
+            min_effect: f32::INFINITY,
+            max_effect: f32::NEG_INFINITY,
+            max_negative_effect: f32::NAN,
+            min_positive_effect: f32::NAN,
+            min_sig: f64::INFINITY,
+            max_sig: f64::NEG_INFINITY,
+            dropped_buckets: 0,
+        }
+    }
 
-    // All categorical facet value database ids that are used in this data set.
-    // This may not be all possible facet values.
-    let mut all_coverage_data_cat_facets: FxHashSet<DbID> = FxHashSet::default();
-    for facet in data.facets.iter() {
-        if let Some(facet_values) = &facet.values {
-            facet_values.keys().for_each(|key| {
-                all_coverage_data_cat_facets.insert(*key);
-            });
+    fn add(&mut self, bucket_data: &BucketData) {
+        self.feature_ids |= &bucket_data.feature_ids;
+        self.min_effect = self.min_effect.min(bucket_data.min_effect);
+        self.max_effect = self.max_effect.max(bucket_data.max_effect);
+        self.max_negative_effect =
+            merge_max_negative(self.max_negative_effect, bucket_data.max_negative_effect);
+        self.min_positive_effect =
+            merge_min_positive(self.min_positive_effect, bucket_data.min_positive_effect);
+        self.min_sig = self.min_sig.min(bucket_data.min_sig);
+        self.max_sig = self.max_sig.max(bucket_data.max_sig);
+    }
+
+    fn merge(mut self, other: BucketStats) -> BucketStats {
+        self.feature_ids |= other.feature_ids;
+        self.min_effect = self.min_effect.min(other.min_effect);
+        self.max_effect = self.max_effect.max(other.max_effect);
+        self.max_negative_effect =
+            merge_max_negative(self.max_negative_effect, other.max_negative_effect);
+        self.min_positive_effect =
+            merge_min_positive(self.min_positive_effect, other.min_positive_effect);
+        self.min_sig = self.min_sig.min(other.min_sig);
+        self.max_sig = self.max_sig.max(other.max_sig);
+        self.dropped_buckets += other.dropped_buckets;
+        self
+    }
+}
+
+// Scales a count up to make up for a preview's sampling
+fn scale_count(count: u64, sample_rate: Option<f64>) -> u64 {
+    match sample_rate {
+        Some(sample_rate) if sample_rate > 0.0 && sample_rate < 1.0 => {
+            (count as f64 / sample_rate).round() as u64
         }
+        _ => count,
     }
+}
 
-    // Categorical facet value database ids for that are filtered on, not including
-    // facet values that aren't used in this data set.
-    let coverage_data_cat_facets: FxHashSet<DbID> = all_coverage_data_cat_facets
-        .intersection(&filters.categorical_facets)
-        .cloned()
-        .collect();
+// Scales a sum up to make up for a preview's sampling
+fn scale_sum(sum: f64, sample_rate: Option<f64>) -> f64 {
+    match sample_rate {
+        Some(sample_rate) if sample_rate > 0.0 && sample_rate < 1.0 => sum / sample_rate,
+        _ => sum,
+    }
+}
 
-    let skip_cat_facet_check = coverage_data_cat_facets.is_empty();
+// `spacing` is the distance between the starts of consecutive buckets (or windows)
+fn filtered_bucket(
+    bucket_idx: u32,
+    bucket_data: BucketData,
+    spacing: u32,
+    sample_rate: Option<f64>,
+    features: &FxHashMap<DbID, BucketLoc>,
+) -> FilteredBucket {
+    // Computed in 64 bits so small bucket sizes on large chromosomes can't wrap around
+    let start = (spacing as u64)
+        .checked_mul(bucket_idx as u64)
+        .and_then(|offset| offset.checked_add(1))
+        .expect("bucket start overflowed");
 
-    // All categorical facet value database ids that are used in this data set,
-    // divided up by facet
-    let mut facet_ids: Vec<FxHashSet<DbID>> = Vec::new();
-    for facet in data
-        .facets
-        .iter()
-        .filter(|f| f.facet_type == "FacetType.CATEGORICAL")
-    {
-        facet_ids.push(FxHashSet::from_iter(
-            facet.values.as_ref().unwrap().keys().cloned(),
-        ));
+    FilteredBucket {
+        start,
+        count: scale_count(bucket_data.feature_ids.len(), sample_rate) as usize,
+        // buckets are stored as a list where the chromosome indexes and bucket indexes alternate.
+        // This cuts down on how much data get sent over the wire.
+        associated_buckets: sorted_associated_buckets(
+            bucket_data
+                .associated_features
+                .iter()
+                .filter_map(|id| features.get(&id))
+                .map(|bucket| (bucket.chrom as u32, bucket.idx)),
+        ),
+        max_log10_sig: bucket_data.max_sig,
+        max_abs_effect: max_abs_effect(bucket_data.max_effect, bucket_data.min_effect),
+        count_is_lower_bound: bucket_data.truncated,
+        annotations: Vec::new(),
+        associated_buckets_truncated: bucket_data.associated_truncated,
+        sig_sum: scale_sum(sig_sum_from_fixed(bucket_data.sig_sum), sample_rate),
+        over_threshold_count: scale_count(bucket_data.over_threshold_count, sample_rate),
+    }
+}
+
+// The lists gen_filtered_data groups each chromosome's buckets into. Keeping them around
+// between calls (see FilterContext) saves reallocating them as they grow.
+#[derive(Default)]
+pub(crate) struct BucketScratch {
+    chrom_buckets: FxHashMap<u8, Vec<(u32, BucketData)>>,
+}
+
+// Turns intermediate bucket data into FilteredBuckets, in order, for each of the
+// (chromosome index, intervals) pairs. Buckets on any other chromosome are left out, and
+// those on chromosomes that aren't in `data_chromosomes` at all are counted. With a window
+// step, the FilteredBuckets are overlapping windows instead of buckets.
+// Returns the summary statistics of the included buckets.
+#[allow(clippy::too_many_arguments)]
+fn gen_filtered_data(
+    buckets: FxHashMap<BucketLoc, BucketData>,
+    intervals: Vec<(u8, &mut Vec<FilteredBucket>)>,
+    data_chromosomes: &FxHashSet<u8>,
+    bucket_size: u32,
+    window_step: Option<u32>,
+    preview: PreviewOptions,
+    features: &FxHashMap<DbID, BucketLoc>,
+    scratch: &mut BucketScratch,
+) -> BucketStats {
+    let chrom_buckets = &mut scratch.chrom_buckets;
+    for (bucket_loc, bucket_data) in buckets {
+        chrom_buckets
+            .entry(bucket_loc.chrom)
+            .or_default()
+            .push((bucket_loc.idx, bucket_data));
     }
 
-    // Facet id sets that have values being filtered on
-    let f_with_selections: Vec<FxHashSet<DbID>> = facet_ids
+    let mut chrom_bucket_lists: FxHashMap<u8, &mut Vec<(u32, BucketData)>> = chrom_buckets
+        .iter_mut()
+        .filter(|(_, buckets)| !buckets.is_empty())
+        .map(|(chrom, buckets)| (*chrom, buckets))
+        .collect();
+    let chrom_intervals: Vec<_> = intervals
         .into_iter()
-        .filter(|f| !f.is_disjoint(&coverage_data_cat_facets))
+        .filter_map(|(chrom, intervals)| {
+            chrom_bucket_lists
+                .remove(&chrom)
+                .map(|buckets| (intervals, buckets))
+        })
         .collect();
 
-    // Facet id sets that have values being filtered on and only have the filtered values included in the set
-    let selected_f: Vec<Vec<DbID>> = f_with_selections
-        .iter()
-        .map(|f| (f & &coverage_data_cat_facets).iter().cloned().collect())
-        .collect();
+    let mut stats = chrom_intervals
+        .into_par_iter()
+        .map(|(intervals, buckets)| {
+            let mut windows;
+            let buckets = match window_step {
+                Some(window_step) => {
+                    windows = window_buckets(std::mem::take(buckets), bucket_size, window_step);
+                    &mut windows
+                }
+                None => buckets,
+            };
+            buckets.sort_by_key(|(idx, _)| *idx);
 
-    // println!("{:?}", filters.categorical_facets); // all filtered facet values
-    // println!("{:?}", all_coverage_data_cat_facets); // all facet values used in data
-    // println!("{:?}", coverage_data_cat_facets); // interesection of the above two
-    // println!("{:?}", f_with_selections); // Facet id sets that have values being filtered on
-    // println!("{:?}", selected_f); // the above, but only including values in the filter
-
-    let direction_facet = data.facets.iter().find(|f| f.name == "Direction").unwrap();
-    let direction_facet_values: FxHashSet<DbID> = direction_facet
-        .values
-        .as_ref()
-        .unwrap()
-        .iter()
-        .map(|fv| *fv.0)
-        .collect();
-    let nonsignificant_facet_value = direction_facet
-        .values
-        .as_ref()
-        .unwrap()
+            let mut stats = BucketStats::new();
+            // Draining keeps the list's allocation for the next call that uses the scratch
+            for (bucket_idx, mut bucket_data) in buckets.drain(..) {
+                if let Some(feature_cap) = preview.feature_cap {
+                    if bucket_data.feature_ids.len() > feature_cap {
+                        bucket_data.truncated |=
+                            cap_features(&mut bucket_data.feature_ids, feature_cap);
+                    }
+                    if bucket_data.associated_features.len() > feature_cap {
+                        bucket_data.associated_truncated |=
+                            cap_features(&mut bucket_data.associated_features, feature_cap);
+                    }
+                }
+                stats.add(&bucket_data);
+                intervals.push(filtered_bucket(
+                    bucket_idx,
+                    bucket_data,
+                    window_step.unwrap_or(bucket_size),
+                    preview.sample_rate,
+                    features,
+                ));
+            }
+            stats
+        })
+        .reduce(BucketStats::new, BucketStats::merge);
+
+    stats.dropped_buckets = chrom_buckets
         .iter()
-        .find(|(_, fv_name)| *fv_name == "Non-significant");
-
-    // Skip filtering (i.e., drop completely) non-significant observations IF
-    // * at least one direction facet value is checked
-    // * and the non-significant facet value isn't checked
-    // * There are no non-significant observations
-    let skip_nonsignificants = if let Some(nfv) = nonsignificant_facet_value {
-        f_with_selections.contains(&direction_facet_values)
-            && !coverage_data_cat_facets.contains(&nfv.0)
-    } else {
-        true
+        .filter(|(chrom, _)| !data_chromosomes.contains(chrom))
+        .map(|(_, buckets)| buckets.len() as u64)
+        .sum();
+    chrom_buckets.values_mut().for_each(Vec::clear);
+    stats
+}
+
+// The range of the finite effect sizes and significances of the observations. Used for data sets
+// whose numeric facets don't have ranges. If there are no finite values the range is (0, 0).
+fn observed_ranges(data: &CoverageData) -> (FacetRange, FacetRange64) {
+    let empty_ranges = || {
+        (
+            f32::INFINITY,
+            f32::NEG_INFINITY,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+        )
     };
 
-    //
-    // Filter Observations
-    //
+    let (min_effect, max_effect, min_sig, max_sig) = data
+        .significant_observations
+        .par_iter()
+        .chain(data.nonsignificant_observations.par_iter())
+        .fold(empty_ranges, |mut acc, observation| {
+            if observation.effect_size.is_finite() {
+                acc.0 = acc.0.min(observation.effect_size);
+                acc.1 = acc.1.max(observation.effect_size);
+            }
+            if observation.neg_log_significance.is_finite() {
+                acc.2 = acc.2.min(observation.neg_log_significance);
+                acc.3 = acc.3.max(observation.neg_log_significance);
+            }
+            acc
+        })
+        .reduce(empty_ranges, |a, b| {
+            (a.0.min(b.0), a.1.max(b.1), a.2.min(b.2), a.3.max(b.3))
+        });
 
-    let empty_vec = Vec::<ObservationData>::new();
-    let observations = if skip_nonsignificants {
-        data.significant_observations
-            .par_iter()
-            .chain(empty_vec.par_iter())
+    // Adding 0.0 turns -0.0 into 0.0, so the ranges don't depend on the order the
+    // observations were seen in
+    let effect_range = if min_effect <= max_effect {
+        FacetRange(min_effect + 0.0, max_effect + 0.0)
     } else {
-        data.significant_observations
-            .par_iter()
-            .chain(data.nonsignificant_observations.par_iter())
+        FacetRange(0.0, 0.0)
     };
+    let sig_range = if min_sig <= max_sig {
+        FacetRange64(min_sig + 0.0, max_sig + 0.0)
+    } else {
+        FacetRange64(0.0, 0.0)
+    };
+    (effect_range, sig_range)
+}
 
-    let filtered_observations: Vec<&ObservationData> =
-        if skip_cont_facet_check && f_with_selections.is_empty() {
-            if let Some(included_features) = included_features {
-                observations
-                    .filter(|o| -> bool {
-                        if let Some(target_id) = o.target_id {
-                            included_features.targets.contains(target_id)
-                        } else {
-                            false
-                        }
-                    })
-                    .collect()
-            } else {
-                observations.collect()
-            }
-        } else {
-            let filtered_observations = observations.filter(|observation| -> bool {
-                if skip_cat_facet_check
-                    || selected_f
-                        .iter()
-                        .all(|f| !is_disjoint(&observation.facet_value_ids, f))
-                {
-                    if skip_cont_facet_check
-                        || (observation.effect_size >= effect_size_interval.0
-                            && observation.effect_size <= effect_size_interval.1
-                            && observation.neg_log_significance >= sig_interval.0
-                            && observation.neg_log_significance <= sig_interval.1)
-                    {
-                        return true;
-                    }
-                }
-
-                false
-            });
+// The parts of a data set's facets that filtering needs, which don't depend on the filter.
+pub(crate) struct DataFacets {
+    // The ranges of the "Effect Size" and "Significance" facets. If a facet doesn't have a
+    // range, it's computed from the observations. A degenerate range (min == max) is used
+    // as is.
+    pub effect_size_range: FacetRange,
+    pub sig_range: FacetRange64,
+    // All categorical facet value database ids that are used in this data set.
+    // This may not be all possible facet values.
+    all_cat_facet_values: FxHashSet<DbID>,
+    // All categorical facet value database ids that are used in this data set,
+    // divided up by facet
+    cat_facet_ids: Vec<FxHashSet<DbID>>,
+    direction_facet_values: FxHashSet<DbID>,
+    nonsignificant_facet_value: Option<DbID>,
+}
 
-            if let Some(included_features) = included_features {
-                filtered_observations
-                    .filter(|o| -> bool {
-                        if let Some(target_id) = o.target_id {
-                            included_features.targets.contains(target_id)
-                        } else {
-                            false
-                        }
-                    })
-                    .collect()
-            } else {
-                filtered_observations.collect()
+impl DataFacets {
+    pub fn new(data: &CoverageData) -> Self {
+        //
+        // Get Numeric Facet Info
+        //
+        let effect_size_range = data
+            .facets
+            .iter()
+            .find(|f| f.name == EFFECT_SIZE_FACET)
+            .and_then(|f| f.range);
+        let sig_range = data
+            .facets
+            .iter()
+            .find(|f| f.name == SIGNIFICANCE_FACET)
+            .and_then(|f| f.range64);
+        let (effect_size_range, sig_range) = match (effect_size_range, sig_range) {
+            (Some(effect_size_range), Some(sig_range)) => (effect_size_range, sig_range),
+            (effect_size_range, sig_range) => {
+                let (observed_effect_range, observed_sig_range) = observed_ranges(data);
+                (
+                    effect_size_range.unwrap_or(observed_effect_range),
+                    sig_range.unwrap_or(observed_sig_range),
+                )
             }
         };
 
-    //
-    // Build intermediate bucket data
-    //
-
-    // How many "chunks" of work we want to split building buckets into, since it can't be fully parallelized
-    // due to using a shared data structure
-    let p_count = if let Ok(p_count) = std::thread::available_parallelism() {
-        p_count.get()
-    } else {
-        6
-    };
-
-    // Merge filtered observations into an intermediate set of data structures
-    // that will then be turned into FilteredData
-    let observation_chunks =
-        filtered_observations.par_chunks(1.max(filtered_observations.len() / p_count));
-    let filter_results: Vec<(
-        RoaringTreemap,
-        FxHashMap<BucketLoc, BucketData>,
-        FxHashMap<BucketLoc, BucketData>,
-    )> = observation_chunks
-        .map(|chunk| {
-            let mut reos = RoaringTreemap::new();
-            let mut source_buckets = FxHashMap::<BucketLoc, BucketData>::default();
-            let mut target_buckets = FxHashMap::<BucketLoc, BucketData>::default();
-
-            for observation in chunk {
-                reos.insert(observation.reo_id);
-                update_buckets(
-                    observation,
-                    &mut source_buckets,
-                    &mut target_buckets,
-                    &feature_buckets,
-                );
+        //
+        // Get Categorical Facet Info
+        //
+        let mut all_cat_facet_values: FxHashSet<DbID> = FxHashSet::default();
+        for facet in data.facets.iter() {
+            if let Some(facet_values) = &facet.values {
+                facet_values.keys().for_each(|key| {
+                    all_cat_facet_values.insert(*key);
+                });
             }
+        }
 
-            (reos, source_buckets, target_buckets)
-        })
-        .collect();
+        let mut cat_facet_ids: Vec<FxHashSet<DbID>> = Vec::new();
+        for facet in data
+            .facets
+            .iter()
+            .filter(|f| f.facet_type == "FacetType.CATEGORICAL")
+        {
+            cat_facet_ids.push(FxHashSet::from_iter(
+                facet.values.as_ref().unwrap().keys().cloned(),
+            ));
+        }
 
-    // Merge bucket collections together
-    let mut reos = RoaringTreemap::new();
-    let mut source_buckets = FxHashMap::<BucketLoc, BucketData>::default();
-    let mut target_buckets = FxHashMap::<BucketLoc, BucketData>::default();
+        let direction_facet = data.facets.iter().find(|f| f.name == "Direction").unwrap();
+        let direction_facet_values: FxHashSet<DbID> = direction_facet
+            .values
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|fv| *fv.0)
+            .collect();
+        let nonsignificant_facet_value = direction_facet
+            .values
+            .as_ref()
+            .unwrap()
+            .iter()
+            .find(|(_, fv_name)| *fv_name == "Non-significant")
+            .map(|fv| *fv.0);
 
-    for (rc, sb, tb) in filter_results {
-        reos.extend(rc);
-        update_bucket_map(&mut source_buckets, &sb);
-        update_bucket_map(&mut target_buckets, &tb);
+        DataFacets {
+            effect_size_range,
+            sig_range,
+            all_cat_facet_values,
+            cat_facet_ids,
+            direction_facet_values,
+            nonsignificant_facet_value,
+        }
     }
+}
 
-    //
-    // Build Final output data
-    //
+// For each REO, the (source id, target id) of the observation that's used when deduplicating
+pub(crate) type CanonicalObservations = FxHashMap<DbID, (DbID, Option<DbID>)>;
 
-    // Turn bucket lists into chromosome
-    let mut chromosomes: Vec<_> = if let Some(chromo_idx) = filters.chrom {
-        data.chromosomes
-            .iter()
-            .filter(|c| c.index == chromo_idx)
-            .map(|c| FilteredChromosome {
-                chrom: c.chrom.clone(),
-                index: c.index,
-                bucket_size: data.bucket_size,
-                target_intervals: Vec::new(),
-                source_intervals: Vec::new(),
-            })
-            .collect()
-    } else {
-        data.chromosomes
-            .iter()
-            .map(|c| FilteredChromosome {
-                chrom: c.chrom.clone(),
-                index: c.index,
-                bucket_size: data.bucket_size,
-                target_intervals: Vec::new(),
-                source_intervals: Vec::new(),
-            })
-            .collect()
-    };
+// Picks the observation with the smallest (source id, target id) for each REO. This doesn't
+// depend on the order the observations are seen in, so the choice is the same no matter how
+// the work is split up.
+pub(crate) fn canonical_observations(data: &CoverageData) -> CanonicalObservations {
+    canonical_observations_in(
+        data.significant_observations
+            .par_iter()
+            .chain(data.nonsignificant_observations.par_iter()),
+    )
+}
 
-    let mut sources = RoaringTreemap::default();
-    let mut targets = RoaringTreemap::default();
-    let mut min_effect = f32::INFINITY;
-    let mut max_effect = f32::NEG_INFINITY;
+// canonical_observations, but only picking from `observations`
+pub(crate) fn canonical_observations_in<'a, I>(observations: I) -> CanonicalObservations
+where
+    I: ParallelIterator<Item = &'a ObservationData>,
+{
+    fn add(observations: &mut CanonicalObservations, reo_id: DbID, pair: (DbID, Option<DbID>)) {
+        match observations.entry(reo_id) {
+            Entry::Occupied(mut entry) => {
+                if pair < *entry.get() {
+                    entry.insert(pair);
+                }
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(pair);
+            }
+        }
+    }
 
-    let mut min_sig = f64::INFINITY;
-    let mut max_sig = f64::NEG_INFINITY;
+    observations
+        .fold(
+            CanonicalObservations::default,
+            |mut observations, observation| {
+                add(
+                    &mut observations,
+                    observation.reo_id,
+                    (observation.source_id, observation.target_id),
+                );
+                observations
+            },
+        )
+        .reduce(
+            CanonicalObservations::default,
+            |mut observations1, observations2| {
+                for (reo_id, pair) in observations2 {
+                    add(&mut observations1, reo_id, pair);
+                }
+                observations1
+            },
+        )
+}
 
-    gen_filtered_data(
-        source_buckets,
-        filters.chrom,
-        &mut sources,
-        &mut min_effect,
-        &mut max_effect,
-        &mut min_sig,
-        &mut max_sig,
-        &mut chromosomes
-            .iter_mut()
-            .map(|c| &mut c.source_intervals)
-            .collect(),
-        bucket_size,
-        feature_buckets,
-    );
-    gen_filtered_data(
-        target_buckets,
-        filters.chrom,
-        &mut targets,
-        &mut min_effect,
-        &mut max_effect,
-        &mut min_sig,
-        &mut max_sig,
-        &mut chromosomes
-            .iter_mut()
-            .map(|c| &mut c.target_intervals)
-            .collect(),
-        bucket_size,
-        feature_buckets,
-    );
+// Mixes up the bits of an REO id (SplitMix64), so sampling by REO id doesn't depend on how
+// the ids were assigned
+pub(crate) fn reo_hash(reo_id: DbID) -> u64 {
+    let mut z = reo_id.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
 
-    // Make sure no numeric intervals include infinity
-    min_effect = if min_effect == f32::INFINITY {
+pub(crate) struct SupportedPairs {
+    // The (source id, target id) pairs with enough supporting observations
+    pairs: FxHashSet<(DbID, DbID)>,
+    excludes_unpaired: bool,
+}
+
+// The (source id, target id) pairs with at least `min_support` of `observations` that
+// match `predicate`
+fn supported_pairs<'a, I>(
+    predicate: &ObservationFilter,
+    observations: I,
+    min_support: u32,
+) -> FxHashSet<(DbID, DbID)>
+where
+    I: ParallelIterator<Item = &'a ObservationData>,
+{
+    observations
+        .filter(|observation| predicate.matches(observation))
+        .filter_map(|observation| {
+            observation
+                .target_id
+                .map(|target_id| (observation.source_id, target_id))
+        })
+        .fold(
+            FxHashMap::default,
+            |mut counts: FxHashMap<(DbID, DbID), u32>, pair| {
+                *counts.entry(pair).or_default() += 1;
+                counts
+            },
+        )
+        .reduce(FxHashMap::default, |mut counts1, counts2| {
+            for (pair, count) in counts2 {
+                *counts1.entry(pair).or_default() += count;
+            }
+            counts1
+        })
+        .into_iter()
+        .filter(|(_, count)| *count >= min_support)
+        .map(|(pair, _)| pair)
+        .collect()
+}
+
+// A NaN effect size or significance can't be compared against a numeric filter and would
+// poison the bucket statistics, so observations with one are always left out of the results.
+pub(crate) fn has_invalid_values(observation: &ObservationData) -> bool {
+    observation.effect_size.is_nan() || observation.neg_log_significance.is_nan()
+}
+
+// The observation predicate shared by every filtering entry point. Building it
+// does all the facet bookkeeping that only depends on the filter and the data set,
+// so checking an individual observation is cheap.
+pub(crate) struct ObservationFilter<'a> {
+    pub effect_size_interval: FacetRange,
+    pub sig_interval: FacetRange64,
+    // Facet value sets that have values being filtered on, only including the filtered values.
+    // An observation has to have at least one value from each set.
+    pub selected_f: Vec<Vec<DbID>>,
+    pub skip_nonsignificants: bool,
+    // Significance values are clamped to this, so observations with a p-value of 0 (an
+    // infinite -log10 significance) can be filtered and summarized
+    pub sig_ceiling: f64,
+    pub sig_threshold: Option<f64>,
+    skip_cat_facet_check: bool,
+    skip_cont_facet_check: bool,
+    included_features: Option<&'a ExperimentFeatureData>,
+    // Set when only one observation per REO should be used
+    canonical_observations: Option<Arc<CanonicalObservations>>,
+    // Set when pairs need a minimum number of supporting observations
+    supported_pairs: Option<SupportedPairs>,
+    pub preview: PreviewOptions,
+    pub output_sides: OutputSides,
+    pub omit_empty_chromosomes: bool,
+    feature_match_mode: FeatureMatchMode,
+    pub max_associated_buckets_per_bucket: Option<usize>,
+    pub max_buckets_per_chromosome: Option<usize>,
+    // The data set's chromosomes other than the one the filter is restricted to. Features on
+    // them are never bucketed, since their buckets would be left out of the results anyway.
+    // Buckets on chromosomes that aren't in the data set at all are still made, so they're
+    // counted as dropped.
+    skipped_chromosomes: FxHashSet<u8>,
+    // Problems with the filter found while building the predicate
+    pub warnings: Vec<FilterWarning>,
+}
+
+impl<'a> ObservationFilter<'a> {
+    pub fn new(
+        filters: &Filter,
+        data: &CoverageData,
+        included_features: Option<&'a ExperimentFeatureData>,
+    ) -> Self {
+        let mut predicate =
+            ObservationFilter::from_facets(filters, &DataFacets::new(data), included_features);
+        // An unknown chromosome is reported by the filter functions themselves
+        let chrom = filters.chromosome_index(&data.chromosomes).unwrap_or(None);
+        predicate.prepare_observations(filters, data, chrom, || {
+            Arc::new(canonical_observations(data))
+        });
+        predicate
+    }
+
+    // Sets up the parts of the predicate that depend on the data's observations rather than
+    // just its facets: REO deduplication, pair support, and the chromosome restriction.
+    // `canonical_observations` is only called if the filter deduplicates REOs.
+    pub fn prepare_observations<C>(
+        &mut self,
+        filters: &Filter,
+        data: &CoverageData,
+        chrom: Option<u8>,
+        canonical_observations: C,
+    ) where
+        C: FnOnce() -> Arc<CanonicalObservations>,
+    {
+        self.prepare_observations_until(filters, data, chrom, canonical_observations, || false);
+    }
+
+    // prepare_observations, but the pair support pass skips the rest of the observations once
+    // `stop` returns true (`canonical_observations` can stop its pass the same way), leaving
+    // the predicate only partly set up. Callers have to check `stop` afterwards and throw the
+    // predicate away if it's true.
+    pub fn prepare_observations_until<C, S>(
+        &mut self,
+        filters: &Filter,
+        data: &CoverageData,
+        chrom: Option<u8>,
+        canonical_observations: C,
+        stop: S,
+    ) where
+        C: FnOnce() -> Arc<CanonicalObservations>,
+        S: Fn() -> bool + Sync,
+    {
+        if filters.dedup_reos {
+            self.canonical_observations = Some(canonical_observations());
+        }
+        if let Some(min_support) = filters.min_pair_support {
+            let observations = self.observations(data).filter(|_| !stop());
+            self.min_pair_support_in(
+                observations,
+                min_support,
+                filters.pair_support_excludes_unpaired,
+            );
+        }
+        self.restrict_to_chromosome(chrom, data);
+    }
+
+    pub fn from_facets(
+        filters: &Filter,
+        facets: &DataFacets,
+        included_features: Option<&'a ExperimentFeatureData>,
+    ) -> Self {
+        //
+        // Get Numeric Facet Info
+        //
+        let skip_cont_facet_check = filters.numeric_intervals.is_none();
+
+        let effect_size_interval = match &filters.numeric_intervals {
+            Some(c) => FacetRange(c.effect.0, c.effect.1),
+            None => facets.effect_size_range,
+        };
+        let sig_interval = match &filters.numeric_intervals {
+            Some(c) => FacetRange64(c.sig.0, c.sig.1),
+            None => facets.sig_range,
+        };
+
+        //
+        // Get Categorical Facet Info
+        //
+
+        // Categorical facet value database ids for that are filtered on, not including
+        // facet values that aren't used in this data set.
+        let coverage_data_cat_facets: FxHashSet<DbID> = facets
+            .all_cat_facet_values
+            .intersection(&filters.categorical_facets)
+            .cloned()
+            .collect();
+
+        let skip_cat_facet_check = coverage_data_cat_facets.is_empty();
+
+        // Facet id sets that have values being filtered on
+        let f_with_selections: Vec<&FxHashSet<DbID>> = facets
+            .cat_facet_ids
+            .iter()
+            .filter(|f| !f.is_disjoint(&coverage_data_cat_facets))
+            .collect();
+
+        // Facet id sets that have values being filtered on and only have the filtered values included in the set
+        let selected_f: Vec<Vec<DbID>> = f_with_selections
+            .iter()
+            .map(|f| (*f & &coverage_data_cat_facets).iter().cloned().collect())
+            .collect();
+
+        // Skip filtering (i.e., drop completely) non-significant observations IF
+        // * at least one direction facet value is checked
+        // * and the non-significant facet value isn't checked
+        // * There are no non-significant observations
+        let skip_nonsignificants = if let Some(nfv) = facets.nonsignificant_facet_value {
+            f_with_selections.contains(&&facets.direction_facet_values)
+                && !coverage_data_cat_facets.contains(&nfv)
+        } else {
+            true
+        };
+
+        let mut warnings = Vec::new();
+        let unknown_facet_ids = filters
+            .categorical_facets
+            .difference(&facets.all_cat_facet_values)
+            .count();
+        if unknown_facet_ids > 0 {
+            warnings.push(FilterWarning::UnknownCategoricalFacetIds(unknown_facet_ids));
+        }
+        if let Some(intervals) = &filters.numeric_intervals {
+            if intervals.effect.1 < facets.effect_size_range.0
+                || intervals.effect.0 > facets.effect_size_range.1
+            {
+                warnings.push(FilterWarning::NumericIntervalOutsideDataRange(
+                    EFFECT_SIZE_FACET.to_string(),
+                ));
+            }
+            if intervals.sig.1 < facets.sig_range.0 || intervals.sig.0 > facets.sig_range.1 {
+                warnings.push(FilterWarning::NumericIntervalOutsideDataRange(
+                    SIGNIFICANCE_FACET.to_string(),
+                ));
+            }
+        }
+        if let Some(included_features) = included_features {
+            if included_features.targets.is_empty()
+                && (included_features.sources.is_empty()
+                    || filters.feature_match_mode == FeatureMatchMode::TargetOnly)
+            {
+                warnings.push(FilterWarning::IncludedFeaturesEmpty);
+            }
+        }
+
+        ObservationFilter {
+            effect_size_interval,
+            sig_interval,
+            selected_f,
+            skip_nonsignificants,
+            sig_ceiling: filters.sig_ceiling.unwrap_or(-MIN_SIG.log10()),
+            sig_threshold: filters.sig_threshold,
+            skip_cat_facet_check,
+            skip_cont_facet_check,
+            included_features,
+            canonical_observations: None,
+            supported_pairs: None,
+            preview: filters.preview.unwrap_or_default(),
+            output_sides: filters.output_sides,
+            omit_empty_chromosomes: filters.omit_empty_chromosomes,
+            feature_match_mode: filters.feature_match_mode,
+            max_associated_buckets_per_bucket: filters.max_associated_buckets_per_bucket,
+            max_buckets_per_chromosome: filters.max_buckets_per_chromosome,
+            skipped_chromosomes: FxHashSet::default(),
+            warnings,
+        }
+    }
+
+    // Only bucket features on chromosome `chrom`, if it's set. This doesn't change the
+    // results, which only ever include that chromosome, but it keeps features on the other
+    // chromosomes out of the bucket maps. Observations are still matched (and counted in
+    // reo_count) no matter where their features are, and a trans observation still adds
+    // to whichever of its source and target buckets is on `chrom`.
+    pub fn restrict_to_chromosome(&mut self, chrom: Option<u8>, data: &CoverageData) {
+        if let Some(chrom) = chrom {
+            if !data.chromosomes.iter().any(|c| c.index == chrom) {
+                self.warnings.push(FilterWarning::ChromosomeNotFound(chrom));
+            }
+        }
+        self.skipped_chromosomes = match chrom {
+            Some(chrom) => data
+                .chromosomes
+                .iter()
+                .map(|c| c.index)
+                .filter(|index| *index != chrom)
+                .collect(),
+            None => FxHashSet::default(),
+        };
+    }
+
+    // Whether the observation's source or target is on a chromosome restrict_to_chromosome
+    // didn't skip, i.e., whether bucketing it adds to a bucket
+    pub fn on_included_chromosome(
+        &self,
+        observation: &ObservationData,
+        data: &CoverageData,
+    ) -> bool {
+        self.skipped_chromosomes.is_empty()
+            || std::iter::once(observation.source_id)
+                .chain(observation.target_id)
+                .any(|feature_id| {
+                    data.feature_buckets
+                        .get(&feature_id)
+                        .is_some_and(|bucket| !self.skipped_chromosomes.contains(&bucket.chrom))
+                })
+    }
+
+    // Only match one observation per REO
+    pub fn dedup_reos(&mut self, data: &CoverageData) {
+        self.canonical_observations = Some(Arc::new(canonical_observations(data)));
+    }
+
+    // Only match observations whose (source, target) pair has at least `min_support`
+    // observations that pass the rest of the filter, counting support from `observations`,
+    // which have to include every observation that could pass the rest of the filter. This
+    // has to be called after dedup_reos.
+    pub fn min_pair_support_in<'b, I>(
+        &mut self,
+        observations: I,
+        min_support: u32,
+        excludes_unpaired: bool,
+    ) where
+        I: ParallelIterator<Item = &'b ObservationData>,
+    {
+        let pairs = supported_pairs(self, observations, min_support);
+        self.supported_pairs = Some(SupportedPairs {
+            pairs,
+            excludes_unpaired,
+        });
+    }
+
+    // The observations that need to be checked against the filter
+    pub fn observations<'b>(
+        &self,
+        data: &'b CoverageData,
+    ) -> impl IndexedParallelIterator<Item = &'b ObservationData> {
+        let observations: &'b [ObservationData] = if self.skip_nonsignificants {
+            &[]
+        } else {
+            &data.nonsignificant_observations
+        };
+        data.significant_observations
+            .par_iter()
+            .chain(observations.par_iter())
+    }
+
+    pub fn matches_categorical(&self, observation: &ObservationData) -> bool {
+        self.skip_cat_facet_check
+            || self
+                .selected_f
+                .iter()
+                .all(|f| !is_disjoint(&observation.facet_value_ids, f))
+    }
+
+    // The observation's significance, clamped to the ceiling
+    pub fn significance(&self, observation: &ObservationData) -> f64 {
+        observation.neg_log_significance.min(self.sig_ceiling)
+    }
+
+    pub fn matches_numeric(&self, observation: &ObservationData) -> bool {
+        self.skip_cont_facet_check
+            || (observation.effect_size >= self.effect_size_interval.0
+                && observation.effect_size <= self.effect_size_interval.1
+                && self.significance(observation) >= self.sig_interval.0
+                && self.significance(observation) <= self.sig_interval.1)
+    }
+
+    pub fn matches_included_features(&self, observation: &ObservationData) -> bool {
+        match self.included_features {
+            Some(included_features) => match (observation.target_id, self.feature_match_mode) {
+                (Some(target_id), _) => included_features.targets.contains(target_id),
+                (None, FeatureMatchMode::TargetOrUnpairedSource) => {
+                    included_features.sources.contains(observation.source_id)
+                }
+                (None, FeatureMatchMode::TargetOnly) => false,
+            },
+            None => true,
+        }
+    }
+
+    pub fn matches_dedup(&self, observation: &ObservationData) -> bool {
+        match &self.canonical_observations {
+            Some(canonical_observations) => {
+                canonical_observations.get(&observation.reo_id)
+                    == Some(&(observation.source_id, observation.target_id))
+            }
+            None => true,
+        }
+    }
+
+    // Whether the observation's REO is in a preview's sample
+    pub fn matches_sample(&self, observation: &ObservationData) -> bool {
+        match self.preview.sample_rate {
+            Some(sample_rate) => {
+                let hash = reo_hash(observation.reo_id);
+                // The top 53 bits, as a fraction between 0 and 1
+                ((hash >> 11) as f64 / (1u64 << 53) as f64) < sample_rate
+            }
+            None => true,
+        }
+    }
+
+    pub fn matches_pair_support(&self, observation: &ObservationData) -> bool {
+        match &self.supported_pairs {
+            Some(supported_pairs) => match observation.target_id {
+                Some(target_id) => supported_pairs
+                    .pairs
+                    .contains(&(observation.source_id, target_id)),
+                None => !supported_pairs.excludes_unpaired,
+            },
+            None => true,
+        }
+    }
+
+    // Whether the observation passes every part of the filter, i.e., whether
+    // bucket_matching_observations would bucket it
+    pub fn matches(&self, observation: &ObservationData) -> bool {
+        self.matches_dedup(observation)
+            && self.matches_sample(observation)
+            && self.matches_non_numeric(observation)
+            && !has_invalid_values(observation)
+            && self.matches_numeric(observation)
+            && self.matches_pair_support(observation)
+    }
+
+    // Every part of the filter except the numeric intervals
+    pub fn matches_non_numeric(&self, observation: &ObservationData) -> bool {
+        self.matches_categorical(observation) && self.matches_included_features(observation)
+    }
+}
+
+pub fn filter_coverage_data(
+    filters: &Filter,
+    data: &CoverageData,
+    included_features: Option<&ExperimentFeatureData>,
+) -> Result<FilteredData, FilterError> {
+    let chrom = filters.chromosome_index(&data.chromosomes)?;
+    let window_step = filters.checked_window_step(data.bucket_size)?;
+    let predicate = ObservationFilter::new(filters, data, included_features);
+
+    //
+    // Filter Observations and build intermediate bucket data
+    //
+
+    let bucket_results = bucket_matching_observations(
+        predicate.observations(data),
+        &predicate,
+        |observation| predicate.matches_non_numeric(observation),
+        &data.feature_buckets,
+    );
+
+    Ok(build_filtered_data(
+        data,
+        &predicate,
+        bucket_results,
+        empty_chromosomes(chrom, data),
+        window_step,
+    ))
+}
+
+// The chromosomes that will be part of the filter output, without any intervals.
+pub(crate) fn empty_chromosomes(chrom: Option<u8>, data: &CoverageData) -> Vec<FilteredChromosome> {
+    if let Some(chromo_idx) = chrom {
+        data.chromosomes
+            .iter()
+            .filter(|c| c.index == chromo_idx)
+            .map(|c| FilteredChromosome {
+                chrom: c.chrom.clone(),
+                index: c.index,
+                bucket_size: data.bucket_size,
+                target_intervals: Vec::new(),
+                source_intervals: Vec::new(),
+                truncated: false,
+                bucket_count: 0,
+            })
+            .collect()
+    } else {
+        data.chromosomes
+            .iter()
+            .map(|c| FilteredChromosome {
+                chrom: c.chrom.clone(),
+                index: c.index,
+                bucket_size: data.bucket_size,
+                target_intervals: Vec::new(),
+                source_intervals: Vec::new(),
+                truncated: false,
+                bucket_count: 0,
+            })
+            .collect()
+    }
+}
+
+// filter_coverage_data, but all the parallel work is done in `pool` rather than
+// rayon's global thread pool. Use this to limit how many cores a filter call can use.
+pub fn filter_coverage_data_in_pool(
+    pool: &ThreadPool,
+    filters: &Filter,
+    data: &CoverageData,
+    included_features: Option<&ExperimentFeatureData>,
+) -> Result<FilteredData, FilterError> {
+    pool.install(|| filter_coverage_data(filters, data, included_features))
+}
+
+// Turns the intermediate bucket data into FilteredData
+pub(crate) fn build_filtered_data(
+    data: &CoverageData,
+    predicate: &ObservationFilter,
+    bucket_results: BucketResults,
+    chromosomes: Vec<FilteredChromosome>,
+    window_step: Option<u32>,
+) -> FilteredData {
+    build_filtered_data_with(
+        data,
+        predicate,
+        bucket_results,
+        chromosomes,
+        window_step,
+        data.bucket_size,
+        &data.feature_buckets,
+        &mut BucketScratch::default(),
+    )
+}
+
+// build_filtered_data for buckets of `bucket_size`, with the features located by
+// `feature_buckets` rather than the data set's own bucket locations. The buckets are grouped
+// using `scratch`'s buffers.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn build_filtered_data_with(
+    data: &CoverageData,
+    predicate: &ObservationFilter,
+    bucket_results: BucketResults,
+    mut chromosomes: Vec<FilteredChromosome>,
+    window_step: Option<u32>,
+    bucket_size: u32,
+    feature_buckets: &FxHashMap<DbID, BucketLoc>,
+    scratch: &mut BucketScratch,
+) -> FilteredData {
+    let data_chromosomes: FxHashSet<u8> = data.chromosomes.iter().map(|c| c.index).collect();
+    let effect_size_interval = predicate.effect_size_interval;
+    let sig_interval = FacetRange64(
+        predicate.sig_interval.0.min(predicate.sig_ceiling),
+        predicate.sig_interval.1.min(predicate.sig_ceiling),
+    );
+    let BucketResults {
+        reos,
+        source_buckets,
+        target_buckets,
+        dropped_invalid,
+    } = bucket_results;
+
+    //
+    // Build Final output data
+    //
+
+    // The bucket maps of a side that isn't included are empty, so there's nothing to build
+    let source_stats = if predicate.output_sides.includes_sources() {
+        gen_filtered_data(
+            source_buckets,
+            chromosomes
+                .iter_mut()
+                .map(|c| (c.index, &mut c.source_intervals))
+                .collect(),
+            &data_chromosomes,
+            bucket_size,
+            window_step,
+            predicate.preview,
+            feature_buckets,
+            scratch,
+        )
+    } else {
+        BucketStats::new()
+    };
+    let target_stats = if predicate.output_sides.includes_targets() {
+        gen_filtered_data(
+            target_buckets,
+            chromosomes
+                .iter_mut()
+                .map(|c| (c.index, &mut c.target_intervals))
+                .collect(),
+            &data_chromosomes,
+            bucket_size,
+            window_step,
+            predicate.preview,
+            feature_buckets,
+            scratch,
+        )
+    } else {
+        BucketStats::new()
+    };
+
+    let sources = source_stats.feature_ids;
+    let targets = target_stats.feature_ids;
+    let mut min_effect = source_stats.min_effect.min(target_stats.min_effect);
+    let mut max_effect = source_stats.max_effect.max(target_stats.max_effect);
+    // The ranges of the observed negative and positive effect sizes, if there were any
+    let effect_negative = (min_effect < 0.0).then(|| {
+        (
+            min_effect,
+            merge_max_negative(
+                source_stats.max_negative_effect,
+                target_stats.max_negative_effect,
+            ),
+        )
+    });
+    let effect_positive = (max_effect > 0.0).then(|| {
+        (
+            merge_min_positive(
+                source_stats.min_positive_effect,
+                target_stats.min_positive_effect,
+            ),
+            max_effect,
+        )
+    });
+    let mut min_sig = source_stats.min_sig.min(target_stats.min_sig);
+    let mut max_sig = source_stats.max_sig.max(target_stats.max_sig);
+
+    // Make sure no numeric intervals include infinity
+    min_effect = if min_effect == f32::INFINITY {
         effect_size_interval.0
     } else {
         min_effect
@@ -501,15 +1450,1531 @@ pub fn filter_coverage_data(
         max_sig
     };
 
+    let bucket_counts = chromosome_bucket_counts(
+        feature_buckets,
+        window_step.map_or(1, |window_step| bucket_size / window_step),
+    );
+    for chromosome in &mut chromosomes {
+        chromosome.bucket_count = bucket_counts.get(&chromosome.index).copied().unwrap_or(0);
+    }
+
+    chromosomes.sort_by_key(|c| c.index);
+    let omitted_empty = if predicate.omit_empty_chromosomes {
+        omit_empty_chromosomes(&mut chromosomes)
+    } else {
+        Vec::new()
+    };
+    for chromosome in &mut chromosomes {
+        apply_output_budget(
+            chromosome,
+            predicate.max_buckets_per_chromosome,
+            predicate.max_associated_buckets_per_bucket,
+        );
+    }
+
     FilteredData {
         chromosomes,
         bucket_size,
         numeric_intervals: FilterIntervals {
             effect: (min_effect, max_effect),
             sig: (min_sig, max_sig),
+            effect_negative,
+            effect_positive,
         },
-        reo_count: reos.len(),
+        reo_count: scale_count(reos.len(), predicate.preview.sample_rate),
+        dropped_invalid,
+        dropped_buckets: source_stats.dropped_buckets + target_stats.dropped_buckets,
+        window_step,
+        approximate: predicate.preview.is_approximate(),
+        counts_approximate: false,
+        annotation_names: Vec::new(),
+        omitted_empty,
+        warnings: predicate.warnings.clone(),
         sources,
         targets,
     }
 }
+
+// Leaves all but the `max_buckets` most significant buckets out of the intervals, and all but
+// the first `max_associated_buckets` of each bucket's associated buckets. Returns whether any
+// buckets were left out.
+fn apply_interval_budget(
+    intervals: &mut Vec<FilteredBucket>,
+    max_buckets: Option<usize>,
+    max_associated_buckets: Option<usize>,
+) -> bool {
+    let truncated = match max_buckets {
+        Some(max_buckets) if intervals.len() > max_buckets => {
+            let mut ranked: Vec<(f64, u64)> = intervals
+                .iter()
+                .map(|bucket| (bucket.max_log10_sig, bucket.start))
+                .collect();
+            ranked.sort_unstable_by(|(sig1, start1), (sig2, start2)| {
+                sig2.total_cmp(sig1).then(start1.cmp(start2))
+            });
+            let kept: FxHashSet<u64> = ranked[..max_buckets]
+                .iter()
+                .map(|(_, start)| *start)
+                .collect();
+            intervals.retain(|bucket| kept.contains(&bucket.start));
+            true
+        }
+        _ => false,
+    };
+
+    if let Some(max_associated_buckets) = max_associated_buckets {
+        // Associated buckets are stored as (chromosome index, bucket index) pairs
+        let max_len = max_associated_buckets.saturating_mul(2);
+        for bucket in intervals.iter_mut() {
+            if bucket.associated_buckets.len() > max_len {
+                bucket.associated_buckets.truncate(max_len);
+                bucket.associated_buckets_truncated = true;
+            }
+        }
+    }
+
+    truncated
+}
+
+// Cuts a chromosome's intervals down to a filter's output budget (see
+// Filter.max_buckets_per_chromosome and Filter.max_associated_buckets_per_bucket)
+pub(crate) fn apply_output_budget(
+    chromosome: &mut FilteredChromosome,
+    max_buckets: Option<usize>,
+    max_associated_buckets: Option<usize>,
+) {
+    let sources_truncated = apply_interval_budget(
+        &mut chromosome.source_intervals,
+        max_buckets,
+        max_associated_buckets,
+    );
+    let targets_truncated = apply_interval_budget(
+        &mut chromosome.target_intervals,
+        max_buckets,
+        max_associated_buckets,
+    );
+    chromosome.truncated |= sources_truncated || targets_truncated;
+}
+
+// Removes the chromosomes without any intervals, and returns their names
+pub(crate) fn omit_empty_chromosomes(chromosomes: &mut Vec<FilteredChromosome>) -> Vec<String> {
+    let mut omitted = Vec::new();
+    chromosomes.retain(|c| {
+        let is_empty = c.source_intervals.is_empty() && c.target_intervals.is_empty();
+        if is_empty {
+            omitted.push(c.chrom.clone());
+        }
+        !is_empty
+    });
+    omitted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synthetic::SyntheticCoverageDataBuilder;
+    use crate::test_data::{add_nan_values, assert_same_results, coverage_data, facet_value_id};
+
+    // Enough observations that they're bucketed in several chunks
+    fn chunked_coverage_data(seed: u64) -> CoverageData {
+        SyntheticCoverageDataBuilder::new()
+            .seed(seed)
+            .chromosomes(4)
+            .buckets_per_chromosome(50)
+            .features(2_000)
+            .observations(60_000)
+            .build()
+    }
+
+    #[test]
+    fn test_merged_bucket_stats_match_sequential() {
+        let mut data = chunked_coverage_data(1);
+        add_nan_values(&mut data, 101, 211);
+        let mut filters = Filter::new();
+        filters.numeric_intervals = Some(FilterIntervals {
+            effect: (-4.0, 4.0),
+            sig: (0.5, 18.0),
+            ..FilterIntervals::new()
+        });
+        let predicate = ObservationFilter::new(&filters, &data, None);
+
+        let mut sequential = BucketResults::default();
+        for observation in data
+            .significant_observations
+            .iter()
+            .chain(data.nonsignificant_observations.iter())
+        {
+            if has_invalid_values(observation) {
+                sequential.dropped_invalid += 1;
+            } else if predicate.matches_numeric(observation) {
+                sequential.add(observation, &predicate, &data.feature_buckets);
+            }
+        }
+
+        assert_same_results(
+            &filter_coverage_data(&filters, &data, None).unwrap(),
+            &build_filtered_data(
+                &data,
+                &predicate,
+                sequential,
+                empty_chromosomes(None, &data),
+                None,
+            ),
+        );
+    }
+
+    #[test]
+    fn test_single_thread_pool() {
+        let data = chunked_coverage_data(2);
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .thread_name(|i| format!("exp-viz-test-{}", i))
+            .build()
+            .unwrap();
+        let filters = Filter::new();
+        assert_same_results(
+            &filter_coverage_data_in_pool(&pool, &filters, &data, None).unwrap(),
+            &filter_coverage_data(&filters, &data, None).unwrap(),
+        );
+
+        // The parallel sections only run on the pool's thread
+        let thread_names = std::sync::Mutex::new(FxHashSet::default());
+        pool.install(|| {
+            let predicate = ObservationFilter::new(&filters, &data, None);
+            bucket_matching_observations(
+                predicate.observations(&data),
+                &predicate,
+                |_| {
+                    let name = std::thread::current().name().map(str::to_string);
+                    thread_names.lock().unwrap().insert(name);
+                    true
+                },
+                &data.feature_buckets,
+            )
+        });
+        assert_eq!(
+            thread_names.into_inner().unwrap(),
+            [Some("exp-viz-test-0".to_string())].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn test_bucketing_chunk_size() {
+        // Small inputs are bucketed in one chunk
+        assert_eq!(bucketing_chunk_size(0), 1);
+        assert_eq!(bucketing_chunk_size(1_000), 1_000);
+        assert_eq!(
+            bucketing_chunk_size(SEQUENTIAL_BUCKETING_THRESHOLD - 1),
+            9_999
+        );
+        assert_eq!(
+            bucketing_chunk_size(SEQUENTIAL_BUCKETING_THRESHOLD),
+            MIN_BUCKETING_CHUNK_SIZE
+        );
+        assert_eq!(bucketing_chunk_size(10_000_000), MIN_BUCKETING_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn test_results_dont_depend_on_thread_count() {
+        for observations in [1_000, 60_000] {
+            let data = SyntheticCoverageDataBuilder::new()
+                .seed(3)
+                .chromosomes(4)
+                .buckets_per_chromosome(50)
+                .features(2_000)
+                .observations(observations)
+                .build();
+            let mut filters = Filter::new();
+            filters.numeric_intervals = Some(FilterIntervals {
+                effect: (-3.0, 4.0),
+                sig: (1.0, 16.0),
+                ..FilterIntervals::new()
+            });
+
+            let results: Vec<FilteredData> = [1, 2, 4, 8]
+                .into_iter()
+                .map(|num_threads| {
+                    let pool = rayon::ThreadPoolBuilder::new()
+                        .num_threads(num_threads)
+                        .build()
+                        .unwrap();
+                    filter_coverage_data_in_pool(&pool, &filters, &data, None).unwrap()
+                })
+                .collect();
+            for result in &results[1..] {
+                assert_same_results(&results[0], result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_preview() {
+        let data = chunked_coverage_data(5);
+        let exact = filter_coverage_data(&Filter::new(), &data, None).unwrap();
+
+        // Previews that don't change anything give the exact results
+        for preview in [
+            PreviewOptions::default(),
+            PreviewOptions {
+                feature_cap: None,
+                sample_rate: Some(1.0),
+            },
+        ] {
+            let filtered =
+                filter_coverage_data(&Filter::new().preview(preview), &data, None).unwrap();
+            assert!(!filtered.approximate);
+            assert_same_results(&filtered, &exact);
+        }
+
+        // Sampled previews are the same every time, with any number of threads
+        let sampled = Filter::new().preview(PreviewOptions {
+            feature_cap: Some(3),
+            sample_rate: Some(0.25),
+        });
+        let first = filter_coverage_data(&sampled, &data, None).unwrap();
+        assert!(first.approximate);
+        assert_ne!(first.reo_count, exact.reo_count);
+        for num_threads in [1, 2, 8] {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(num_threads)
+                .build()
+                .unwrap();
+            for _ in 0..2 {
+                assert_same_results(
+                    &filter_coverage_data_in_pool(&pool, &sampled, &data, None).unwrap(),
+                    &first,
+                );
+            }
+        }
+
+        // Buckets keep at most feature_cap features, and buckets that had more are marked
+        let capped = filter_coverage_data(
+            &Filter::new().preview(PreviewOptions {
+                feature_cap: Some(3),
+                sample_rate: None,
+            }),
+            &data,
+            None,
+        )
+        .unwrap();
+        assert!(capped.approximate);
+        let mut lower_bounds = 0;
+        for (capped_chrom, exact_chrom) in capped.chromosomes.iter().zip(&exact.chromosomes) {
+            for (capped_intervals, exact_intervals) in [
+                (
+                    &capped_chrom.source_intervals,
+                    &exact_chrom.source_intervals,
+                ),
+                (
+                    &capped_chrom.target_intervals,
+                    &exact_chrom.target_intervals,
+                ),
+            ] {
+                assert_eq!(capped_intervals.len(), exact_intervals.len());
+                for (capped_bucket, exact_bucket) in capped_intervals.iter().zip(exact_intervals) {
+                    assert_eq!(capped_bucket.start, exact_bucket.start);
+                    assert_eq!(capped_bucket.count, exact_bucket.count.min(3));
+                    assert_eq!(capped_bucket.count_is_lower_bound, exact_bucket.count > 3);
+                    lower_bounds += capped_bucket.count_is_lower_bound as usize;
+                }
+            }
+        }
+        assert!(lower_bounds > 0);
+
+        // Buckets with more associated features than the cap are marked, since some of their
+        // associated buckets can be missing
+        let mut associated_features: [FxHashMap<(u8, u64), FxHashSet<DbID>>; 2] =
+            Default::default();
+        let bucket = |id: DbID| {
+            let loc = data.feature_buckets[&id];
+            (loc.chrom, loc.idx as u64 * data.bucket_size as u64 + 1)
+        };
+        let observations = data
+            .significant_observations
+            .iter()
+            .chain(&data.nonsignificant_observations);
+        for observation in observations {
+            if let Some(target_id) = observation.target_id {
+                associated_features[0]
+                    .entry(bucket(observation.source_id))
+                    .or_default()
+                    .insert(target_id);
+                associated_features[1]
+                    .entry(bucket(target_id))
+                    .or_default()
+                    .insert(observation.source_id);
+            }
+        }
+        let mut associated_truncated = 0;
+        for chromosome in &capped.chromosomes {
+            let sides = [&chromosome.source_intervals, &chromosome.target_intervals];
+            for (side, intervals) in sides.into_iter().enumerate() {
+                for bucket in intervals {
+                    let feature_count = associated_features[side]
+                        .get(&(chromosome.index, bucket.start))
+                        .map_or(0, |features| features.len());
+                    assert_eq!(bucket.associated_buckets_truncated, feature_count > 3);
+                    associated_truncated += bucket.associated_buckets_truncated as usize;
+                }
+            }
+        }
+        assert!(associated_truncated > 0);
+    }
+
+    #[test]
+    fn test_output_sides() {
+        let data = coverage_data(9);
+        let both = filter_coverage_data(&Filter::new(), &data, None).unwrap();
+        let json = |intervals: &Vec<FilteredBucket>| serde_json::to_value(intervals).unwrap();
+
+        for output_sides in [OutputSides::SourcesOnly, OutputSides::TargetsOnly] {
+            let one_side =
+                filter_coverage_data(&Filter::new().output_sides(output_sides), &data, None)
+                    .unwrap();
+            let sources = output_sides.includes_sources();
+            assert_eq!(one_side.reo_count, both.reo_count);
+            assert_eq!(one_side.chromosomes.len(), both.chromosomes.len());
+            for (chromosome, both_chromosome) in one_side.chromosomes.iter().zip(&both.chromosomes)
+            {
+                if sources {
+                    assert_eq!(
+                        json(&chromosome.source_intervals),
+                        json(&both_chromosome.source_intervals)
+                    );
+                    assert!(chromosome.target_intervals.is_empty());
+                } else {
+                    assert_eq!(
+                        json(&chromosome.target_intervals),
+                        json(&both_chromosome.target_intervals)
+                    );
+                    assert!(chromosome.source_intervals.is_empty());
+                }
+            }
+            if sources {
+                assert_eq!(one_side.sources, both.sources);
+                assert!(one_side.targets.is_empty());
+            } else {
+                assert_eq!(one_side.targets, both.targets);
+                assert!(one_side.sources.is_empty());
+            }
+
+            // The numeric intervals only cover one side, so they're within both sides'
+            let (effect, both_effect) = (
+                one_side.numeric_intervals.effect,
+                both.numeric_intervals.effect,
+            );
+            assert!(effect.0 >= both_effect.0 && effect.1 <= both_effect.1);
+            let (sig, both_sig) = (one_side.numeric_intervals.sig, both.numeric_intervals.sig);
+            assert!(sig.0 >= both_sig.0 && sig.1 <= both_sig.1);
+        }
+    }
+
+    #[test]
+    fn test_effect_ranges_by_sign() {
+        let mut data = coverage_data(10);
+        add_nan_values(&mut data, 23, 29);
+        data.significant_observations[0].effect_size = 0.0;
+
+        // Computed by hand from every valid observation
+        let effects: Vec<f32> = data
+            .significant_observations
+            .iter()
+            .chain(&data.nonsignificant_observations)
+            .filter(|o| !has_invalid_values(o))
+            .map(|o| o.effect_size)
+            .collect();
+        let range = |effects: Vec<f32>| {
+            effects
+                .into_iter()
+                .fold(None, |range: Option<(f32, f32)>, effect| match range {
+                    Some((min, max)) => Some((min.min(effect), max.max(effect))),
+                    None => Some((effect, effect)),
+                })
+        };
+        let negative = range(effects.iter().copied().filter(|e| *e < 0.0).collect());
+        let positive = range(effects.iter().copied().filter(|e| *e > 0.0).collect());
+        assert!(negative.is_some() && positive.is_some());
+
+        let filtered = filter_coverage_data(&Filter::new(), &data, None).unwrap();
+        assert_eq!(filtered.numeric_intervals.effect_negative, negative);
+        assert_eq!(filtered.numeric_intervals.effect_positive, positive);
+
+        // Without any effect sizes of a sign, its range is None. Zeros are in neither.
+        let mut positive_data = data.clone();
+        for observation in positive_data
+            .significant_observations
+            .iter_mut()
+            .chain(positive_data.nonsignificant_observations.iter_mut())
+        {
+            observation.effect_size = observation.effect_size.abs();
+        }
+        let filtered = filter_coverage_data(&Filter::new(), &positive_data, None).unwrap();
+        assert_eq!(filtered.numeric_intervals.effect_negative, None);
+        assert!(filtered.numeric_intervals.effect_positive.unwrap().0 > 0.0);
+
+        let mut zero_data = data.clone();
+        for observation in zero_data
+            .significant_observations
+            .iter_mut()
+            .chain(zero_data.nonsignificant_observations.iter_mut())
+        {
+            observation.effect_size = 0.0;
+        }
+        let filtered = filter_coverage_data(&Filter::new(), &zero_data, None).unwrap();
+        assert_eq!(filtered.numeric_intervals.effect, (0.0, 0.0));
+        assert_eq!(filtered.numeric_intervals.effect_negative, None);
+        assert_eq!(filtered.numeric_intervals.effect_positive, None);
+    }
+
+    #[test]
+    fn test_feature_match_modes() {
+        let data = coverage_data(18);
+        let included_features = ExperimentFeatureData {
+            sources: (0..500).filter(|id| id % 3 != 0).collect(),
+            targets: (0..500).filter(|id| id % 2 == 0).collect(),
+        };
+        let observations: Vec<&ObservationData> = data
+            .significant_observations
+            .iter()
+            .chain(&data.nonsignificant_observations)
+            .collect();
+        assert!(observations.iter().any(|o| o.target_id.is_none()));
+
+        for mode in [
+            FeatureMatchMode::TargetOrUnpairedSource,
+            FeatureMatchMode::TargetOnly,
+        ] {
+            // The sources and REOs of the observations that should match, found by hand
+            let matching: Vec<&&ObservationData> = observations
+                .iter()
+                .filter(|o| match o.target_id {
+                    Some(target_id) => included_features.targets.contains(target_id),
+                    None => {
+                        mode == FeatureMatchMode::TargetOrUnpairedSource
+                            && included_features.sources.contains(o.source_id)
+                    }
+                })
+                .collect();
+            let sources: RoaringTreemap = matching.iter().map(|o| o.source_id).collect();
+            let reos: FxHashSet<DbID> = matching.iter().map(|o| o.reo_id).collect();
+
+            let filtered = filter_coverage_data(
+                &Filter::new().feature_match_mode(mode),
+                &data,
+                Some(&included_features),
+            )
+            .unwrap();
+            assert_eq!(filtered.sources, sources);
+            assert_eq!(filtered.reo_count, reos.len() as u64);
+            assert!(filtered.targets.is_subset(&included_features.targets));
+        }
+
+        // Without included features, the mode doesn't matter
+        assert_same_results(
+            &filter_coverage_data(
+                &Filter::new().feature_match_mode(FeatureMatchMode::TargetOnly),
+                &data,
+                None,
+            )
+            .unwrap(),
+            &filter_coverage_data(&Filter::new(), &data, None).unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_serialization_is_deterministic() {
+        let mut data = chunked_coverage_data(6);
+        add_nan_values(&mut data, 103, 107);
+        let filters = Filter::new()
+            .window_step(500)
+            .numeric_intervals(FilterIntervals {
+                effect: (-3.0, 3.0),
+                sig: (0.5, 15.0),
+                ..FilterIntervals::new()
+            });
+
+        let mut outputs = (0..20).map(|run| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(run % 8 + 1)
+                .build()
+                .unwrap();
+            let filtered = filter_coverage_data_in_pool(&pool, &filters, &data, None).unwrap();
+            (
+                serde_json::to_string(&filtered).unwrap(),
+                bincode::serialize(&filtered).unwrap(),
+            )
+        });
+        let (json, bytes) = outputs.next().unwrap();
+        for (run_json, run_bytes) in outputs {
+            assert!(run_json == json);
+            assert!(run_bytes == bytes);
+        }
+    }
+
+    #[test]
+    fn test_output_budget() {
+        let data = coverage_data(13);
+        let full = filter_coverage_data(&Filter::new(), &data, None).unwrap();
+        let json = |intervals: &Vec<FilteredBucket>| serde_json::to_value(intervals).unwrap();
+        assert!(full.chromosomes.iter().all(|c| !c.truncated));
+
+        // Budgets that everything fits in exactly don't change anything
+        let max_buckets = full
+            .chromosomes
+            .iter()
+            .map(|c| c.source_intervals.len().max(c.target_intervals.len()))
+            .max()
+            .unwrap();
+        let max_associated = full
+            .chromosomes
+            .iter()
+            .flat_map(|c| c.source_intervals.iter().chain(c.target_intervals.iter()))
+            .map(|b| b.associated_buckets.len() / 2)
+            .max()
+            .unwrap();
+        let at_budget = filter_coverage_data(
+            &Filter::new()
+                .max_buckets_per_chromosome(max_buckets)
+                .max_associated_buckets_per_bucket(max_associated),
+            &data,
+            None,
+        )
+        .unwrap();
+        assert_same_results(&at_budget, &full);
+
+        // Over the budget, the most significant buckets (ties broken by position) and the
+        // first associated buckets are kept, without changing their stats
+        let over_budget = filter_coverage_data(
+            &Filter::new()
+                .max_buckets_per_chromosome(10)
+                .max_associated_buckets_per_bucket(1),
+            &data,
+            None,
+        )
+        .unwrap();
+        assert_eq!(over_budget.reo_count, full.reo_count);
+        assert_eq!(over_budget.sources, full.sources);
+        assert_eq!(over_budget.targets, full.targets);
+        let expected_intervals = |intervals: &Vec<FilteredBucket>| {
+            let mut kept: Vec<FilteredBucket> = intervals.clone();
+            kept.sort_by(|b1, b2| {
+                b2.max_log10_sig
+                    .total_cmp(&b1.max_log10_sig)
+                    .then(b1.start.cmp(&b2.start))
+            });
+            kept.truncate(10);
+            kept.sort_by_key(|b| b.start);
+            for bucket in &mut kept {
+                if bucket.associated_buckets.len() > 2 {
+                    bucket.associated_buckets.truncate(2);
+                    bucket.associated_buckets_truncated = true;
+                }
+            }
+            kept
+        };
+        for (chromosome, full_chromosome) in over_budget.chromosomes.iter().zip(&full.chromosomes) {
+            assert_eq!(
+                chromosome.truncated,
+                full_chromosome.source_intervals.len() > 10
+                    || full_chromosome.target_intervals.len() > 10
+            );
+            assert_eq!(
+                json(&chromosome.source_intervals),
+                json(&expected_intervals(&full_chromosome.source_intervals))
+            );
+            assert_eq!(
+                json(&chromosome.target_intervals),
+                json(&expected_intervals(&full_chromosome.target_intervals))
+            );
+        }
+        assert!(over_budget.chromosomes.iter().any(|c| c.truncated));
+    }
+
+    #[test]
+    fn test_chromosome_restriction() {
+        // The synthetic data's features are spread over all the chromosomes, so most
+        // observations are trans. In the cis data each target is moved to a feature on its
+        // source's chromosome.
+        let trans_data = coverage_data(14);
+        let mut cis_data = coverage_data(14);
+        let mut chromosome_features: FxHashMap<u8, Vec<DbID>> = FxHashMap::default();
+        let mut feature_ids: Vec<&DbID> = cis_data.feature_buckets.keys().collect();
+        feature_ids.sort_unstable();
+        for id in feature_ids {
+            chromosome_features
+                .entry(cis_data.feature_buckets[id].chrom)
+                .or_default()
+                .push(*id);
+        }
+        let feature_buckets = cis_data.feature_buckets.clone();
+        let observations = cis_data
+            .significant_observations
+            .iter_mut()
+            .chain(cis_data.nonsignificant_observations.iter_mut());
+        for observation in observations {
+            if let Some(target_id) = observation.target_id {
+                let features = &chromosome_features[&feature_buckets[&observation.source_id].chrom];
+                observation.target_id = Some(features[target_id as usize % features.len()]);
+            }
+        }
+
+        let numeric = FilterIntervals {
+            effect: (-3.0, 3.0),
+            sig: (1.0, 15.0),
+            ..FilterIntervals::new()
+        };
+        for data in [&cis_data, &trans_data] {
+            for chrom in 0..4 {
+                for filters in [
+                    Filter::new().chrom(chrom),
+                    Filter::new().chrom(chrom).numeric_intervals(numeric),
+                ] {
+                    let restricted = ObservationFilter::new(&filters, data, None);
+                    let restricted_results = bucket_matching_observations(
+                        restricted.observations(data),
+                        &restricted,
+                        |observation| restricted.matches_non_numeric(observation),
+                        &data.feature_buckets,
+                    );
+                    assert!(restricted_results
+                        .source_buckets
+                        .keys()
+                        .chain(restricted_results.target_buckets.keys())
+                        .all(|loc| loc.chrom == chrom));
+
+                    // The same filter, with every chromosome's features bucketed
+                    let mut unrestricted = ObservationFilter::new(&filters, data, None);
+                    unrestricted.restrict_to_chromosome(None, data);
+                    let unrestricted_results = bucket_matching_observations(
+                        unrestricted.observations(data),
+                        &unrestricted,
+                        |observation| unrestricted.matches_non_numeric(observation),
+                        &data.feature_buckets,
+                    );
+                    let expected = build_filtered_data(
+                        data,
+                        &unrestricted,
+                        unrestricted_results,
+                        empty_chromosomes(Some(chrom), data),
+                        None,
+                    );
+                    assert_same_results(
+                        &filter_coverage_data(&filters, data, None).unwrap(),
+                        &expected,
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_bucket_count() {
+        let mut data = coverage_data(15);
+        // Chromosome 3 has no features, and chromosome 2's last feature is in bucket 36
+        for loc in data.feature_buckets.values_mut() {
+            if loc.chrom == 3 {
+                loc.chrom = 0;
+            } else if loc.chrom == 2 {
+                loc.idx = loc.idx.min(36);
+            }
+        }
+        let mut last_buckets: FxHashMap<u8, u32> = FxHashMap::default();
+        for loc in data.feature_buckets.values() {
+            let last_bucket = last_buckets.entry(loc.chrom).or_default();
+            *last_bucket = (*last_bucket).max(loc.idx);
+        }
+        assert_eq!(last_buckets[&2], 36);
+
+        // Each bucket has 4 windows
+        for (filters, intervals_per_bucket) in
+            [(Filter::new(), 1), (Filter::new().window_step(250), 4)]
+        {
+            let result = filter_coverage_data(&filters, &data, None).unwrap();
+            let interval_size = (data.bucket_size / intervals_per_bucket) as u64;
+            for chromosome in &result.chromosomes {
+                let expected = last_buckets
+                    .get(&chromosome.index)
+                    .map_or(0, |last_bucket| (last_bucket + 1) * intervals_per_bucket);
+                assert_eq!(chromosome.bucket_count, expected);
+                assert!(chromosome
+                    .source_intervals
+                    .iter()
+                    .chain(chromosome.target_intervals.iter())
+                    .all(|b| (b.start - 1) / interval_size < chromosome.bucket_count as u64));
+            }
+            assert_eq!(result.chromosomes[3].bucket_count, 0);
+        }
+    }
+
+    #[test]
+    fn test_sig_sums() {
+        let mut data = coverage_data(16);
+        data.significant_observations[0].neg_log_significance = f64::INFINITY;
+        let chromosome_list: Vec<String> =
+            data.chromosomes.iter().map(|c| c.chrom.clone()).collect();
+        // (chromosome index, start) -> (sig_sum, over_threshold_count), for each side
+        type BucketSums = FxHashMap<(u8, u64), (f64, u64)>;
+        let bucket_sums = |result: &FilteredData| {
+            let mut sources = BucketSums::default();
+            let mut targets = BucketSums::default();
+            for chromosome in &result.chromosomes {
+                for (intervals, sums) in [
+                    (&chromosome.source_intervals, &mut sources),
+                    (&chromosome.target_intervals, &mut targets),
+                ] {
+                    for bucket in intervals {
+                        sums.insert(
+                            (chromosome.index, bucket.start),
+                            (bucket.sig_sum, bucket.over_threshold_count),
+                        );
+                    }
+                }
+            }
+            (sources, targets)
+        };
+
+        for sig_threshold in [None, Some(5.0)] {
+            let mut filters = Filter::new();
+            filters.sig_threshold = sig_threshold;
+            let result = filter_coverage_data(&filters, &data, None).unwrap();
+
+            // Summed by hand, in fixed point, with infinite significance clamped to the ceiling
+            let predicate = ObservationFilter::new(&filters, &data, None);
+            let mut expected: [FxHashMap<(u8, u64), (i128, u64)>; 2] = Default::default();
+            let observations = data
+                .significant_observations
+                .iter()
+                .chain(&data.nonsignificant_observations);
+            for observation in observations {
+                let sig = predicate.significance(observation);
+                assert!(sig.is_finite());
+                let over = sig_threshold.is_some_and(|threshold| sig > threshold) as u64;
+                let sides = [Some(observation.source_id), observation.target_id];
+                for (side, id) in sides.into_iter().enumerate() {
+                    if let Some(loc) = id.and_then(|id| data.feature_buckets.get(&id)) {
+                        let start = loc.idx as u64 * data.bucket_size as u64 + 1;
+                        let sums = expected[side].entry((loc.chrom, start)).or_default();
+                        sums.0 += sig_sum_to_fixed(sig);
+                        sums.1 += over;
+                    }
+                }
+            }
+            let expected = expected.map(|sums| {
+                sums.into_iter()
+                    .map(|(loc, (sig_sum, over))| (loc, (sig_sum_from_fixed(sig_sum), over)))
+                    .collect::<BucketSums>()
+            });
+            let (sources, targets) = bucket_sums(&result);
+            assert_eq!(sources, expected[0]);
+            assert_eq!(targets, expected[1]);
+            if sig_threshold.is_none() {
+                assert!(sources
+                    .values()
+                    .chain(targets.values())
+                    .all(|(_, over)| *over == 0));
+            } else {
+                assert!(sources.values().any(|(_, over)| *over > 0));
+            }
+
+            // Merging the results of two halves of the observations adds up their sums
+            let mut first_half = data.clone();
+            let mut second_half = data.clone();
+            let half = data.significant_observations.len() / 2;
+            first_half.significant_observations.truncate(half);
+            second_half.significant_observations.drain(..half);
+            second_half.nonsignificant_observations.clear();
+            let merged = crate::merge::merge_filtered_data(
+                vec![
+                    filter_coverage_data(&filters, &first_half, None).unwrap(),
+                    filter_coverage_data(&filters, &second_half, None).unwrap(),
+                ],
+                chromosome_list.clone(),
+            )
+            .unwrap();
+            assert_eq!(bucket_sums(&merged), bucket_sums(&result));
+        }
+    }
+
+    #[test]
+    fn test_warnings() {
+        let data = coverage_data(20);
+        let library_id = facet_value_id(&data, "Library", "Library 1");
+        let warnings = |filters: &Filter, included_features: Option<&ExperimentFeatureData>| {
+            filter_coverage_data(filters, &data, included_features)
+                .unwrap()
+                .warnings
+        };
+
+        // Facet values and a chromosome from another experiment
+        let mut filters = Filter::new().chrom(9);
+        filters.categorical_facets = [library_id, 999_998, 999_999].into_iter().collect();
+        assert_eq!(
+            warnings(&filters, None),
+            vec![
+                FilterWarning::UnknownCategoricalFacetIds(2),
+                FilterWarning::ChromosomeNotFound(9),
+            ]
+        );
+
+        // Numeric intervals past the data's effect sizes and significances
+        let filters = Filter::new().numeric_intervals(FilterIntervals {
+            effect: (10.0, 20.0),
+            sig: (30.0, 40.0),
+            ..FilterIntervals::new()
+        });
+        assert_eq!(
+            warnings(&filters, None),
+            vec![
+                FilterWarning::NumericIntervalOutsideDataRange(EFFECT_SIZE_FACET.to_string()),
+                FilterWarning::NumericIntervalOutsideDataRange(SIGNIFICANCE_FACET.to_string()),
+            ]
+        );
+
+        // Included features that nothing can match
+        let no_features = ExperimentFeatureData {
+            sources: RoaringTreemap::new(),
+            targets: RoaringTreemap::new(),
+        };
+        assert_eq!(
+            warnings(&Filter::new(), Some(&no_features)),
+            vec![FilterWarning::IncludedFeaturesEmpty]
+        );
+        let only_sources = ExperimentFeatureData {
+            sources: RoaringTreemap::from([1, 2, 3]),
+            targets: RoaringTreemap::new(),
+        };
+        assert!(warnings(&Filter::new(), Some(&only_sources)).is_empty());
+        assert_eq!(
+            warnings(
+                &Filter::new().feature_match_mode(FeatureMatchMode::TargetOnly),
+                Some(&only_sources)
+            ),
+            vec![FilterWarning::IncludedFeaturesEmpty]
+        );
+
+        // A filter that fits the data
+        let mut filters = Filter::new().chrom(1).numeric_intervals(FilterIntervals {
+            effect: (-1.0, 1.0),
+            sig: (2.0, 10.0),
+            ..FilterIntervals::new()
+        });
+        filters.categorical_facets = [library_id].into_iter().collect();
+        let features = ExperimentFeatureData {
+            sources: (0..100).collect(),
+            targets: (0..100).collect(),
+        };
+        assert!(warnings(&filters, Some(&features)).is_empty());
+        assert!(warnings(&Filter::new(), None).is_empty());
+    }
+
+    #[test]
+    fn test_fused_pass_matches_two_passes() {
+        let mut data = chunked_coverage_data(4);
+        add_nan_values(&mut data, 97, 89);
+        let mut categorical = Filter::new();
+        categorical.categorical_facets = [
+            facet_value_id(&data, "Direction", "Enriched Only"),
+            facet_value_id(&data, "Direction", "Non-significant"),
+        ]
+        .into_iter()
+        .collect();
+        let mut numeric = categorical.clone();
+        numeric.numeric_intervals = Some(FilterIntervals {
+            effect: (-2.5, 3.5),
+            sig: (0.0, 10.0),
+            ..FilterIntervals::new()
+        });
+
+        for filters in [Filter::new(), categorical, numeric] {
+            let predicate = ObservationFilter::new(&filters, &data, None);
+            // Collect the matching observations, then bucket them
+            let (invalid, matching): (Vec<&ObservationData>, Vec<&ObservationData>) = predicate
+                .observations(&data)
+                .filter(|observation| predicate.matches_non_numeric(observation))
+                .partition(|observation| has_invalid_values(observation));
+            let mut two_passes = BucketResults {
+                dropped_invalid: invalid.len() as u64,
+                ..BucketResults::default()
+            };
+            for observation in matching {
+                if predicate.matches(observation) {
+                    two_passes.add(observation, &predicate, &data.feature_buckets);
+                }
+            }
+
+            assert_same_results(
+                &filter_coverage_data(&filters, &data, None).unwrap(),
+                &build_filtered_data(
+                    &data,
+                    &predicate,
+                    two_passes,
+                    empty_chromosomes(None, &data),
+                    None,
+                ),
+            );
+        }
+    }
+
+    #[test]
+    fn test_non_contiguous_chromosome_indexes() {
+        let mut data = SyntheticCoverageDataBuilder::new()
+            .seed(5)
+            .chromosomes(2)
+            .buckets_per_chromosome(50)
+            .features(500)
+            .observations(5_000)
+            .build();
+        // Only chromosomes 3 and 7, listed out of order
+        let new_index = |chrom: u8| if chrom == 0 { 3 } else { 7 };
+        for chromosome in &mut data.chromosomes {
+            chromosome.index = new_index(chromosome.index);
+        }
+        data.chromosomes.reverse();
+        for bucket in data.feature_buckets.values_mut() {
+            bucket.chrom = new_index(bucket.chrom);
+        }
+
+        let result = filter_coverage_data(&Filter::new(), &data, None).unwrap();
+        let indexes: Vec<u8> = result.chromosomes.iter().map(|c| c.index).collect();
+        assert_eq!(indexes, vec![3, 7]);
+        assert_eq!(result.dropped_buckets, 0);
+        for chromosome in &result.chromosomes {
+            // Every observation's source bucket is in its chromosome's intervals
+            let starts: FxHashSet<u64> = chromosome
+                .source_intervals
+                .iter()
+                .map(|bucket| bucket.start)
+                .collect();
+            for observation in &data.significant_observations {
+                let bucket = data.feature_buckets[&observation.source_id];
+                if bucket.chrom == chromosome.index {
+                    assert!(starts.contains(&(bucket.idx as u64 * data.bucket_size as u64 + 1)));
+                }
+            }
+            assert!(chromosome
+                .source_intervals
+                .windows(2)
+                .all(|w| w[0].start < w[1].start));
+        }
+
+        let mut filters = Filter::new();
+        filters.chrom = Some(7);
+        let chrom_result = filter_coverage_data(&filters, &data, None).unwrap();
+        assert_eq!(chrom_result.chromosomes.len(), 1);
+        assert_eq!(chrom_result.chromosomes[0].index, 7);
+        assert_eq!(
+            chrom_result.chromosomes[0].source_intervals.len(),
+            result.chromosomes[1].source_intervals.len()
+        );
+    }
+
+    #[test]
+    fn test_nan_values_are_dropped() {
+        let mut numeric = Filter::new();
+        numeric.numeric_intervals = Some(FilterIntervals {
+            effect: (-3.0, 3.0),
+            sig: (0.0, 20.0),
+            ..FilterIntervals::new()
+        });
+
+        // NaN effect sizes, then NaN significances
+        for (effect_every, sig_every) in [(7, usize::MAX), (usize::MAX, 5)] {
+            let mut data = coverage_data(6);
+            add_nan_values(&mut data, effect_every, sig_every);
+            let mut valid_data = data.clone();
+            valid_data
+                .significant_observations
+                .retain(|observation| !has_invalid_values(observation));
+            valid_data
+                .nonsignificant_observations
+                .retain(|observation| !has_invalid_values(observation));
+            let invalid = (data.significant_observations.len()
+                + data.nonsignificant_observations.len()
+                - valid_data.significant_observations.len()
+                - valid_data.nonsignificant_observations.len()) as u64;
+            assert!(invalid > 0);
+
+            for filters in [Filter::new(), numeric.clone()] {
+                let result = filter_coverage_data(&filters, &data, None).unwrap();
+                let valid_result = filter_coverage_data(&filters, &valid_data, None).unwrap();
+
+                // Invalid observations are counted whether or not they'd pass the numeric
+                // intervals, and are otherwise ignored
+                assert_eq!(result.dropped_invalid, invalid);
+                assert_eq!(valid_result.dropped_invalid, 0);
+                assert_eq!(result.reo_count, valid_result.reo_count);
+                assert_eq!(
+                    serde_json::to_value(&result.chromosomes).unwrap(),
+                    serde_json::to_value(&valid_result.chromosomes).unwrap()
+                );
+
+                let intervals = result.numeric_intervals;
+                assert!(!intervals.effect.0.is_nan() && !intervals.effect.1.is_nan());
+                assert!(!intervals.sig.0.is_nan() && !intervals.sig.1.is_nan());
+                for bucket in result
+                    .chromosomes
+                    .iter()
+                    .flat_map(|c| c.source_intervals.iter().chain(c.target_intervals.iter()))
+                {
+                    assert!(!bucket.max_abs_effect.is_nan());
+                    assert!(!bucket.max_log10_sig.is_nan());
+                    assert!(!bucket.sig_sum.is_nan());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_infinite_significance_is_clamped() {
+        let mut data = coverage_data(7);
+        data.significant_observations[0].neg_log_significance = f64::INFINITY;
+
+        // The default ceiling, then one that's lower than some of the data's significances
+        for (sig_ceiling, ceiling) in [(None, -MIN_SIG.log10()), (Some(15.0), 15.0)] {
+            let mut filters = Filter::new();
+            filters.sig_ceiling = sig_ceiling;
+            let result = filter_coverage_data(&filters, &data, None).unwrap();
+            assert_eq!(result.numeric_intervals.sig.1, ceiling);
+            let buckets = || {
+                result
+                    .chromosomes
+                    .iter()
+                    .flat_map(|c| c.source_intervals.iter().chain(c.target_intervals.iter()))
+            };
+            assert!(buckets().all(|bucket| bucket.max_log10_sig <= ceiling));
+            assert!(buckets().any(|bucket| bucket.max_log10_sig == ceiling));
+            assert!(buckets().all(|bucket| bucket.sig_sum.is_finite()));
+            // Infinity would be serialized as null
+            assert_eq!(
+                serde_json::to_value(&result).unwrap()["numeric_intervals"]["sig"][1].as_f64(),
+                Some(ceiling)
+            );
+
+            // An upper bound at the ceiling includes the observation
+            filters.numeric_intervals = Some(FilterIntervals {
+                sig: (ceiling, ceiling),
+                ..FilterIntervals::new()
+            });
+            let at_ceiling = filter_coverage_data(&filters, &data, None).unwrap();
+            let ceiling_reos = data
+                .significant_observations
+                .iter()
+                .filter(|o| o.neg_log_significance >= ceiling)
+                .count() as u64;
+            assert!(ceiling_reos >= 1);
+            assert_eq!(at_ceiling.reo_count, ceiling_reos);
+            assert_eq!(at_ceiling.numeric_intervals.sig, (ceiling, ceiling));
+            assert!(at_ceiling
+                .sources
+                .contains(data.significant_observations[0].source_id));
+        }
+    }
+
+    #[test]
+    fn test_dedup_reos() {
+        let data = coverage_data(8);
+        // Every third REO is observed again with another source
+        let mut duplicated = data.clone();
+        for observations in [
+            &mut duplicated.significant_observations,
+            &mut duplicated.nonsignificant_observations,
+        ] {
+            let duplicates: Vec<ObservationData> = observations
+                .iter()
+                .filter(|observation| observation.reo_id % 3 == 0)
+                .map(|observation| ObservationData {
+                    source_id: (observation.source_id + 1) % 500,
+                    effect_size: -observation.effect_size,
+                    ..observation.clone()
+                })
+                .collect();
+            observations.extend(duplicates);
+        }
+        // The same observations in another order
+        let mut reordered = duplicated.clone();
+        reordered.significant_observations.reverse();
+        reordered.nonsignificant_observations.reverse();
+
+        // Only each REO's observation with the smallest (source, target)
+        let mut expected_data = duplicated.clone();
+        let canonical = canonical_observations(&duplicated);
+        for observations in [
+            &mut expected_data.significant_observations,
+            &mut expected_data.nonsignificant_observations,
+        ] {
+            observations.retain(|observation| {
+                canonical[&observation.reo_id] == (observation.source_id, observation.target_id)
+            });
+        }
+        assert_eq!(
+            expected_data.significant_observations.len(),
+            data.significant_observations.len()
+        );
+
+        let mut filters = Filter::new().dedup_reos(true);
+        let expected = filter_coverage_data(&Filter::new(), &expected_data, None).unwrap();
+        let result = filter_coverage_data(&filters, &duplicated, None).unwrap();
+        assert_same_results(&result, &expected);
+        assert_same_results(
+            &filter_coverage_data(&filters, &reordered, None).unwrap(),
+            &expected,
+        );
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(3)
+            .build()
+            .unwrap();
+        assert_same_results(
+            &filter_coverage_data_in_pool(&pool, &filters, &duplicated, None).unwrap(),
+            &expected,
+        );
+
+        // The REO count is the same either way, but the per-bucket statistics aren't
+        let without_dedup = filter_coverage_data(&Filter::new(), &duplicated, None).unwrap();
+        assert_eq!(without_dedup.reo_count, result.reo_count);
+        let sig_sum = |data: &FilteredData| -> f64 {
+            data.chromosomes
+                .iter()
+                .flat_map(|c| c.source_intervals.iter())
+                .map(|bucket| bucket.sig_sum)
+                .sum()
+        };
+        assert!(sig_sum(&without_dedup) > sig_sum(&result));
+
+        // Deduplication also applies with the rest of the filter
+        filters.categorical_facets = [facet_value_id(&data, "Library", "Library 1")]
+            .into_iter()
+            .collect();
+        let mut expected_filters = filters.clone();
+        expected_filters.dedup_reos = false;
+        assert_same_results(
+            &filter_coverage_data(&filters, &duplicated, None).unwrap(),
+            &filter_coverage_data(&expected_filters, &expected_data, None).unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_bucket_starts_past_u32() {
+        let mut data = coverage_data(9);
+        data.bucket_size = 100_000;
+        for chromosome in &mut data.chromosomes {
+            chromosome.bucket_size = 100_000;
+        }
+        let source_id = data.significant_observations[0].source_id;
+        let loc = data.feature_buckets.get_mut(&source_id).unwrap();
+        loc.idx = 50_000;
+        let chrom = loc.chrom;
+        let expected_start = 50_000 * 100_000 + 1;
+        assert!(expected_start > u32::MAX as u64);
+
+        let result = filter_coverage_data(&Filter::new(), &data, None).unwrap();
+        let chromosome = result
+            .chromosomes
+            .iter()
+            .find(|c| c.index == chrom)
+            .unwrap();
+        // It's the last bucket on the chromosome
+        assert_eq!(
+            chromosome.source_intervals.last().unwrap().start,
+            expected_start
+        );
+        assert_eq!(chromosome.bucket_count, 50_001);
+
+        let json = serde_json::to_string(&result).unwrap();
+        let from_json: FilteredData = serde_json::from_str(&json).unwrap();
+        let chromosome = from_json
+            .chromosomes
+            .iter()
+            .find(|c| c.index == chrom)
+            .unwrap();
+        assert_eq!(
+            chromosome.source_intervals.last().unwrap().start,
+            expected_start
+        );
+
+        let chromosome_list: Vec<String> =
+            data.chromosomes.iter().map(|c| c.chrom.clone()).collect();
+        let merged =
+            crate::merge::merge_filtered_data(vec![result.clone(), result], chromosome_list)
+                .unwrap();
+        let chromosome = merged
+            .chromosomes
+            .iter()
+            .find(|c| c.index == chrom)
+            .unwrap();
+        assert_eq!(
+            chromosome.source_intervals.last().unwrap().start,
+            expected_start
+        );
+    }
+
+    #[test]
+    fn test_missing_facet_ranges() {
+        let data = coverage_data(10);
+        let facets = DataFacets::new(&data);
+        let mut without_ranges = data.clone();
+        for facet in &mut without_ranges.facets {
+            facet.range = None;
+            facet.range64 = None;
+        }
+
+        // The synthetic data's ranges are the observed ranges, so computing them gives the
+        // same ones
+        let computed = DataFacets::new(&without_ranges);
+        assert_eq!(computed.effect_size_range, facets.effect_size_range);
+        assert_eq!(computed.sig_range, facets.sig_range);
+        let mut numeric = Filter::new();
+        numeric.numeric_intervals = Some(FilterIntervals {
+            effect: (-1.0, 1.0),
+            sig: (2.0, 10.0),
+            ..FilterIntervals::new()
+        });
+        for filters in [Filter::new(), numeric] {
+            assert_same_results(
+                &filter_coverage_data(&filters, &without_ranges, None).unwrap(),
+                &filter_coverage_data(&filters, &data, None).unwrap(),
+            );
+        }
+
+        // Only the missing range is computed
+        let mut without_sig_range = data.clone();
+        for facet in &mut without_sig_range.facets {
+            if facet.name == EFFECT_SIZE_FACET {
+                facet.range = Some(FacetRange(-100.0, 100.0));
+            }
+            facet.range64 = None;
+        }
+        let computed = DataFacets::new(&without_sig_range);
+        assert_eq!(computed.effect_size_range, FacetRange(-100.0, 100.0));
+        assert_eq!(computed.sig_range, facets.sig_range);
+
+        // Without any finite values to compute them from, the ranges are (0, 0)
+        add_nan_values(&mut without_ranges, 1, 1);
+        let computed = DataFacets::new(&without_ranges);
+        assert_eq!(computed.effect_size_range, FacetRange(0.0, 0.0));
+        assert_eq!(computed.sig_range, FacetRange64(0.0, 0.0));
+        let result = filter_coverage_data(&Filter::new(), &without_ranges, None).unwrap();
+        assert_eq!(result.reo_count, 0);
+    }
+
+    #[test]
+    fn test_degenerate_facet_ranges() {
+        let mut data = coverage_data(10);
+        for facet in &mut data.facets {
+            if facet.name == EFFECT_SIZE_FACET {
+                facet.range = Some(FacetRange(1.5, 1.5));
+            } else if facet.name == SIGNIFICANCE_FACET {
+                facet.range64 = Some(FacetRange64(3.0, 3.0));
+            }
+        }
+        let facets = DataFacets::new(&data);
+        assert_eq!(facets.effect_size_range, FacetRange(1.5, 1.5));
+        assert_eq!(facets.sig_range, FacetRange64(3.0, 3.0));
+
+        // The range isn't used to filter, only to check numeric intervals against
+        let result = filter_coverage_data(&Filter::new(), &data, None).unwrap();
+        assert_eq!(
+            result.reo_count,
+            (data.significant_observations.len() + data.nonsignificant_observations.len()) as u64
+        );
+        let mut filters = Filter::new();
+        filters.numeric_intervals = Some(FilterIntervals {
+            effect: (-1.0, 1.0),
+            ..FilterIntervals::new()
+        });
+        let result = filter_coverage_data(&filters, &data, None).unwrap();
+        assert!(result.reo_count > 0);
+        assert_eq!(
+            result.warnings,
+            vec![FilterWarning::NumericIntervalOutsideDataRange(
+                EFFECT_SIZE_FACET.to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_buckets_on_missing_chromosomes_are_dropped() {
+        let data = SyntheticCoverageDataBuilder::new()
+            .seed(11)
+            .chromosomes(3)
+            .buckets_per_chromosome(50)
+            .features(500)
+            .observations(5_000)
+            .build();
+        let original = filter_coverage_data(&Filter::new(), &data, None).unwrap();
+
+        // Chromosomes 0 and 1 become 5 and 9, and chromosome 2 isn't in the chromosome list
+        // anymore, though features are still on it
+        let mut subset = data.clone();
+        let new_index = |chrom: u8| match chrom {
+            0 => 5,
+            1 => 9,
+            chrom => chrom,
+        };
+        subset.chromosomes.retain(|c| c.index != 2);
+        for chromosome in &mut subset.chromosomes {
+            chromosome.index = new_index(chromosome.index);
+        }
+        for bucket in subset.feature_buckets.values_mut() {
+            bucket.chrom = new_index(bucket.chrom);
+        }
+
+        let result = filter_coverage_data(&Filter::new(), &subset, None).unwrap();
+        let intervals = |chromosome: &FilteredChromosome| -> Vec<(u64, usize)> {
+            chromosome
+                .source_intervals
+                .iter()
+                .chain(chromosome.target_intervals.iter())
+                .map(|bucket| (bucket.start, bucket.count))
+                .collect()
+        };
+        assert_eq!(result.chromosomes.len(), 2);
+        for (chromosome, original_chromosome) in
+            result.chromosomes.iter().zip(&original.chromosomes)
+        {
+            assert_eq!(chromosome.index, new_index(original_chromosome.index));
+            assert_eq!(intervals(chromosome), intervals(original_chromosome));
+        }
+        assert_eq!(
+            result.dropped_buckets,
+            intervals(&original.chromosomes[2]).len() as u64
+        );
+        assert!(result.dropped_buckets > 0);
+        assert_eq!(original.dropped_buckets, 0);
+    }
+
+    #[test]
+    fn test_sliding_windows_at_bucket_boundary() {
+        // A hotspot straddling the boundary between buckets 9 and 10 of chromosome 0, with
+        // 10 features on each side
+        let mut data = coverage_data(18);
+        data.significant_observations.truncate(20);
+        data.nonsignificant_observations.clear();
+        for (i, observation) in data.significant_observations.iter_mut().enumerate() {
+            observation.source_id = 10_000 + i as DbID;
+            observation.target_id = None;
+            data.feature_buckets.insert(
+                observation.source_id,
+                BucketLoc {
+                    chrom: 0,
+                    idx: 9 + (i % 2) as u32,
+                },
+            );
+        }
+        let profile = |data: &FilteredData| -> Vec<(u64, usize)> {
+            data.chromosomes[0]
+                .source_intervals
+                .iter()
+                .map(|bucket| (bucket.start, bucket.count))
+                .collect()
+        };
+
+        let buckets = filter_coverage_data(&Filter::new(), &data, None).unwrap();
+        assert_eq!(profile(&buckets), vec![(9_001, 10), (10_001, 10)]);
+
+        // Windows 37 through 39 (starting at 9250, 9500, and 9750) cover both sides of the
+        // boundary
+        let windows = filter_coverage_data(&Filter::new().window_step(250), &data, None).unwrap();
+        let expected: Vec<(u64, usize)> = (33..=43)
+            .map(|window: u64| {
+                let count = if (37..=39).contains(&window) { 20 } else { 10 };
+                (window * 250 + 1, count)
+            })
+            .collect();
+        assert_eq!(profile(&windows), expected);
+        assert_eq!(windows.window_step, Some(250));
+        assert_eq!(windows.reo_count, buckets.reo_count);
+
+        // A step equal to the bucket size is the same as no windows
+        let same = filter_coverage_data(&Filter::new().window_step(1_000), &data, None).unwrap();
+        assert_eq!(profile(&same), profile(&buckets));
+
+        for window_step in [0, 300] {
+            assert_eq!(
+                filter_coverage_data(&Filter::new().window_step(window_step), &data, None)
+                    .unwrap_err(),
+                FilterError::InvalidWindowStep {
+                    bucket_size: 1_000,
+                    window_step
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_min_pair_support() {
+        // Pairs with 3, 2, and 1 observations, with the sources in buckets 1, 2, and 3 of
+        // chromosome 0 and the targets in buckets 5, 6, and 7 of chromosome 1, and 2
+        // observations without targets in bucket 0 of chromosome 2
+        let mut data = coverage_data(19);
+        data.significant_observations.truncate(8);
+        data.nonsignificant_observations.clear();
+        let pairs = [(0, 3), (1, 2), (2, 1)]
+            .into_iter()
+            .flat_map(|(pair, count)| std::iter::repeat_n(Some(pair), count))
+            .chain([None, None]);
+        for (observation, pair) in data.significant_observations.iter_mut().zip(pairs) {
+            match pair {
+                Some(pair) => {
+                    observation.source_id = 10_000 + pair;
+                    observation.target_id = Some(20_000 + pair);
+                }
+                None => {
+                    observation.source_id = 30_000;
+                    observation.target_id = None;
+                }
+            }
+        }
+        for pair in 0..3 {
+            data.feature_buckets.insert(
+                10_000 + pair,
+                BucketLoc {
+                    chrom: 0,
+                    idx: 1 + pair as u32,
+                },
+            );
+            data.feature_buckets.insert(
+                20_000 + pair,
+                BucketLoc {
+                    chrom: 1,
+                    idx: 5 + pair as u32,
+                },
+            );
+        }
+        data.feature_buckets
+            .insert(30_000, BucketLoc { chrom: 2, idx: 0 });
+
+        let bucket_starts = |intervals: &[FilteredBucket]| -> Vec<u64> {
+            intervals.iter().map(|bucket| bucket.start).collect()
+        };
+        for (min_support, sources, targets) in [
+            (1, vec![1_001, 2_001, 3_001], vec![5_001, 6_001, 7_001]),
+            (2, vec![1_001, 2_001], vec![5_001, 6_001]),
+            (3, vec![1_001], vec![5_001]),
+            (4, vec![], vec![]),
+        ] {
+            for excludes_unpaired in [false, true] {
+                let filters = Filter::new()
+                    .min_pair_support(min_support)
+                    .pair_support_excludes_unpaired(excludes_unpaired);
+                let result = filter_coverage_data(&filters, &data, None).unwrap();
+                let chromosomes = &result.chromosomes;
+                assert_eq!(bucket_starts(&chromosomes[0].source_intervals), sources);
+                assert_eq!(bucket_starts(&chromosomes[1].target_intervals), targets);
+                assert_eq!(
+                    chromosomes[2].source_intervals.len(),
+                    if excludes_unpaired { 0 } else { 1 }
+                );
+
+                // Pruned pairs aren't associated with anything
+                for bucket in &chromosomes[0].source_intervals {
+                    let idx = (bucket.start / 1_000) as u32;
+                    assert_eq!(bucket.associated_buckets, vec![1, idx + 4]);
+                }
+                for bucket in &chromosomes[1].target_intervals {
+                    let idx = (bucket.start / 1_000) as u32;
+                    assert_eq!(bucket.associated_buckets, vec![0, idx - 4]);
+                }
+            }
+        }
+    }
+}
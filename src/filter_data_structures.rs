@@ -6,6 +6,7 @@ use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
 use serde::ser::{SerializeStruct, Serializer};
 use serde::{Deserialize, Serialize};
 
+use crate::error::FilterError;
 use cov_viz_ds::{BucketLoc, ChromosomeData, CoverageData, DbID};
 
 // When filtering this is the smallest we let a significance value be. Sometimes
@@ -14,31 +15,312 @@ use cov_viz_ds::{BucketLoc, ChromosomeData, CoverageData, DbID};
 // Don't be afraid to change it if another number becomes more "resonable sounding".
 pub const MIN_SIG: f64 = 1e-100;
 
-#[derive(Debug)]
+fn without_chr_prefix(name: &str) -> &str {
+    match name.get(..3) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("chr") => &name[3..],
+        _ => name,
+    }
+}
+
+// Filters can be read from JSON (e.g., by the C bindings). Every field is optional and
+// defaults to its value in Filter::new(), e.g.
+// {"chrom": 3, "categorical_facets": [1, 2], "numeric_intervals": {"effect": [-1, 1], "sig": [2, 10]}}
+//
+// Options are added often, so outside of this crate filters are made with Filter::new() and
+// the builder methods (or by setting fields), e.g. Filter::new().chrom_name("chr17").dedup_reos(true)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
 pub struct Filter {
     pub chrom: Option<u8>,
+    // The name of the chromosome to filter on, for when the index isn't known. If `chrom` is
+    // also set they must refer to the same chromosome.
+    pub chrom_name: Option<String>,
+    // When set, a leading "chr" is optional and case-insensitive when matching `chrom_name`,
+    // so "17", "chr17", and "CHR17" all match "chr17".
+    pub chrom_name_prefix_optional: bool,
     pub categorical_facets: FxHashSet<DbID>,
     pub numeric_intervals: Option<FilterIntervals>,
+    // Significance values (-log10) above this are treated as if they were this value. If not
+    // set, -log10(MIN_SIG) is used.
+    pub sig_ceiling: Option<f64>,
+    // Observations with a significance (-log10, after the ceiling is applied) above this are
+    // counted in each bucket's over_threshold_count
+    pub sig_threshold: Option<f64>,
+    // Only bucket one observation per REO, for data sets that contain the same REO more than
+    // once (e.g., once per feature pairing). The observation with the smallest
+    // (source id, target id) is the one that's used.
+    pub dedup_reos: bool,
+    // Smooths the output: instead of one bucket per `bucket_size` bases, there's a
+    // `bucket_size`-wide window starting every `window_step` bases. The step must evenly
+    // divide the bucket size.
+    pub window_step: Option<u32>,
+    // Only bucket observations whose (source, target) pair is supported by at least this many
+    // observations that pass the rest of the filter
+    pub min_pair_support: Option<u32>,
+    // Whether min_pair_support drops observations without a target. Otherwise they're exempt.
+    pub pair_support_excludes_unpaired: bool,
+    // Trade accuracy for speed, e.g., for the first genome-wide view. The results are
+    // deterministic, but approximate.
+    pub preview: Option<PreviewOptions>,
+    // Which sides of the interactions to build buckets for. When only one side is built, the
+    // other side's intervals and feature set (`targets` or `sources`) are left empty and the
+    // numeric intervals only cover the side that was built.
+    pub output_sides: OutputSides,
+    // Leave chromosomes without any source or target intervals out of the results. Their
+    // names are listed in FilteredData.omitted_empty instead.
+    pub omit_empty_chromosomes: bool,
+    // How observations are checked against the included features, when there are any
+    pub feature_match_mode: FeatureMatchMode,
+    // Limits on how much of the results are returned, for very dense data. Only the returned
+    // buckets are cut down; the counts, numeric intervals, sources, and targets still cover
+    // everything that passed the filter.
+    //
+    // Buckets with more associated buckets than this keep the first ones, in order of
+    // chromosome index and bucket index, and are marked with associated_buckets_truncated.
+    pub max_associated_buckets_per_bucket: Option<usize>,
+    // Chromosomes with more source (or target) intervals than this keep the most significant
+    // ones, and are marked as truncated. Ties are broken by position.
+    pub max_buckets_per_chromosome: Option<usize>,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::new()
+    }
 }
 
 impl Filter {
     pub fn new() -> Self {
         Filter {
             chrom: None,
+            chrom_name: None,
+            chrom_name_prefix_optional: true,
             categorical_facets: FxHashSet::default(),
             numeric_intervals: None,
+            sig_ceiling: None,
+            sig_threshold: None,
+            dedup_reos: false,
+            window_step: None,
+            min_pair_support: None,
+            pair_support_excludes_unpaired: false,
+            preview: None,
+            output_sides: OutputSides::Both,
+            omit_empty_chromosomes: false,
+            feature_match_mode: FeatureMatchMode::TargetOrUnpairedSource,
+            max_associated_buckets_per_bucket: None,
+            max_buckets_per_chromosome: None,
         }
     }
 
+    pub fn chrom(mut self, chrom: u8) -> Self {
+        self.chrom = Some(chrom);
+        self
+    }
+
+    pub fn chrom_name(mut self, chrom_name: impl Into<String>) -> Self {
+        self.chrom_name = Some(chrom_name.into());
+        self
+    }
+
+    pub fn chrom_name_prefix_optional(mut self, prefix_optional: bool) -> Self {
+        self.chrom_name_prefix_optional = prefix_optional;
+        self
+    }
+
+    pub fn categorical_facets<I>(mut self, facet_value_ids: I) -> Self
+    where
+        I: IntoIterator<Item = DbID>,
+    {
+        self.categorical_facets = facet_value_ids.into_iter().collect();
+        self
+    }
+
+    pub fn numeric_intervals(mut self, numeric_intervals: FilterIntervals) -> Self {
+        self.numeric_intervals = Some(numeric_intervals);
+        self
+    }
+
+    pub fn sig_ceiling(mut self, sig_ceiling: f64) -> Self {
+        self.sig_ceiling = Some(sig_ceiling);
+        self
+    }
+
+    pub fn sig_threshold(mut self, sig_threshold: f64) -> Self {
+        self.sig_threshold = Some(sig_threshold);
+        self
+    }
+
+    pub fn dedup_reos(mut self, dedup_reos: bool) -> Self {
+        self.dedup_reos = dedup_reos;
+        self
+    }
+
+    pub fn window_step(mut self, window_step: u32) -> Self {
+        self.window_step = Some(window_step);
+        self
+    }
+
+    pub fn min_pair_support(mut self, min_pair_support: u32) -> Self {
+        self.min_pair_support = Some(min_pair_support);
+        self
+    }
+
+    pub fn pair_support_excludes_unpaired(mut self, excludes_unpaired: bool) -> Self {
+        self.pair_support_excludes_unpaired = excludes_unpaired;
+        self
+    }
+
+    pub fn preview(mut self, preview: PreviewOptions) -> Self {
+        self.preview = Some(preview);
+        self
+    }
+
+    pub fn output_sides(mut self, output_sides: OutputSides) -> Self {
+        self.output_sides = output_sides;
+        self
+    }
+
+    pub fn omit_empty_chromosomes(mut self, omit_empty_chromosomes: bool) -> Self {
+        self.omit_empty_chromosomes = omit_empty_chromosomes;
+        self
+    }
+
+    pub fn feature_match_mode(mut self, feature_match_mode: FeatureMatchMode) -> Self {
+        self.feature_match_mode = feature_match_mode;
+        self
+    }
+
+    pub fn max_associated_buckets_per_bucket(mut self, max_associated_buckets: usize) -> Self {
+        self.max_associated_buckets_per_bucket = Some(max_associated_buckets);
+        self
+    }
+
+    pub fn max_buckets_per_chromosome(mut self, max_buckets: usize) -> Self {
+        self.max_buckets_per_chromosome = Some(max_buckets);
+        self
+    }
+
     pub fn __str__(&self) -> String {
         format!("Categorical Effects: {:?}", self.categorical_facets)
     }
+
+    fn chrom_name_matches(&self, chrom_name: &str, chromosome: &str) -> bool {
+        if self.chrom_name_prefix_optional {
+            without_chr_prefix(chrom_name) == without_chr_prefix(chromosome)
+        } else {
+            chrom_name == chromosome
+        }
+    }
+
+    // The index of the chromosome the filter is restricted to, if any, looking up `chrom_name`
+    // in `chromosomes`.
+    pub fn chromosome_index(
+        &self,
+        chromosomes: &[ChromosomeData],
+    ) -> Result<Option<u8>, FilterError> {
+        let chrom_name = match &self.chrom_name {
+            Some(chrom_name) => chrom_name,
+            None => return Ok(self.chrom),
+        };
+
+        let index = chromosomes
+            .iter()
+            .find(|c| self.chrom_name_matches(chrom_name, &c.chrom))
+            .map(|c| c.index)
+            .ok_or_else(|| FilterError::UnknownChromosome(chrom_name.clone()))?;
+
+        match self.chrom {
+            Some(chrom) if chrom != index => Err(FilterError::ConflictingChromosome {
+                chrom,
+                chrom_name: chrom_name.clone(),
+            }),
+            _ => Ok(Some(index)),
+        }
+    }
+
+    // The window step, if any, after checking that it works with `bucket_size`
+    pub fn checked_window_step(&self, bucket_size: u32) -> Result<Option<u32>, FilterError> {
+        match self.window_step {
+            Some(window_step) if window_step == 0 || bucket_size % window_step != 0 => {
+                Err(FilterError::InvalidWindowStep {
+                    bucket_size,
+                    window_step,
+                })
+            }
+            window_step => Ok(window_step),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PreviewOptions {
+    // Each bucket keeps at most this many features (the ones with the smallest ids). The
+    // counts of buckets with more features are lower bounds.
+    pub feature_cap: Option<u64>,
+    // Only use this fraction (0 to 1) of the REOs, picked by a hash of the REO id. Counts are
+    // scaled up to make up for the REOs that were left out.
+    pub sample_rate: Option<f64>,
+}
+
+impl PreviewOptions {
+    // Whether these options change the results
+    pub fn is_approximate(&self) -> bool {
+        self.feature_cap.is_some() || self.sample_rate.is_some_and(|rate| rate < 1.0)
+    }
+}
+
+// For views that only show one track, e.g., a density plot of the tested elements
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputSides {
+    #[default]
+    Both,
+    SourcesOnly,
+    TargetsOnly,
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+impl OutputSides {
+    pub fn includes_sources(self) -> bool {
+        self != OutputSides::TargetsOnly
+    }
+
+    pub fn includes_targets(self) -> bool {
+        self != OutputSides::SourcesOnly
+    }
+}
+
+// Which of an observation's features have to be in the included features
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeatureMatchMode {
+    // The target has to be an included target. Observations without a target (e.g., from
+    // experiments that only test elements) are matched by their source instead, which has to
+    // be an included source.
+    #[default]
+    TargetOrUnpairedSource,
+    // The target has to be an included target, so observations without one never match
+    TargetOnly,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct FilterIntervals {
     pub effect: (f32, f32),
     pub sig: (f64, f64),
+    // In filter results, the ranges of the negative and positive effect sizes that were
+    // observed, or None if there weren't any of that sign. Effect sizes of exactly 0 are in
+    // neither. These are ignored when filtering.
+    #[serde(default)]
+    pub effect_negative: Option<(f32, f32)>,
+    #[serde(default)]
+    pub effect_positive: Option<(f32, f32)>,
+}
+
+impl Default for FilterIntervals {
+    fn default() -> Self {
+        FilterIntervals::new()
+    }
 }
 
 impl FilterIntervals {
@@ -46,6 +328,8 @@ impl FilterIntervals {
         FilterIntervals {
             effect: (f32::NEG_INFINITY, f32::INFINITY),
             sig: (f64::NEG_INFINITY, f64::INFINITY),
+            effect_negative: None,
+            effect_positive: None,
         }
     }
 
@@ -57,13 +341,94 @@ impl FilterIntervals {
     }
 }
 
+// The rule for a bucket's max_abs_effect: the signed effect size with the largest absolute
+// value. On ties the positive one is used. Both filtering and merging use this so merged
+// results match filtering the combined data.
+pub(crate) fn max_abs_effect(effect1: f32, effect2: f32) -> f32 {
+    if effect1.abs() > effect2.abs() || (effect1.abs() == effect2.abs() && effect1 > effect2) {
+        effect1
+    } else {
+        effect2
+    }
+}
+
+// Associated buckets as a flat list of (chromosome index, bucket index) pairs, sorted and
+// without duplicates, so the list doesn't depend on the order the buckets were found in
+pub(crate) fn sorted_associated_buckets<I>(locs: I) -> Vec<u32>
+where
+    I: IntoIterator<Item = (u32, u32)>,
+{
+    let mut locs: Vec<(u32, u32)> = locs.into_iter().collect();
+    locs.sort_unstable();
+    locs.dedup();
+    locs.into_iter()
+        .flat_map(|(chrom, idx)| [chrom, idx])
+        .collect()
+}
+
+// The sorted union of two buckets' associated buckets
+pub(crate) fn merge_associated_buckets(buckets1: &[u32], buckets2: &[u32]) -> Vec<u32> {
+    sorted_associated_buckets(
+        buckets1
+            .chunks_exact(2)
+            .chain(buckets2.chunks_exact(2))
+            .map(|loc| (loc[0], loc[1])),
+    )
+}
+
+// The sorted union of two buckets' annotations
+pub(crate) fn merge_annotations(annotations1: &[u32], annotations2: &[u32]) -> Vec<u32> {
+    let mut annotations = [annotations1, annotations2].concat();
+    annotations.sort_unstable();
+    annotations.dedup();
+    annotations
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct FilteredBucket {
-    pub start: u32,
+    pub start: u64,
     pub count: usize,
+    // (chromosome index, bucket index) pairs, flattened and sorted
     pub associated_buckets: Vec<u32>,
     pub max_log10_sig: f64,  // Lower significance values are more significant
     pub max_abs_effect: f32, // largest absolute effect size
+    // Set when the bucket had more features than a preview's feature cap
+    #[serde(default)]
+    pub count_is_lower_bound: bool,
+    // Sorted indexes into FilteredData.annotation_names of the regions the bucket overlaps
+    #[serde(default)]
+    pub annotations: Vec<u32>,
+    // Set when associated buckets were left out because of
+    // Filter.max_associated_buckets_per_bucket, or could be missing because a preview's
+    // feature cap left out some of the bucket's associated features
+    #[serde(default)]
+    pub associated_buckets_truncated: bool,
+    // The sum of the significance values (-log10, after the ceiling is applied) of the
+    // bucket's observations
+    #[serde(default)]
+    pub sig_sum: f64,
+    // The number of the bucket's observations with a significance above
+    // Filter.sig_threshold. Always 0 if there's no threshold.
+    #[serde(default)]
+    pub over_threshold_count: u64,
+}
+
+// Significance sums are added up in fixed point, with this many steps per unit, so they
+// don't depend on the order observations (or buckets) are added in. Sums up to 2^33 are
+// exact in a f64.
+const SIG_SUM_SCALE: f64 = (1u64 << 20) as f64;
+
+pub(crate) fn sig_sum_to_fixed(sig: f64) -> i128 {
+    (sig * SIG_SUM_SCALE).round() as i128
+}
+
+pub(crate) fn sig_sum_from_fixed(fixed: i128) -> f64 {
+    fixed as f64 / SIG_SUM_SCALE
+}
+
+// The sum of two buckets' sig_sums
+pub(crate) fn add_sig_sums(sig_sum1: f64, sig_sum2: f64) -> f64 {
+    sig_sum_from_fixed(sig_sum_to_fixed(sig_sum1).saturating_add(sig_sum_to_fixed(sig_sum2)))
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -73,20 +438,97 @@ pub struct FilteredChromosome {
     pub bucket_size: u32,
     pub target_intervals: Vec<FilteredBucket>,
     pub source_intervals: Vec<FilteredBucket>,
+    // Set when intervals were left out because of Filter.max_buckets_per_chromosome
+    #[serde(default)]
+    pub truncated: bool,
+    // How many buckets (or windows) there are from the start of the chromosome through the
+    // last bucket with any of the data set's features, i.e., one more than the highest
+    // index an interval could have, where an interval's index is (start - 1) divided by the
+    // window step or bucket size. Clients can use it to fill in the empty buckets. Coverage
+    // data doesn't include chromosome lengths, so buckets past the last feature aren't
+    // counted.
+    #[serde(default)]
+    pub bucket_count: u32,
+}
+
+// The bucket_count of each chromosome that has any features, for buckets located by
+// `feature_buckets`. Each bucket holds `intervals_per_bucket` intervals, e.g., for
+// sliding windows.
+pub(crate) fn chromosome_bucket_counts(
+    feature_buckets: &FxHashMap<DbID, BucketLoc>,
+    intervals_per_bucket: u32,
+) -> FxHashMap<u8, u32> {
+    let mut last_buckets: FxHashMap<u8, u32> = FxHashMap::default();
+    for loc in feature_buckets.values() {
+        let last_bucket = last_buckets.entry(loc.chrom).or_insert(loc.idx);
+        *last_bucket = (*last_bucket).max(loc.idx);
+    }
+    last_buckets
+        .into_iter()
+        .map(|(chrom, last_bucket)| {
+            let count = (last_bucket as u64 + 1) * intervals_per_bucket as u64;
+            (
+                chrom,
+                u32::try_from(count).expect("bucket count overflowed"),
+            )
+        })
+        .collect()
+}
+
+// Parts of a filter that can't do anything with the data it's used on. They aren't errors,
+// since the results are still what the filter asks for, but they're usually mistakes, e.g.,
+// a filter made for another experiment.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterWarning {
+    // The number of categorical facet value ids that aren't values of any of the data's facets
+    UnknownCategoricalFacetIds(usize),
+    // Filter.chrom isn't the index of any of the data's chromosomes, so there are no results
+    ChromosomeNotFound(u8),
+    // The numeric interval for a facet (by name) doesn't overlap the range of the data's values
+    NumericIntervalOutsideDataRange(String),
+    // None of the observations can match the included features
+    IncludedFeaturesEmpty,
 }
 
 #[derive(Clone, Debug)]
 pub struct FilteredData {
+    // Always in order of their index
     pub chromosomes: Vec<FilteredChromosome>,
     pub bucket_size: u32,
     pub numeric_intervals: FilterIntervals,
     pub reo_count: u64,
+    // Observations that would have passed the filter, but have a NaN effect size or significance
+    pub dropped_invalid: u64,
+    // Buckets on chromosomes that aren't in the data set's chromosome list, so they can't be
+    // part of the output
+    pub dropped_buckets: u64,
+    // Set when the intervals are overlapping windows rather than buckets. Each window is
+    // `bucket_size` wide and they start every `window_step` bases.
+    pub window_step: Option<u32>,
+    // Set when the data comes from a preview filter, so the counts are approximate
+    pub approximate: bool,
+    // Set when buckets were removed without going back to the observations (see
+    // FilteredData::refilter), so the bucket counts, sources, and targets can include
+    // observations that no longer pass
+    pub counts_approximate: bool,
+    // The names of the regions buckets are annotated with (see annotate_filtered_data)
+    pub annotation_names: Vec<String>,
+    // The chromosomes that were left out because they had no intervals (see
+    // Filter.omit_empty_chromosomes), in order of their index, or for merged data, in the
+    // order of the merge's chromosome list. Chromosomes that weren't part of the request
+    // (e.g., because the filter was restricted to another chromosome) aren't listed.
+    pub omitted_empty: Vec<String>,
+    // Problems with the filter, in the order they were found. Merged data has each of its
+    // data sets' warnings, once.
+    pub warnings: Vec<FilterWarning>,
     pub sources: RoaringTreemap,
     pub targets: RoaringTreemap,
 }
 
 impl FilteredData {
     pub fn from(data: &CoverageData) -> Self {
+        let bucket_counts = chromosome_bucket_counts(&data.feature_buckets, 1);
         FilteredData {
             chromosomes: data
                 .chromosomes
@@ -97,11 +539,21 @@ impl FilteredData {
                     bucket_size: data.bucket_size,
                     target_intervals: Vec::new(),
                     source_intervals: Vec::new(),
+                    truncated: false,
+                    bucket_count: bucket_counts.get(&c.index).copied().unwrap_or(0),
                 })
                 .collect(),
             bucket_size: data.bucket_size,
             numeric_intervals: FilterIntervals::new(),
             reo_count: 0,
+            dropped_invalid: 0,
+            dropped_buckets: 0,
+            window_step: None,
+            approximate: false,
+            counts_approximate: false,
+            annotation_names: Vec::new(),
+            omitted_empty: Vec::new(),
+            warnings: Vec::new(),
             sources: RoaringTreemap::default(),
             targets: RoaringTreemap::default(),
         }
@@ -112,9 +564,23 @@ const FILTERED_DATA_CHROMOSOMES: &str = "chromosomes";
 const FILTERED_DATA_BUCKET_SIZE: &str = "bucket_Size";
 const FILTERED_DATA_NUMERIC_INTERVALS: &str = "numeric_intervals";
 const FILTERED_DATA_REO_COUNT: &str = "reo_count";
+const FILTERED_DATA_DROPPED_INVALID: &str = "dropped_invalid";
+const FILTERED_DATA_DROPPED_BUCKETS: &str = "dropped_buckets";
+const FILTERED_DATA_WINDOW_STEP: &str = "window_step";
+const FILTERED_DATA_APPROXIMATE: &str = "approximate";
+const FILTERED_DATA_COUNTS_APPROXIMATE: &str = "counts_approximate";
+const FILTERED_DATA_ANNOTATION_NAMES: &str = "annotation_names";
+const FILTERED_DATA_OMITTED_EMPTY: &str = "omitted_empty";
+const FILTERED_DATA_WARNINGS: &str = "warnings";
 const FILTERED_DATA_SOURCES: &str = "sources";
 const FILTERED_DATA_TARGETS: &str = "targets";
 
+// The serialized form only depends on the filter and the data, not on how the filtering was
+// split up between threads: chromosomes are in order of their index, intervals are in order of
+// their start, and associated buckets and annotations are sorted. The floating point values
+// are minimums and maximums, which don't depend on the order observations are combined in
+// (-0.0 is always stored as 0.0), except for the significance sums, which are added up in
+// fixed point.
 impl Serialize for FilteredData {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -125,6 +591,14 @@ impl Serialize for FilteredData {
         state.serialize_field(FILTERED_DATA_BUCKET_SIZE, &self.bucket_size)?;
         state.serialize_field(FILTERED_DATA_NUMERIC_INTERVALS, &self.numeric_intervals)?;
         state.serialize_field(FILTERED_DATA_REO_COUNT, &self.reo_count)?;
+        state.serialize_field(FILTERED_DATA_DROPPED_INVALID, &self.dropped_invalid)?;
+        state.serialize_field(FILTERED_DATA_DROPPED_BUCKETS, &self.dropped_buckets)?;
+        state.serialize_field(FILTERED_DATA_WINDOW_STEP, &self.window_step)?;
+        state.serialize_field(FILTERED_DATA_APPROXIMATE, &self.approximate)?;
+        state.serialize_field(FILTERED_DATA_COUNTS_APPROXIMATE, &self.counts_approximate)?;
+        state.serialize_field(FILTERED_DATA_ANNOTATION_NAMES, &self.annotation_names)?;
+        state.serialize_field(FILTERED_DATA_OMITTED_EMPTY, &self.omitted_empty)?;
+        state.serialize_field(FILTERED_DATA_WARNINGS, &self.warnings)?;
         let mut source_data = vec![];
         let _ = self.sources.serialize_into(&mut source_data);
         state.serialize_field(FILTERED_DATA_SOURCES, &source_data)?;
@@ -142,12 +616,22 @@ impl<'de> Deserialize<'de> for FilteredData {
         D: Deserializer<'de>,
     {
         #[derive(Deserialize)]
-        #[serde(field_identifier, rename_all = "lowercase")]
+        #[serde(field_identifier, rename_all = "snake_case")]
         enum Field {
             Chromosomes,
-            Bucket_Size,
-            Numeric_Intervals,
-            Reo_Count,
+            // Serialized as "bucket_Size"
+            #[serde(alias = "bucket_Size")]
+            BucketSize,
+            NumericIntervals,
+            ReoCount,
+            DroppedInvalid,
+            DroppedBuckets,
+            WindowStep,
+            Approximate,
+            CountsApproximate,
+            AnnotationNames,
+            OmittedEmpty,
+            Warnings,
             Sources,
             Targets,
         }
@@ -177,20 +661,54 @@ impl<'de> Deserialize<'de> for FilteredData {
                 let reo_count = seq
                     .next_element()?
                     .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let dropped_invalid = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let dropped_buckets = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let window_step = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let approximate = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let counts_approximate = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let annotation_names = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let omitted_empty = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let warnings = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
                 let source_data: Vec<u8> = seq
                     .next_element()?
                     .ok_or_else(|| de::Error::invalid_length(1, &self))?;
                 let target_data: Vec<u8> = seq
                     .next_element()?
                     .ok_or_else(|| de::Error::invalid_length(1, &self))?;
-                let sources = RoaringTreemap::deserialize_from(&source_data[..]).unwrap();
-                let targets = RoaringTreemap::deserialize_from(&target_data[..]).unwrap();
+                let sources = RoaringTreemap::deserialize_from(&source_data[..])
+                    .map_err(de::Error::custom)?;
+                let targets = RoaringTreemap::deserialize_from(&target_data[..])
+                    .map_err(de::Error::custom)?;
 
                 Ok(FilteredData {
                     chromosomes,
                     bucket_size,
                     numeric_intervals,
                     reo_count,
+                    dropped_invalid,
+                    dropped_buckets,
+                    window_step,
+                    approximate,
+                    counts_approximate,
+                    annotation_names,
+                    omitted_empty,
+                    warnings,
                     sources,
                     targets,
                 })
@@ -204,6 +722,14 @@ impl<'de> Deserialize<'de> for FilteredData {
                 let mut bucket_size = None;
                 let mut numeric_intervals = None;
                 let mut reo_count = None;
+                let mut dropped_invalid = None;
+                let mut dropped_buckets = None;
+                let mut window_step = None;
+                let mut approximate = None;
+                let mut counts_approximate = None;
+                let mut annotation_names = None;
+                let mut omitted_empty = None;
+                let mut warnings = None;
                 let mut source_data: Option<Vec<u8>> = None;
                 let mut target_data: Option<Vec<u8>> = None;
                 while let Some(key) = map.next_key()? {
@@ -214,13 +740,13 @@ impl<'de> Deserialize<'de> for FilteredData {
                             }
                             chromosomes = Some(map.next_value()?);
                         }
-                        Field::Bucket_Size => {
+                        Field::BucketSize => {
                             if bucket_size.is_some() {
                                 return Err(de::Error::duplicate_field(FILTERED_DATA_BUCKET_SIZE));
                             }
                             bucket_size = Some(map.next_value()?);
                         }
-                        Field::Numeric_Intervals => {
+                        Field::NumericIntervals => {
                             if numeric_intervals.is_some() {
                                 return Err(de::Error::duplicate_field(
                                     FILTERED_DATA_NUMERIC_INTERVALS,
@@ -228,12 +754,70 @@ impl<'de> Deserialize<'de> for FilteredData {
                             }
                             numeric_intervals = Some(map.next_value()?);
                         }
-                        Field::Reo_Count => {
+                        Field::ReoCount => {
                             if reo_count.is_some() {
                                 return Err(de::Error::duplicate_field(FILTERED_DATA_REO_COUNT));
                             }
                             reo_count = Some(map.next_value()?);
                         }
+                        Field::DroppedInvalid => {
+                            if dropped_invalid.is_some() {
+                                return Err(de::Error::duplicate_field(
+                                    FILTERED_DATA_DROPPED_INVALID,
+                                ));
+                            }
+                            dropped_invalid = Some(map.next_value()?);
+                        }
+                        Field::DroppedBuckets => {
+                            if dropped_buckets.is_some() {
+                                return Err(de::Error::duplicate_field(
+                                    FILTERED_DATA_DROPPED_BUCKETS,
+                                ));
+                            }
+                            dropped_buckets = Some(map.next_value()?);
+                        }
+                        Field::WindowStep => {
+                            if window_step.is_some() {
+                                return Err(de::Error::duplicate_field(FILTERED_DATA_WINDOW_STEP));
+                            }
+                            window_step = Some(map.next_value()?);
+                        }
+                        Field::Approximate => {
+                            if approximate.is_some() {
+                                return Err(de::Error::duplicate_field(FILTERED_DATA_APPROXIMATE));
+                            }
+                            approximate = Some(map.next_value()?);
+                        }
+                        Field::CountsApproximate => {
+                            if counts_approximate.is_some() {
+                                return Err(de::Error::duplicate_field(
+                                    FILTERED_DATA_COUNTS_APPROXIMATE,
+                                ));
+                            }
+                            counts_approximate = Some(map.next_value()?);
+                        }
+                        Field::AnnotationNames => {
+                            if annotation_names.is_some() {
+                                return Err(de::Error::duplicate_field(
+                                    FILTERED_DATA_ANNOTATION_NAMES,
+                                ));
+                            }
+                            annotation_names = Some(map.next_value()?);
+                        }
+                        Field::OmittedEmpty => {
+                            if omitted_empty.is_some() {
+                                return Err(de::Error::duplicate_field(
+                                    FILTERED_DATA_OMITTED_EMPTY,
+                                ));
+                            }
+                            omitted_empty = Some(map.next_value()?);
+                        }
+                        Field::Warnings => {
+                            if warnings.is_some() {
+                                return Err(de::Error::duplicate_field(FILTERED_DATA_WARNINGS));
+                            }
+                            warnings = Some(map.next_value()?);
+                        }
                         Field::Sources => {
                             if source_data.is_some() {
                                 return Err(de::Error::duplicate_field(FILTERED_DATA_SOURCES));
@@ -256,29 +840,56 @@ impl<'de> Deserialize<'de> for FilteredData {
                     .ok_or_else(|| de::Error::missing_field(FILTERED_DATA_NUMERIC_INTERVALS))?;
                 let reo_count =
                     reo_count.ok_or_else(|| de::Error::missing_field(FILTERED_DATA_REO_COUNT))?;
+                // Data serialized before these fields existed doesn't have them
+                let dropped_invalid = dropped_invalid.unwrap_or(0);
+                let dropped_buckets = dropped_buckets.unwrap_or(0);
+                let window_step = window_step.unwrap_or(None);
+                let approximate = approximate.unwrap_or(false);
+                let counts_approximate = counts_approximate.unwrap_or(false);
+                let annotation_names = annotation_names.unwrap_or_default();
+                let omitted_empty = omitted_empty.unwrap_or_default();
+                let warnings = warnings.unwrap_or_default();
                 let source_data =
                     source_data.ok_or_else(|| de::Error::missing_field(FILTERED_DATA_SOURCES))?;
                 let target_data =
                     target_data.ok_or_else(|| de::Error::missing_field(FILTERED_DATA_TARGETS))?;
-                let sources = RoaringTreemap::deserialize_from(&source_data[..]).unwrap();
-                let targets = RoaringTreemap::deserialize_from(&target_data[..]).unwrap();
+                let sources = RoaringTreemap::deserialize_from(&source_data[..])
+                    .map_err(de::Error::custom)?;
+                let targets = RoaringTreemap::deserialize_from(&target_data[..])
+                    .map_err(de::Error::custom)?;
 
                 Ok(FilteredData {
                     chromosomes,
                     bucket_size,
                     numeric_intervals,
                     reo_count,
+                    dropped_invalid,
+                    dropped_buckets,
+                    window_step,
+                    approximate,
+                    counts_approximate,
+                    annotation_names,
+                    omitted_empty,
+                    warnings,
                     sources,
                     targets,
                 })
             }
         }
 
-        const FIELDS: &'static [&'static str] = &[
+        const FIELDS: &[&str] = &[
             FILTERED_DATA_CHROMOSOMES,
             FILTERED_DATA_BUCKET_SIZE,
             FILTERED_DATA_NUMERIC_INTERVALS,
             FILTERED_DATA_REO_COUNT,
+            FILTERED_DATA_DROPPED_INVALID,
+            FILTERED_DATA_DROPPED_BUCKETS,
+            FILTERED_DATA_WINDOW_STEP,
+            FILTERED_DATA_APPROXIMATE,
+            FILTERED_DATA_COUNTS_APPROXIMATE,
+            FILTERED_DATA_ANNOTATION_NAMES,
+            FILTERED_DATA_OMITTED_EMPTY,
+            FILTERED_DATA_WARNINGS,
             FILTERED_DATA_SOURCES,
             FILTERED_DATA_TARGETS,
         ];
@@ -302,11 +913,9 @@ impl BucketList {
     }
 
     pub fn insert(&mut self, chrom: u8, bucket: usize) {
-        let x = self.buckets.get_mut(&chrom);
-        match x {
-            Some(v) => v[bucket] = 1,
-            None => (),
-        };
+        if let Some(v) = self.buckets.get_mut(&chrom) {
+            v[bucket] = 1;
+        }
     }
 
     pub fn insert_from<'a, I>(&mut self, from: I)
@@ -331,3 +940,178 @@ impl BucketList {
         new_list
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Replaces the serialized targets, which are last, with a treemap that claims to have
+    // bitmaps that aren't there
+    fn corrupt_targets(bytes: &mut [u8]) {
+        let len = bytes.len();
+        bytes[len - 8..].copy_from_slice(&5u64.to_le_bytes());
+    }
+
+    #[test]
+    fn test_invalid_feature_sets_are_errors() {
+        let data = FilteredData {
+            targets: RoaringTreemap::new(),
+            ..FilteredData::from(&crate::test_data::coverage_data(1))
+        };
+
+        let mut bytes = bincode::serialize(&data).unwrap();
+        assert!(bincode::deserialize::<FilteredData>(&bytes).is_ok());
+        corrupt_targets(&mut bytes);
+        assert!(bincode::deserialize::<FilteredData>(&bytes).is_err());
+
+        let mut json = serde_json::to_value(&data).unwrap();
+        let mut targets: Vec<u8> = serde_json::from_value(json["targets"].take()).unwrap();
+        corrupt_targets(&mut targets);
+        json["targets"] = serde_json::to_value(targets).unwrap();
+        assert!(serde_json::from_value::<FilteredData>(json).is_err());
+    }
+
+    fn chromosomes(names: &[&str]) -> Vec<ChromosomeData> {
+        names
+            .iter()
+            .enumerate()
+            .map(|(index, name)| ChromosomeData {
+                chrom: name.to_string(),
+                index: index as u8,
+                bucket_size: 1000,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_chromosome_names() {
+        let chromosomes = chromosomes(&["chr1", "chr17", "chrX", "scaffold_2"]);
+
+        assert_eq!(Filter::new().chromosome_index(&chromosomes), Ok(None));
+        assert_eq!(
+            Filter::new().chrom(2).chromosome_index(&chromosomes),
+            Ok(Some(2))
+        );
+        for name in ["chr17", "17", "CHR17", "Chr17"] {
+            assert_eq!(
+                Filter::new()
+                    .chrom_name(name)
+                    .chromosome_index(&chromosomes),
+                Ok(Some(1))
+            );
+        }
+        // Only the prefix is case-insensitive
+        assert_eq!(
+            Filter::new().chrom_name("x").chromosome_index(&chromosomes),
+            Err(FilterError::UnknownChromosome("x".to_string()))
+        );
+        assert_eq!(
+            Filter::new().chrom_name("X").chromosome_index(&chromosomes),
+            Ok(Some(2))
+        );
+        assert_eq!(
+            Filter::new()
+                .chrom_name("scaffold_2")
+                .chromosome_index(&chromosomes),
+            Ok(Some(3))
+        );
+
+        // Exact matches only
+        let exact = Filter::new().chrom_name_prefix_optional(false);
+        assert_eq!(
+            exact
+                .clone()
+                .chrom_name("chr17")
+                .chromosome_index(&chromosomes),
+            Ok(Some(1))
+        );
+        for name in ["17", "CHR17"] {
+            assert_eq!(
+                exact
+                    .clone()
+                    .chrom_name(name)
+                    .chromosome_index(&chromosomes),
+                Err(FilterError::UnknownChromosome(name.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_unknown_chromosome_name() {
+        let data = crate::test_data::coverage_data(1);
+        let filters = Filter::new().chrom_name("chr99");
+        assert_eq!(
+            crate::filter::filter_coverage_data(&filters, &data, None).unwrap_err(),
+            FilterError::UnknownChromosome("chr99".to_string())
+        );
+    }
+
+    #[test]
+    fn test_conflicting_chromosomes() {
+        let chromosomes = chromosomes(&["chr1", "chr2"]);
+        assert_eq!(
+            Filter::new()
+                .chrom(1)
+                .chrom_name("chr2")
+                .chromosome_index(&chromosomes),
+            Ok(Some(1))
+        );
+        assert_eq!(
+            Filter::new()
+                .chrom(0)
+                .chrom_name("chr2")
+                .chromosome_index(&chromosomes),
+            Err(FilterError::ConflictingChromosome {
+                chrom: 0,
+                chrom_name: "chr2".to_string()
+            })
+        );
+
+        let data = crate::test_data::coverage_data(1);
+        let chrom_name = data.chromosomes[1].chrom.clone();
+        let filters = Filter::new().chrom(0).chrom_name(chrom_name.clone());
+        assert_eq!(
+            crate::filter::filter_coverage_data(&filters, &data, None).unwrap_err(),
+            FilterError::ConflictingChromosome {
+                chrom: 0,
+                chrom_name
+            }
+        );
+    }
+
+    #[test]
+    fn test_chromosome_name_matches_index() {
+        let data = crate::test_data::coverage_data(1);
+        let by_index =
+            crate::filter::filter_coverage_data(&Filter::new().chrom(2), &data, None).unwrap();
+        let by_name = crate::filter::filter_coverage_data(
+            &Filter::new().chrom_name(data.chromosomes[2].chrom.clone()),
+            &data,
+            None,
+        )
+        .unwrap();
+        crate::test_data::assert_same_results(&by_index, &by_name);
+    }
+
+    #[test]
+    fn test_filter_json() {
+        let filters: Filter =
+            serde_json::from_str(r#"{"chrom_name": "chr3", "categorical_facets": [1, 2]}"#)
+                .unwrap();
+        assert_eq!(
+            filters,
+            Filter::new().chrom_name("chr3").categorical_facets([1, 2])
+        );
+    }
+
+    #[test]
+    fn test_max_abs_effect() {
+        assert_eq!(max_abs_effect(1.0, -2.0), -2.0);
+        assert_eq!(max_abs_effect(-2.0, 1.0), -2.0);
+        assert_eq!(max_abs_effect(3.0, -2.0), 3.0);
+        // Ties go to the positive effect, whichever order they're in
+        assert_eq!(max_abs_effect(-2.0, 2.0), 2.0);
+        assert_eq!(max_abs_effect(2.0, -2.0), 2.0);
+        assert_eq!(max_abs_effect(-2.0, -2.0), -2.0);
+    }
+}
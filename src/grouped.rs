@@ -0,0 +1,295 @@
+use rayon::prelude::*;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::error::FilterError;
+use crate::filter::{
+    bucketing_chunk_size, build_filtered_data, empty_chromosomes, has_invalid_values,
+    merge_bucket_results, BucketResults, ObservationFilter,
+};
+use crate::filter_data_structures::*;
+use cov_viz_ds::{CoverageData, DbID, ExperimentFeatureData, Facet, ObservationData};
+
+// The categorical facet to group filter results by
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GroupByFacet {
+    Id(DbID),
+    Name(String),
+}
+
+impl GroupByFacet {
+    fn find<'a>(&self, facets: &'a [Facet]) -> Result<&'a Facet, FilterError> {
+        facets
+            .iter()
+            .filter(|f| f.facet_type == "FacetType.CATEGORICAL" && f.values.is_some())
+            .find(|f| match self {
+                GroupByFacet::Id(id) => f.id == *id,
+                GroupByFacet::Name(name) => f.name == *name,
+            })
+            .ok_or_else(|| {
+                FilterError::UnknownFacet(match self {
+                    GroupByFacet::Id(id) => id.to_string(),
+                    GroupByFacet::Name(name) => name.clone(),
+                })
+            })
+    }
+}
+
+// The observation's value of the group-by facet
+fn group_value(
+    observation: &ObservationData,
+    group_values: &FxHashSet<DbID>,
+) -> Result<DbID, FilterError> {
+    let mut values = observation
+        .facet_value_ids
+        .iter()
+        .filter(|id| group_values.contains(id));
+    match (values.next(), values.count()) {
+        (Some(value), 0) => Ok(*value),
+        (value, rest) => Err(FilterError::UngroupableObservation {
+            reo_id: observation.reo_id,
+            values: value.map_or(0, |_| rest + 1),
+        }),
+    }
+}
+
+// The (group value, source id, target id) triples with at least `min_support` observations
+// that match `predicate`, i.e., the supported pairs of each group's filter
+fn supported_group_pairs<'a, I>(
+    predicate: &ObservationFilter,
+    observations: I,
+    group_values: &FxHashSet<DbID>,
+    min_support: u32,
+) -> Result<FxHashSet<(DbID, DbID, DbID)>, FilterError>
+where
+    I: ParallelIterator<Item = &'a ObservationData>,
+{
+    let counts = observations
+        .filter(|observation| predicate.matches(observation))
+        .try_fold(
+            FxHashMap::default,
+            |mut counts: FxHashMap<(DbID, DbID, DbID), u32>, observation| {
+                let value = group_value(observation, group_values)?;
+                if let Some(target_id) = observation.target_id {
+                    *counts
+                        .entry((value, observation.source_id, target_id))
+                        .or_default() += 1;
+                }
+                Ok(counts)
+            },
+        )
+        .try_reduce(FxHashMap::default, |mut counts1, counts2| {
+            for (pair, count) in counts2 {
+                *counts1.entry(pair).or_default() += count;
+            }
+            Ok(counts1)
+        })?;
+
+    Ok(counts
+        .into_iter()
+        .filter(|(_, count)| *count >= min_support)
+        .map(|(pair, _)| pair)
+        .collect())
+}
+
+// filter_coverage_data for each value of a categorical facet, in a single pass over the
+// data. The result for a value is the same as filtering with that value as the only
+// selected value of the facet. If the filter has any values of the facet selected, only
+// those values are included; otherwise every value of the facet is, even the ones without
+// any results.
+//
+// Every observation that passes the rest of the filter has to have exactly one value of the
+// facet. Observations can still have more than one value of any other facet.
+pub fn filter_coverage_data_grouped(
+    filters: &Filter,
+    data: &CoverageData,
+    group_by: &GroupByFacet,
+    included_features: Option<&ExperimentFeatureData>,
+) -> Result<FxHashMap<DbID, FilteredData>, FilterError> {
+    let chrom = filters.chromosome_index(&data.chromosomes)?;
+    let window_step = filters.checked_window_step(data.bucket_size)?;
+    let group_values: FxHashSet<DbID> = group_by
+        .find(&data.facets)?
+        .values
+        .iter()
+        .flat_map(|values| values.keys().cloned())
+        .collect();
+    let selected_values: FxHashSet<DbID> = filters
+        .categorical_facets
+        .intersection(&group_values)
+        .cloned()
+        .collect();
+    let included_values = if selected_values.is_empty() {
+        &group_values
+    } else {
+        &selected_values
+    };
+
+    // The filter without any of the facet's values. Pair support depends on the group, so
+    // it's counted separately.
+    let ungrouped_filters = Filter {
+        categorical_facets: filters
+            .categorical_facets
+            .difference(&group_values)
+            .cloned()
+            .collect(),
+        min_pair_support: None,
+        ..filters.clone()
+    };
+    let predicate = ObservationFilter::new(&ungrouped_filters, data, included_features);
+    let supported_pairs = match filters.min_pair_support {
+        Some(min_support) => Some(supported_group_pairs(
+            &predicate,
+            predicate.observations(data),
+            &group_values,
+            min_support,
+        )?),
+        None => None,
+    };
+    let matches_pair_support = |value: DbID, observation: &ObservationData| match &supported_pairs {
+        Some(supported_pairs) => match observation.target_id {
+            Some(target_id) => supported_pairs.contains(&(value, observation.source_id, target_id)),
+            None => !filters.pair_support_excludes_unpaired,
+        },
+        None => true,
+    };
+
+    let observations = predicate.observations(data);
+    let min_chunk_size = bucketing_chunk_size(observations.len());
+    let mut group_results = observations
+        .with_min_len(min_chunk_size)
+        .filter(|observation| {
+            predicate.matches_dedup(observation)
+                && predicate.matches_sample(observation)
+                && predicate.matches_non_numeric(observation)
+        })
+        .try_fold(
+            FxHashMap::default,
+            |mut group_results: FxHashMap<DbID, BucketResults>, observation| {
+                let value = group_value(observation, &group_values)?;
+                if !included_values.contains(&value) {
+                    return Ok(group_results);
+                }
+
+                let results = group_results.entry(value).or_default();
+                if has_invalid_values(observation) {
+                    results.dropped_invalid += 1;
+                } else if predicate.matches_numeric(observation)
+                    && matches_pair_support(value, observation)
+                {
+                    results.add(observation, &predicate, &data.feature_buckets);
+                }
+                Ok(group_results)
+            },
+        )
+        .try_reduce(FxHashMap::default, |mut group_results1, group_results2| {
+            for (value, results2) in group_results2 {
+                let results1 = group_results1.remove(&value).unwrap_or_default();
+                group_results1.insert(value, merge_bucket_results(results1, results2));
+            }
+            Ok(group_results1)
+        })?;
+
+    Ok(included_values
+        .iter()
+        .map(|value| {
+            let filtered_data = build_filtered_data(
+                data,
+                &predicate,
+                group_results.remove(value).unwrap_or_default(),
+                empty_chromosomes(chrom, data),
+                window_step,
+            );
+            (*value, filtered_data)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::filter_coverage_data;
+    use crate::test_data::{add_nan_values, assert_same_results, coverage_data, facet_value_id};
+
+    #[test]
+    fn test_groups_match_filtering_each_value() {
+        let mut data = coverage_data(17);
+        add_nan_values(&mut data, 61, 67);
+        let library = |i| facet_value_id(&data, "Library", &format!("Library {}", i));
+        let enriched = facet_value_id(&data, "Direction", "Enriched Only");
+        let by_library = GroupByFacet::Name("Library".to_string());
+
+        for filters in [
+            Filter::new(),
+            Filter::new().categorical_facets([enriched]),
+            Filter::new().dedup_reos(true).min_pair_support(2),
+            Filter::new().chrom(0).numeric_intervals(FilterIntervals {
+                effect: (-2.0, 2.0),
+                sig: (1.0, 12.0),
+                ..FilterIntervals::new()
+            }),
+        ] {
+            let groups = filter_coverage_data_grouped(&filters, &data, &by_library, None).unwrap();
+            assert_eq!(groups.len(), 3);
+            for i in 0..3 {
+                let mut value_filters = filters.clone();
+                value_filters.categorical_facets.insert(library(i));
+                assert_same_results(
+                    &groups[&library(i)],
+                    &filter_coverage_data(&value_filters, &data, None).unwrap(),
+                );
+            }
+        }
+
+        // Only the selected values are included
+        let filters = Filter::new().categorical_facets([library(0), library(2)]);
+        let library_facet = data.facets.iter().find(|f| f.name == "Library").unwrap();
+        let groups = filter_coverage_data_grouped(
+            &filters,
+            &data,
+            &GroupByFacet::Id(library_facet.id),
+            None,
+        )
+        .unwrap();
+        let mut values: Vec<DbID> = groups.keys().cloned().collect();
+        values.sort_unstable();
+        let mut expected = vec![library(0), library(2)];
+        expected.sort_unstable();
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn test_grouping_errors() {
+        let mut data = coverage_data(17);
+        assert_eq!(
+            filter_coverage_data_grouped(
+                &Filter::new(),
+                &data,
+                &GroupByFacet::Name("Effect Size".to_string()),
+                None
+            )
+            .unwrap_err(),
+            FilterError::UnknownFacet("Effect Size".to_string())
+        );
+
+        // An observation with two libraries
+        let libraries: Vec<DbID> = (0..3)
+            .map(|i| facet_value_id(&data, "Library", &format!("Library {}", i)))
+            .collect();
+        let observation = &mut data.significant_observations[10];
+        observation
+            .facet_value_ids
+            .retain(|id| !libraries.contains(id));
+        observation.facet_value_ids.extend(&libraries[..2]);
+        let reo_id = observation.reo_id;
+        assert_eq!(
+            filter_coverage_data_grouped(
+                &Filter::new(),
+                &data,
+                &GroupByFacet::Name("Library".to_string()),
+                None
+            )
+            .unwrap_err(),
+            FilterError::UngroupableObservation { reo_id, values: 2 }
+        );
+    }
+}
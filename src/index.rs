@@ -0,0 +1,502 @@
+use rayon::prelude::*;
+use roaring::RoaringTreemap;
+use rustc_hash::FxHashMap;
+
+use crate::error::FilterError;
+use crate::filter::{
+    bucket_matching_observations, build_filtered_data, empty_chromosomes, has_invalid_values,
+    merge_bucket_results, BucketResults, ObservationFilter,
+};
+use crate::filter_data_structures::*;
+use cov_viz_ds::{CoverageData, DbID, ExperimentFeatureData, ObservationData};
+
+// Precomputed lookups for a single CoverageData that let repeated filter calls skip
+// observations that can't possibly pass.
+//
+// Observations are identified by their "position": significant observations are numbered
+// first (0..significant_observations.len()) followed by the non-significant observations.
+//
+// Memory: each observation is stored once in the bitmap of every categorical facet value
+// it has, and once or twice in the chromosome bitmaps (once for the source's chromosome
+// and, for trans observations, once for the target's). Sparse bitmaps cost ~2 bytes per
+// entry, which is the upper bound: a data set with 10M observations tagged with 4 facet
+// values each has 50-60M bitmap entries, so at most roughly 100-120MB for the bitmaps.
+// Dense bitmaps are cheaper, since each block of 65536 positions with more than 4096
+// entries costs a fixed 8KB, down to 1 bit per entry; a bitmap over 10M positions never
+// needs more than ~1.25MB. The significance-sorted observation lists add another 16 bytes
+// per observation (160MB for 10M observations).
+pub struct CoverageDataIndex {
+    significant_count: u64,
+    nonsignificant_count: u64,
+    facet_values: FxHashMap<DbID, RoaringTreemap>,
+    // The observations with a source or target feature on each chromosome
+    chromosomes: FxHashMap<u8, RoaringTreemap>,
+    // (neg_log_significance, position) of the significant and non-significant observations,
+    // sorted by significance. Observations with a NaN effect size or significance are never
+    // bucketed, so they aren't included.
+    significant_by_sig: Vec<(f64, u64)>,
+    nonsignificant_by_sig: Vec<(f64, u64)>,
+    // The observations with a NaN effect size or significance
+    invalid: RoaringTreemap,
+}
+
+// Sorts the observations without NaN values by significance
+fn sort_by_significance(observations: &[ObservationData], first_position: u64) -> Vec<(f64, u64)> {
+    let mut by_sig: Vec<(f64, u64)> = observations
+        .par_iter()
+        .enumerate()
+        .filter(|(_, observation)| !has_invalid_values(observation))
+        .map(|(i, observation)| (observation.neg_log_significance, first_position + i as u64))
+        .collect();
+    by_sig.par_sort_unstable_by(|(sig1, _), (sig2, _)| sig1.total_cmp(sig2));
+    by_sig
+}
+
+// The positions of observations with a significance of at least `min_sig`
+fn at_least_significance(by_sig: &[(f64, u64)], min_sig: f64) -> &[(f64, u64)] {
+    &by_sig[by_sig.partition_point(|(sig, _)| *sig < min_sig)..]
+}
+
+#[derive(Default)]
+struct IndexMaps {
+    facet_values: FxHashMap<DbID, RoaringTreemap>,
+    chromosomes: FxHashMap<u8, RoaringTreemap>,
+    invalid: RoaringTreemap,
+}
+
+fn merge_index_maps<K: std::hash::Hash + Eq>(
+    maps1: &mut FxHashMap<K, RoaringTreemap>,
+    maps2: FxHashMap<K, RoaringTreemap>,
+) {
+    for (key, positions) in maps2 {
+        *maps1.entry(key).or_default() |= positions;
+    }
+}
+
+impl CoverageDataIndex {
+    pub fn build(data: &CoverageData) -> Self {
+        let significant_count = data.significant_observations.len() as u64;
+        let nonsignificant_count = data.nonsignificant_observations.len() as u64;
+
+        let maps = data
+            .significant_observations
+            .par_iter()
+            .chain(data.nonsignificant_observations.par_iter())
+            .enumerate()
+            .fold(IndexMaps::default, |mut maps, (position, observation)| {
+                let position = position as u64;
+                if has_invalid_values(observation) {
+                    maps.invalid.insert(position);
+                }
+                for facet_value in &observation.facet_value_ids {
+                    maps.facet_values
+                        .entry(*facet_value)
+                        .or_default()
+                        .insert(position);
+                }
+
+                let feature_ids =
+                    std::iter::once(observation.source_id).chain(observation.target_id);
+                for feature_id in feature_ids {
+                    if let Some(bucket) = data.feature_buckets.get(&feature_id) {
+                        maps.chromosomes
+                            .entry(bucket.chrom)
+                            .or_default()
+                            .insert(position);
+                    }
+                }
+
+                maps
+            })
+            .reduce(IndexMaps::default, |mut acc, maps| {
+                merge_index_maps(&mut acc.facet_values, maps.facet_values);
+                merge_index_maps(&mut acc.chromosomes, maps.chromosomes);
+                acc.invalid |= maps.invalid;
+                acc
+            });
+
+        CoverageDataIndex {
+            significant_count,
+            nonsignificant_count,
+            facet_values: maps.facet_values,
+            chromosomes: maps.chromosomes,
+            significant_by_sig: sort_by_significance(&data.significant_observations, 0),
+            nonsignificant_by_sig: sort_by_significance(
+                &data.nonsignificant_observations,
+                significant_count,
+            ),
+            invalid: maps.invalid,
+        }
+    }
+
+    fn observation<'a>(&self, data: &'a CoverageData, position: u64) -> &'a ObservationData {
+        if position < self.significant_count {
+            &data.significant_observations[position as usize]
+        } else {
+            &data.nonsignificant_observations[(position - self.significant_count) as usize]
+        }
+    }
+
+    // The positions of all observations that pass the categorical facet part of the filter
+    fn categorical_candidates(&self, predicate: &ObservationFilter) -> RoaringTreemap {
+        let mut candidates = RoaringTreemap::new();
+        if predicate.skip_nonsignificants {
+            candidates.insert_range(0..self.significant_count);
+        } else {
+            candidates.insert_range(0..self.significant_count + self.nonsignificant_count);
+        }
+
+        // An observation has to match at least one of the selected values of each facet
+        for selected_values in &predicate.selected_f {
+            let facet_candidates = selected_values
+                .iter()
+                .filter_map(|facet_value| self.facet_values.get(facet_value))
+                .fold(RoaringTreemap::new(), |acc, positions| acc | positions);
+            candidates &= facet_candidates;
+        }
+
+        candidates
+    }
+
+    // When the filter has a lower bound on significance, the positions of the observations
+    // that meet it. This is a superset of the observations that pass the numeric filter.
+    fn significance_candidates(
+        &self,
+        filters: &Filter,
+        predicate: &ObservationFilter,
+    ) -> Option<Vec<u64>> {
+        let min_sig = filters.numeric_intervals?.sig.0;
+
+        let significant = at_least_significance(&self.significant_by_sig, min_sig);
+        let nonsignificant = if predicate.skip_nonsignificants {
+            &[]
+        } else {
+            at_least_significance(&self.nonsignificant_by_sig, min_sig)
+        };
+
+        Some(
+            significant
+                .iter()
+                .chain(nonsignificant.iter())
+                .map(|(_, position)| *position)
+                .collect(),
+        )
+    }
+
+    // The positions of the observations that have to be checked against the filter. This
+    // uses whichever of the categorical candidates or the significance candidates is smaller.
+    // Categorical candidates with NaN values are always included so they can be counted.
+    fn candidate_positions(&self, filters: &Filter, predicate: &ObservationFilter) -> Vec<u64> {
+        let candidates = self.categorical_candidates(predicate);
+        match self.significance_candidates(filters, predicate) {
+            Some(sig_candidates) if (sig_candidates.len() as u64) < candidates.len() => {
+                let mut positions: Vec<u64> = sig_candidates
+                    .into_par_iter()
+                    .filter(|position| candidates.contains(*position))
+                    .collect();
+                positions.extend((candidates & &self.invalid).iter());
+                positions
+            }
+            _ => candidates.iter().collect(),
+        }
+    }
+
+    // Filters and buckets the candidate observations
+    fn bucket_candidates(
+        &self,
+        data: &CoverageData,
+        predicate: &ObservationFilter,
+        positions: &[u64],
+    ) -> BucketResults {
+        bucket_matching_observations(
+            positions
+                .par_iter()
+                .map(|position| self.observation(data, *position)),
+            predicate,
+            |observation| predicate.matches_included_features(observation),
+            &data.feature_buckets,
+        )
+    }
+}
+
+// Produces the same results as filter_coverage_data, but uses the index to avoid looking at
+// observations that can't pass the filter: those that don't have the selected categorical
+// facet values or, when there is a lower bound on significance, those that aren't significant
+// enough. When the filter is restricted to a chromosome, buckets are only built for
+// observations with a source or target on that chromosome, or on a chromosome that isn't in
+// the data set.
+// The index must have been built from `data`.
+pub fn filter_coverage_data_indexed(
+    filters: &Filter,
+    data: &CoverageData,
+    index: &CoverageDataIndex,
+    included_features: Option<&ExperimentFeatureData>,
+) -> Result<FilteredData, FilterError> {
+    debug_assert_eq!(
+        index.significant_count + index.nonsignificant_count,
+        (data.significant_observations.len() + data.nonsignificant_observations.len()) as u64
+    );
+
+    let chrom = filters.chromosome_index(&data.chromosomes)?;
+    let window_step = filters.checked_window_step(data.bucket_size)?;
+    let predicate = ObservationFilter::new(filters, data, included_features);
+    let candidates = index.candidate_positions(filters, &predicate);
+
+    let bucket_results = match chrom {
+        Some(chrom) => {
+            // Features on chromosomes that aren't in the data set are bucketed too, the same
+            // as filter_coverage_data does, so their buckets are counted as dropped. Features
+            // on the data set's other chromosomes are skipped by the predicate.
+            let chrom_positions = index
+                .chromosomes
+                .iter()
+                .filter(|(chrom_index, _)| {
+                    **chrom_index == chrom
+                        || !data.chromosomes.iter().any(|c| c.index == **chrom_index)
+                })
+                .fold(RoaringTreemap::new(), |acc, (_, positions)| acc | positions);
+            let (chrom_candidates, other_candidates): (Vec<u64>, Vec<u64>) = candidates
+                .into_par_iter()
+                .partition(|position| chrom_positions.contains(*position));
+
+            // Observations that aren't on those chromosomes don't contribute any buckets (so
+            // they're bucketed with no feature locations), but they are still counted.
+            merge_bucket_results(
+                index.bucket_candidates(data, &predicate, &chrom_candidates),
+                bucket_matching_observations(
+                    other_candidates
+                        .par_iter()
+                        .map(|position| index.observation(data, *position)),
+                    &predicate,
+                    |observation| predicate.matches_included_features(observation),
+                    &FxHashMap::default(),
+                ),
+            )
+        }
+        None => index.bucket_candidates(data, &predicate, &candidates),
+    };
+
+    Ok(build_filtered_data(
+        data,
+        &predicate,
+        bucket_results,
+        empty_chromosomes(chrom, data),
+        window_step,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::filter_coverage_data;
+    use crate::test_data::{add_nan_values, assert_same_results, coverage_data, facet_value_id};
+
+    fn filters(data: &CoverageData) -> Vec<Filter> {
+        let enriched = facet_value_id(data, "Direction", "Enriched Only");
+        let nonsignificant = facet_value_id(data, "Direction", "Non-significant");
+        let library = facet_value_id(data, "Library", "Library 1");
+
+        let mut all = Filter::new();
+        all.numeric_intervals = None;
+
+        let mut categorical = Filter::new();
+        categorical.categorical_facets = [enriched, library].into_iter().collect();
+
+        let mut nonsignificant_only = Filter::new();
+        nonsignificant_only.categorical_facets = [nonsignificant].into_iter().collect();
+
+        let mut significance = Filter::new();
+        significance.numeric_intervals = Some(FilterIntervals {
+            effect: (-2.0, 4.0),
+            sig: (5.0, 15.0),
+            ..FilterIntervals::new()
+        });
+
+        let mut combined = significance.clone();
+        combined.categorical_facets = [library].into_iter().collect();
+
+        vec![
+            all,
+            categorical,
+            nonsignificant_only,
+            significance,
+            combined,
+        ]
+    }
+
+    fn assert_indexed_matches_linear(data: &CoverageData, filters: &Filter) {
+        let index = CoverageDataIndex::build(data);
+        assert_same_results(
+            &filter_coverage_data_indexed(filters, data, &index, None).unwrap(),
+            &filter_coverage_data(filters, data, None).unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_indexed_matches_linear() {
+        let data = coverage_data(1);
+        for filters in filters(&data) {
+            assert_indexed_matches_linear(&data, &filters);
+        }
+    }
+
+    #[test]
+    fn test_indexed_matches_linear_with_nan_values() {
+        let mut data = coverage_data(2);
+        add_nan_values(&mut data, 37, 53);
+        for filters in filters(&data) {
+            let index = CoverageDataIndex::build(&data);
+            let indexed = filter_coverage_data_indexed(&filters, &data, &index, None).unwrap();
+            assert_same_results(
+                &indexed,
+                &filter_coverage_data(&filters, &data, None).unwrap(),
+            );
+            assert!(indexed.dropped_invalid > 0);
+        }
+    }
+
+    #[test]
+    fn test_indexed_matches_linear_with_empty_facet_values() {
+        let mut data = coverage_data(3);
+        // A facet value that no observation has
+        let library = data
+            .facets
+            .iter_mut()
+            .find(|f| f.name == "Library")
+            .unwrap();
+        library
+            .values
+            .as_mut()
+            .unwrap()
+            .insert(10_000, "Unused".to_string());
+
+        let mut filters = Filter::new();
+        filters.categorical_facets = [10_000].into_iter().collect();
+        let index = CoverageDataIndex::build(&data);
+        let indexed = filter_coverage_data_indexed(&filters, &data, &index, None).unwrap();
+        assert_same_results(
+            &indexed,
+            &filter_coverage_data(&filters, &data, None).unwrap(),
+        );
+        assert_eq!(indexed.reo_count, 0);
+
+        let mut filters = Filter::new();
+        filters.categorical_facets = [10_000, facet_value_id(&data, "Library", "Library 0")]
+            .into_iter()
+            .collect();
+        assert_indexed_matches_linear(&data, &filters);
+    }
+
+    #[test]
+    fn test_indexed_matches_linear_on_one_chromosome() {
+        let data = coverage_data(4);
+        for mut filters in filters(&data) {
+            for chrom in 0..data.chromosomes.len() as u8 {
+                filters.chrom = Some(chrom);
+                assert_indexed_matches_linear(&data, &filters);
+            }
+        }
+    }
+
+    #[test]
+    fn test_chromosome_index_includes_trans_targets() {
+        let data = coverage_data(5);
+        let index = CoverageDataIndex::build(&data);
+        let chrom = 1;
+        // Observations with a source on another chromosome and a target on this one
+        let trans: Vec<(u64, &ObservationData)> = data
+            .significant_observations
+            .iter()
+            .chain(data.nonsignificant_observations.iter())
+            .enumerate()
+            .map(|(position, observation)| (position as u64, observation))
+            .filter(|(_, observation)| {
+                let target_chrom = observation
+                    .target_id
+                    .map(|target_id| data.feature_buckets[&target_id].chrom);
+                data.feature_buckets[&observation.source_id].chrom != chrom
+                    && target_chrom == Some(chrom)
+            })
+            .collect();
+        assert!(!trans.is_empty());
+        for (position, _) in &trans {
+            assert!(index.chromosomes[&chrom].contains(*position));
+        }
+
+        let mut filters = Filter::new();
+        filters.chrom = Some(chrom);
+        let indexed = filter_coverage_data_indexed(&filters, &data, &index, None).unwrap();
+        assert_same_results(
+            &indexed,
+            &filter_coverage_data(&filters, &data, None).unwrap(),
+        );
+        let target_starts: Vec<u64> = indexed.chromosomes[0]
+            .target_intervals
+            .iter()
+            .map(|bucket| bucket.start)
+            .collect();
+        for (_, observation) in &trans {
+            let target_bucket = data.feature_buckets[&observation.target_id.unwrap()];
+            assert!(
+                target_starts.contains(&(target_bucket.idx as u64 * data.bucket_size as u64 + 1))
+            );
+        }
+    }
+
+    #[test]
+    fn test_significance_thresholds() {
+        let mut data = coverage_data(6);
+        add_nan_values(&mut data, 41, 43);
+        // Below every observation, exactly at an observation's significance, in the middle,
+        // and above every observation
+        let exact = data.significant_observations[3].neg_log_significance;
+        assert!(!exact.is_nan());
+        for min_sig in [-1.0, 0.0, 1.0, exact, 10.0, 19.99, 25.0] {
+            let mut filters = Filter::new();
+            filters.numeric_intervals = Some(FilterIntervals {
+                effect: (f32::MIN, f32::MAX),
+                sig: (min_sig, f64::MAX),
+                ..FilterIntervals::new()
+            });
+            assert_indexed_matches_linear(&data, &filters);
+
+            // Significances clamped below the threshold
+            filters.sig_ceiling = Some(5.0);
+            assert_indexed_matches_linear(&data, &filters);
+        }
+    }
+
+    #[test]
+    fn test_indexed_matches_linear_with_missing_chromosomes() {
+        // Chromosomes 0 and 1 become 5 and 9, and chromosome 2 isn't in the chromosome list
+        // anymore, though features are still on it
+        let mut data = coverage_data(7);
+        let new_index = |chrom: u8| match chrom {
+            0 => 5,
+            1 => 9,
+            chrom => chrom,
+        };
+        data.chromosomes.retain(|c| c.index != 2);
+        for chromosome in &mut data.chromosomes {
+            chromosome.index = new_index(chromosome.index);
+        }
+        for bucket in data.feature_buckets.values_mut() {
+            bucket.chrom = new_index(bucket.chrom);
+        }
+
+        let index = CoverageDataIndex::build(&data);
+        for mut filters in filters(&data) {
+            for chrom in [None, Some(5), Some(9)] {
+                filters.chrom = chrom;
+                let indexed = filter_coverage_data_indexed(&filters, &data, &index, None).unwrap();
+                assert_same_results(
+                    &indexed,
+                    &filter_coverage_data(&filters, &data, None).unwrap(),
+                );
+                if filters.numeric_intervals.is_none() && filters.categorical_facets.is_empty() {
+                    assert!(indexed.dropped_buckets > 0);
+                }
+            }
+        }
+    }
+}
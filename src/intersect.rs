@@ -3,7 +3,7 @@ use cov_viz_ds::ExperimentFeatureData;
 pub fn intersect_coverage_data_features(
     feature_data: Vec<ExperimentFeatureData>,
 ) -> ExperimentFeatureData {
-    if feature_data.len() == 0 {
+    if feature_data.is_empty() {
         return ExperimentFeatureData::default();
     }
 
@@ -17,3 +17,67 @@ pub fn intersect_coverage_data_features(
             }
         })
 }
+
+pub fn union_coverage_data_features(
+    feature_data: Vec<ExperimentFeatureData>,
+) -> ExperimentFeatureData {
+    feature_data
+        .into_iter()
+        .fold(ExperimentFeatureData::default(), |acc, feature_data| {
+            ExperimentFeatureData {
+                sources: acc.sources | feature_data.sources,
+                targets: acc.targets | feature_data.targets,
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use roaring::RoaringTreemap;
+
+    fn feature_data(sources: &[u64], targets: &[u64]) -> ExperimentFeatureData {
+        ExperimentFeatureData {
+            sources: sources.iter().copied().collect::<RoaringTreemap>(),
+            targets: targets.iter().copied().collect::<RoaringTreemap>(),
+        }
+    }
+
+    fn ids(treemap: &RoaringTreemap) -> Vec<u64> {
+        treemap.iter().collect()
+    }
+
+    #[test]
+    fn test_empty_input() {
+        let intersection = intersect_coverage_data_features(Vec::new());
+        assert!(intersection.sources.is_empty() && intersection.targets.is_empty());
+        let union = union_coverage_data_features(Vec::new());
+        assert!(union.sources.is_empty() && union.targets.is_empty());
+    }
+
+    #[test]
+    fn test_single_input() {
+        let intersection = intersect_coverage_data_features(vec![feature_data(&[1, 2], &[3])]);
+        assert_eq!(ids(&intersection.sources), vec![1, 2]);
+        assert_eq!(ids(&intersection.targets), vec![3]);
+        let union = union_coverage_data_features(vec![feature_data(&[1, 2], &[3])]);
+        assert_eq!(ids(&union.sources), vec![1, 2]);
+        assert_eq!(ids(&union.targets), vec![3]);
+    }
+
+    #[test]
+    fn test_three_inputs() {
+        let feature_sets = vec![
+            feature_data(&[1, 2, 3, 4], &[10, 11, 12]),
+            feature_data(&[2, 3, 4, 5], &[11, 12, 13]),
+            feature_data(&[3, 4, 5, 6], &[12, 13, 14]),
+        ];
+
+        let intersection = intersect_coverage_data_features(feature_sets.clone());
+        assert_eq!(ids(&intersection.sources), vec![3, 4]);
+        assert_eq!(ids(&intersection.targets), vec![12]);
+        let union = union_coverage_data_features(feature_sets);
+        assert_eq!(ids(&union.sources), vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(ids(&union.targets), vec![10, 11, 12, 13, 14]);
+    }
+}
@@ -0,0 +1,193 @@
+use roaring::RoaringTreemap;
+use serde::Deserialize;
+
+use crate::filter_data_structures::{
+    sorted_associated_buckets, FilterIntervals, FilteredBucket, FilteredChromosome, FilteredData,
+};
+
+// Reading FilteredData that was serialized with bincode by versions before 0.4. bincode
+// stores fields by position, not by name, so the fields added in 0.4 can't be defaulted
+// when reading the old layout; it has to be read as its own format.
+
+#[derive(Deserialize)]
+struct LegacyFilterIntervals {
+    effect: (f32, f32),
+    sig: (f64, f64),
+}
+
+#[derive(Deserialize)]
+struct LegacyFilteredBucket {
+    start: u32,
+    count: usize,
+    associated_buckets: Vec<u32>,
+    max_log10_sig: f64,
+    max_abs_effect: f32,
+}
+
+#[derive(Deserialize)]
+struct LegacyFilteredChromosome {
+    chrom: String,
+    index: u8,
+    bucket_size: u32,
+    target_intervals: Vec<LegacyFilteredBucket>,
+    source_intervals: Vec<LegacyFilteredBucket>,
+}
+
+#[derive(Deserialize)]
+struct LegacyFilteredData {
+    chromosomes: Vec<LegacyFilteredChromosome>,
+    bucket_size: u32,
+    numeric_intervals: LegacyFilterIntervals,
+    reo_count: u64,
+    sources: Vec<u8>,
+    targets: Vec<u8>,
+}
+
+// Older versions didn't sort intervals or their associated buckets
+fn intervals_from_legacy(intervals: Vec<LegacyFilteredBucket>) -> Vec<FilteredBucket> {
+    let mut intervals: Vec<FilteredBucket> =
+        intervals.into_iter().map(bucket_from_legacy).collect();
+    intervals.sort_by_key(|bucket| bucket.start);
+    intervals
+}
+
+fn bucket_from_legacy(bucket: LegacyFilteredBucket) -> FilteredBucket {
+    FilteredBucket {
+        start: bucket.start as u64,
+        count: bucket.count,
+        associated_buckets: sorted_associated_buckets(
+            bucket
+                .associated_buckets
+                .chunks_exact(2)
+                .map(|loc| (loc[0], loc[1])),
+        ),
+        max_log10_sig: bucket.max_log10_sig,
+        max_abs_effect: bucket.max_abs_effect,
+        count_is_lower_bound: false,
+        annotations: Vec::new(),
+        associated_buckets_truncated: false,
+        // Not stored by older versions
+        sig_sum: 0.0,
+        over_threshold_count: 0,
+    }
+}
+
+// Reads FilteredData serialized with bincode by a version before 0.4. The fields older
+// versions didn't have are left at their defaults, so sig_sum and bucket_count are 0.
+pub fn filtered_data_from_legacy_bytes(bytes: &[u8]) -> Result<FilteredData, bincode::Error> {
+    let legacy: LegacyFilteredData = bincode::deserialize(bytes)?;
+    let sources = RoaringTreemap::deserialize_from(&legacy.sources[..])?;
+    let targets = RoaringTreemap::deserialize_from(&legacy.targets[..])?;
+
+    let mut chromosomes: Vec<FilteredChromosome> = legacy
+        .chromosomes
+        .into_iter()
+        .map(|chromosome| FilteredChromosome {
+            chrom: chromosome.chrom,
+            index: chromosome.index,
+            bucket_size: chromosome.bucket_size,
+            target_intervals: intervals_from_legacy(chromosome.target_intervals),
+            source_intervals: intervals_from_legacy(chromosome.source_intervals),
+            truncated: false,
+            bucket_count: 0,
+        })
+        .collect();
+    // Older versions didn't order them
+    chromosomes.sort_by_key(|chromosome| chromosome.index);
+
+    Ok(FilteredData {
+        chromosomes,
+        bucket_size: legacy.bucket_size,
+        numeric_intervals: FilterIntervals {
+            effect: legacy.numeric_intervals.effect,
+            sig: legacy.numeric_intervals.sig,
+            ..FilterIntervals::new()
+        },
+        reo_count: legacy.reo_count,
+        dropped_invalid: 0,
+        dropped_buckets: 0,
+        window_step: None,
+        approximate: false,
+        counts_approximate: false,
+        annotation_names: Vec::new(),
+        omitted_empty: Vec::new(),
+        warnings: Vec::new(),
+        sources,
+        targets,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_legacy_bytes() {
+        let mut sources = RoaringTreemap::new();
+        sources.insert(3);
+        let mut source_bytes = vec![];
+        sources.serialize_into(&mut source_bytes).unwrap();
+        let mut target_bytes = vec![];
+        RoaringTreemap::new()
+            .serialize_into(&mut target_bytes)
+            .unwrap();
+
+        // bincode stores structs as their fields in order, so tuples have the same layout
+        let bucket = |start: u32, associated_buckets: Vec<u32>| {
+            (start, 2usize, associated_buckets, 4.5f64, -1.5f32)
+        };
+        let chromosomes = vec![
+            (
+                "chr2".to_string(),
+                1u8,
+                1000u32,
+                Vec::new(),
+                vec![bucket(2001, vec![])],
+            ),
+            (
+                "chr1".to_string(),
+                0u8,
+                1000u32,
+                vec![bucket(3001, vec![0, 5]), bucket(1, vec![1, 2, 0, 5])],
+                vec![bucket(1001, vec![])],
+            ),
+        ];
+        let bytes = bincode::serialize(&(
+            chromosomes,
+            1000u32,
+            ((-1.5f32, 1.5f32), (4.5f64, 4.5f64)),
+            3u64,
+            source_bytes,
+            target_bytes,
+        ))
+        .unwrap();
+
+        let data = filtered_data_from_legacy_bytes(&bytes).unwrap();
+        let chroms: Vec<&str> = data.chromosomes.iter().map(|c| c.chrom.as_str()).collect();
+        assert_eq!(chroms, vec!["chr1", "chr2"]);
+        let starts: Vec<u64> = data.chromosomes[0]
+            .target_intervals
+            .iter()
+            .map(|bucket| bucket.start)
+            .collect();
+        assert_eq!(starts, vec![1, 3001]);
+        assert_eq!(
+            data.chromosomes[0].target_intervals[0].associated_buckets,
+            vec![0, 5, 1, 2]
+        );
+        assert_eq!(data.chromosomes[1].source_intervals[0].count, 2);
+        assert_eq!(data.numeric_intervals.effect, (-1.5, 1.5));
+        assert_eq!(data.numeric_intervals.effect_negative, None);
+        assert_eq!(data.reo_count, 3);
+        assert_eq!(data.sources, sources);
+        assert!(data.targets.is_empty());
+
+        // The converted data can be stored in the current format
+        let current = bincode::serialize(&data).unwrap();
+        let round_trip: FilteredData = bincode::deserialize(&current).unwrap();
+        assert_eq!(round_trip.chromosomes.len(), 2);
+        assert_eq!(round_trip.reo_count, 3);
+
+        assert!(filtered_data_from_legacy_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -1,11 +1,81 @@
+mod aggregate;
+mod annotate;
+mod cache;
+mod cancel;
+// Loads coverage data from files, which the browser doesn't have
+#[cfg(all(feature = "capi", not(target_arch = "wasm32")))]
+mod capi;
+mod context;
+mod diff;
+mod drilldown;
+mod error;
+mod export;
+mod facets;
+mod feature_sets;
 mod filter;
 mod filter_data_structures;
+mod grouped;
+mod index;
 mod intersect;
+mod legacy;
+mod memory;
 mod merge;
+mod pairs;
+mod quantiles;
+mod rebucket;
+mod refilter;
+mod resolutions;
+mod sample;
+mod stats;
+mod summary;
+#[cfg(any(test, feature = "synthetic"))]
+mod synthetic;
+#[cfg(test)]
+mod test_data;
+#[cfg(feature = "proptest")]
+pub mod testing;
+mod two_phase;
+mod validate;
+mod volcano;
+#[cfg(feature = "wasm")]
+mod wasm;
 
-pub use crate::filter::filter_coverage_data;
+pub use crate::aggregate::{
+    aggregate_by_feature, top_features, FeatureAggregate, FeatureAggregateRow, FeatureRole,
+};
+pub use crate::annotate::{annotate_filtered_data, NamedRegion};
+pub use crate::cache::{filter_coverage_data_cached, CacheCapacity, FilterCache};
+pub use crate::cancel::{filter_coverage_data_cancellable, CancelToken};
+pub use crate::context::{filter_coverage_data_with_context, FilterContext};
+pub use crate::diff::{diff_filtered_data, DiffBucket, DiffChromosome, FilteredDiff};
+pub use crate::drilldown::{query_bucket_observations, BucketKind, ObservationDetail};
+pub use crate::error::{FeatureDataError, FilterError, MergeError, RebucketError};
+pub use crate::export::{export_filtered_observations, ObservationPage, ObservationSort, PageSpec};
+pub use crate::facets::{facet_info, FacetInfo};
+pub use crate::feature_sets::{
+    feature_data_from_bytes, feature_data_from_ids, feature_data_len, feature_data_to_bytes,
+};
+pub use crate::filter::{filter_coverage_data, filter_coverage_data_in_pool};
 pub use crate::filter_data_structures::{
-    BucketList, Filter, FilterIntervals, FilteredBucket, FilteredChromosome, FilteredData, MIN_SIG,
+    BucketList, FeatureMatchMode, Filter, FilterIntervals, FilterWarning, FilteredBucket,
+    FilteredChromosome, FilteredData, OutputSides, PreviewOptions, MIN_SIG,
 };
-pub use crate::intersect::intersect_coverage_data_features;
-pub use crate::merge::merge_filtered_data;
+pub use crate::grouped::{filter_coverage_data_grouped, GroupByFacet};
+pub use crate::index::{filter_coverage_data_indexed, CoverageDataIndex};
+pub use crate::intersect::{intersect_coverage_data_features, union_coverage_data_features};
+pub use crate::legacy::filtered_data_from_legacy_bytes;
+pub use crate::memory::coverage_data_mem_usage;
+pub use crate::merge::{filter_coverage_data_multi, merge_filtered_data};
+pub use crate::pairs::{significant_pairs, write_tsv, InteractionPair};
+pub use crate::quantiles::{numeric_quantiles, QuantileReport};
+pub use crate::rebucket::rebucket_filtered_data;
+pub use crate::refilter::refilter_filtered_data;
+pub use crate::resolutions::filter_coverage_data_resolutions;
+pub use crate::sample::{sample_filtered_observations, SampledObservation};
+pub use crate::stats::{filter_coverage_data_instrumented, FilterStats};
+pub use crate::summary::{coverage_summary, ChromosomeSummary, CoverageSummary};
+#[cfg(any(test, feature = "synthetic"))]
+pub use crate::synthetic::SyntheticCoverageDataBuilder;
+pub use crate::two_phase::{apply_numeric_filter, prefilter_categorical, CategoricalSelection};
+pub use crate::validate::{validate_coverage_data, CoverageDataReport};
+pub use crate::volcano::{volcano_histogram, VolcanoHistogram};
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::mem::size_of;
+
+use roaring::{RoaringBitmap, RoaringTreemap};
+
+use crate::filter_data_structures::*;
+use cov_viz_ds::{CoverageData, Facet, ObservationData};
+
+// Estimates of how much memory (in bytes) loaded data and filter results use, e.g., for sizing
+// caches. They count the space allocated for vectors, strings, and hash maps, not just what's
+// in use, but not any per-allocation overhead of the allocator.
+
+fn vec_size<T>(v: &Vec<T>) -> usize {
+    v.capacity() * size_of::<T>()
+}
+
+// A hash map's table: its buckets and a control byte for each bucket (plus a group's worth of
+// extra control bytes)
+fn map_size<K, V, S>(map: &HashMap<K, V, S>) -> usize {
+    let capacity = map.capacity();
+    // The table always has a power of two number of buckets. Up to 7/8 of them can be used.
+    let buckets = match capacity {
+        0 => return 0,
+        1..=7 => capacity + 1,
+        _ => capacity / 7 * 8,
+    };
+    buckets * (size_of::<(K, V)>() + 1) + 16
+}
+
+// Roaring bitmaps' containers take up about as much space in memory as they do serialized.
+// Each of a treemap's bitmaps also has a map entry.
+fn treemap_size(treemap: &RoaringTreemap) -> usize {
+    treemap.serialized_size() + treemap.bitmaps().count() * size_of::<(u32, RoaringBitmap)>()
+}
+
+fn facet_size(facet: &Facet) -> usize {
+    facet.name.capacity()
+        + facet.description.capacity()
+        + facet.facet_type.capacity()
+        + facet.coverage.as_ref().map_or(0, |coverage| {
+            vec_size(coverage) + coverage.iter().map(String::capacity).sum::<usize>()
+        })
+        + facet.values.as_ref().map_or(0, |values| {
+            map_size(values) + values.values().map(String::capacity).sum::<usize>()
+        })
+}
+
+fn observations_size(observations: &Vec<ObservationData>) -> usize {
+    vec_size(observations)
+        + observations
+            .iter()
+            .map(|observation| vec_size(&observation.facet_value_ids))
+            .sum::<usize>()
+}
+
+// An estimate of how much memory loaded coverage data uses
+pub fn coverage_data_mem_usage(data: &CoverageData) -> usize {
+    size_of::<CoverageData>()
+        + vec_size(&data.facets)
+        + data.facets.iter().map(facet_size).sum::<usize>()
+        + vec_size(&data.chromosomes)
+        + data
+            .chromosomes
+            .iter()
+            .map(|c| c.chrom.capacity())
+            .sum::<usize>()
+        + observations_size(&data.significant_observations)
+        + observations_size(&data.nonsignificant_observations)
+        + map_size(&data.feature_buckets)
+}
+
+fn bucket_size(bucket: &FilteredBucket) -> usize {
+    vec_size(&bucket.associated_buckets) + vec_size(&bucket.annotations)
+}
+
+fn chromosome_size(chromosome: &FilteredChromosome) -> usize {
+    chromosome.chrom.capacity()
+        + vec_size(&chromosome.source_intervals)
+        + vec_size(&chromosome.target_intervals)
+        + chromosome
+            .source_intervals
+            .iter()
+            .chain(chromosome.target_intervals.iter())
+            .map(bucket_size)
+            .sum::<usize>()
+}
+
+impl FilteredData {
+    // An estimate of how much memory the results use
+    pub fn mem_usage(&self) -> usize {
+        size_of::<FilteredData>()
+            + vec_size(&self.chromosomes)
+            + self.chromosomes.iter().map(chromosome_size).sum::<usize>()
+            + vec_size(&self.annotation_names)
+            + self
+                .annotation_names
+                .iter()
+                .chain(self.omitted_empty.iter())
+                .map(String::capacity)
+                .sum::<usize>()
+            + vec_size(&self.omitted_empty)
+            + vec_size(&self.warnings)
+            + self
+                .warnings
+                .iter()
+                .map(|warning| match warning {
+                    FilterWarning::NumericIntervalOutsideDataRange(name) => name.capacity(),
+                    _ => 0,
+                })
+                .sum::<usize>()
+            + treemap_size(&self.sources)
+            + treemap_size(&self.targets)
+    }
+}
+
+impl BucketList {
+    // An estimate of how much memory the bucket list uses
+    pub fn mem_usage(&self) -> usize {
+        size_of::<BucketList>()
+            + map_size(&self.buckets)
+            + self.buckets.values().map(vec_size).sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::filter_coverage_data;
+    use crate::test_data::coverage_data;
+    use cov_viz_ds::{BucketLoc, ChromosomeData, DbID};
+
+    // The estimates should be at least the size of the elements we know are there, and not
+    // much more than that, since the extra is only unused capacity and per-container overhead.
+    fn assert_close(estimate: usize, lower_bound: usize) {
+        assert!(estimate >= lower_bound, "{} < {}", estimate, lower_bound);
+        assert!(
+            estimate <= lower_bound * 2,
+            "{} > 2 * {}",
+            estimate,
+            lower_bound
+        );
+    }
+
+    #[test]
+    fn test_coverage_data_mem_usage() {
+        let data = coverage_data(1);
+        let observations = data
+            .significant_observations
+            .iter()
+            .chain(data.nonsignificant_observations.iter());
+        let lower_bound = observations
+            .map(|o| size_of::<ObservationData>() + o.facet_value_ids.len() * size_of::<DbID>())
+            .sum::<usize>()
+            + data.feature_buckets.len() * size_of::<(DbID, BucketLoc)>()
+            + data.chromosomes.len() * size_of::<ChromosomeData>();
+        assert_close(coverage_data_mem_usage(&data), lower_bound);
+    }
+
+    #[test]
+    fn test_filtered_data_mem_usage() {
+        let data = coverage_data(2);
+        let filtered = filter_coverage_data(&Filter::new(), &data, None).unwrap();
+        let lower_bound = filtered
+            .chromosomes
+            .iter()
+            .flat_map(|c| c.source_intervals.iter().chain(c.target_intervals.iter()))
+            .map(|b| size_of::<FilteredBucket>() + b.associated_buckets.len() * size_of::<u32>())
+            .sum::<usize>()
+            + filtered.chromosomes.len() * size_of::<FilteredChromosome>()
+            + filtered.sources.serialized_size()
+            + filtered.targets.serialized_size();
+        assert!(lower_bound > 0);
+        assert_close(filtered.mem_usage(), lower_bound);
+    }
+
+    #[test]
+    fn test_bucket_list_mem_usage() {
+        let buckets = BucketList {
+            buckets: (0..4u8).map(|chrom| (chrom, vec![0; 1_000])).collect(),
+        };
+        let lower_bound = 4 * 1_000 * size_of::<u32>();
+        assert_close(buckets.mem_usage(), lower_bound);
+    }
+}
@@ -1,30 +1,36 @@
+use rayon::prelude::*;
 use roaring::RoaringTreemap;
-use rustc_hash::FxHashSet;
+use rustc_hash::{FxHashMap, FxHashSet};
 
+use crate::error::MergeError;
+use crate::filter::filter_coverage_data;
 use crate::filter_data_structures::*;
+use cov_viz_ds::{CoverageData, ExperimentFeatureData};
 
 fn merge_chromosomes(
     result_data: &Vec<FilteredData>,
     chromosomes: Vec<String>,
 ) -> Vec<FilteredChromosome> {
-    if result_data.len() == 0 {
+    if result_data.is_empty() {
         return Vec::new();
     } else if result_data.len() == 1 {
-        return result_data[0].chromosomes.clone();
+        return chromosomes
+            .iter()
+            .filter_map(|chrom| {
+                result_data[0]
+                    .chromosomes
+                    .iter()
+                    .find(|c| c.chrom == *chrom)
+                    .cloned()
+            })
+            .collect();
     }
 
     let mut new_coverage = Vec::new();
-    let mut chroms_covered: FxHashSet<String> = FxHashSet::default();
 
     for chrom in chromosomes {
-        // The default new_chromosome should never ever be used.
-        let mut new_chromosome = FilteredChromosome {
-            chrom: chrom.clone(),
-            index: 0,
-            bucket_size: 0,
-            target_intervals: Vec::new(),
-            source_intervals: Vec::new(),
-        };
+        // Chromosomes that aren't in any of the results are left out
+        let mut merged_chromosome: Option<FilteredChromosome> = None;
         for filtered_data in result_data {
             for filtered_chrom in &filtered_data.chromosomes {
                 // Wrong chromosome
@@ -33,11 +39,13 @@ fn merge_chromosomes(
                 }
 
                 // Right chromosome, but hasn't been added yet so no merging needed
-                if !chroms_covered.contains(&filtered_chrom.chrom) {
-                    chroms_covered.insert(filtered_chrom.chrom.clone());
-                    new_chromosome = filtered_chrom.clone();
-                    break;
-                }
+                let new_chromosome = match &mut merged_chromosome {
+                    Some(new_chromosome) => new_chromosome,
+                    None => {
+                        merged_chromosome = Some(filtered_chrom.clone());
+                        break;
+                    }
+                };
 
                 // Right chromosome; has been added already; need to merge
                 let mut source_intervals: Vec<FilteredBucket> = Vec::new();
@@ -75,11 +83,10 @@ fn merge_chromosomes(
                         source_intervals.push(filtered_chrom.source_intervals[j].clone());
                         j += 1;
                     } else {
-                        let mut assoc_buckets = filtered_chrom.source_intervals[j]
-                            .associated_buckets
-                            .clone();
-                        assoc_buckets
-                            .extend(new_chromosome.source_intervals[i].associated_buckets.iter());
+                        let assoc_buckets = merge_associated_buckets(
+                            &filtered_chrom.source_intervals[j].associated_buckets,
+                            &new_chromosome.source_intervals[i].associated_buckets,
+                        );
                         source_intervals.push(FilteredBucket {
                             start: filtered_chrom.source_intervals[j].start,
                             count: filtered_chrom.source_intervals[j].count
@@ -88,15 +95,27 @@ fn merge_chromosomes(
                             max_log10_sig: filtered_chrom.source_intervals[j]
                                 .max_log10_sig
                                 .max(new_chromosome.source_intervals[i].max_log10_sig),
-                            max_abs_effect: if filtered_chrom.source_intervals[j]
-                                .max_abs_effect
-                                .abs()
-                                > new_chromosome.source_intervals[i].max_abs_effect.abs()
-                            {
-                                filtered_chrom.source_intervals[j].max_abs_effect
-                            } else {
-                                new_chromosome.source_intervals[i].max_abs_effect
-                            },
+                            max_abs_effect: max_abs_effect(
+                                filtered_chrom.source_intervals[j].max_abs_effect,
+                                new_chromosome.source_intervals[i].max_abs_effect,
+                            ),
+                            count_is_lower_bound: filtered_chrom.source_intervals[j]
+                                .count_is_lower_bound
+                                || new_chromosome.source_intervals[i].count_is_lower_bound,
+                            annotations: merge_annotations(
+                                &filtered_chrom.source_intervals[j].annotations,
+                                &new_chromosome.source_intervals[i].annotations,
+                            ),
+                            associated_buckets_truncated: filtered_chrom.source_intervals[j]
+                                .associated_buckets_truncated
+                                || new_chromosome.source_intervals[i].associated_buckets_truncated,
+                            sig_sum: add_sig_sums(
+                                filtered_chrom.source_intervals[j].sig_sum,
+                                new_chromosome.source_intervals[i].sig_sum,
+                            ),
+                            over_threshold_count: filtered_chrom.source_intervals[j]
+                                .over_threshold_count
+                                + new_chromosome.source_intervals[i].over_threshold_count,
                         });
                         i += 1;
                         j += 1;
@@ -138,11 +157,10 @@ fn merge_chromosomes(
                         target_intervals.push(filtered_chrom.target_intervals[j].clone());
                         j += 1;
                     } else {
-                        let mut assoc_buckets = filtered_chrom.target_intervals[j]
-                            .associated_buckets
-                            .clone();
-                        assoc_buckets
-                            .extend(new_chromosome.target_intervals[i].associated_buckets.iter());
+                        let assoc_buckets = merge_associated_buckets(
+                            &filtered_chrom.target_intervals[j].associated_buckets,
+                            &new_chromosome.target_intervals[i].associated_buckets,
+                        );
                         target_intervals.push(FilteredBucket {
                             start: filtered_chrom.target_intervals[j].start,
                             count: filtered_chrom.target_intervals[j].count
@@ -151,15 +169,27 @@ fn merge_chromosomes(
                             max_log10_sig: filtered_chrom.target_intervals[j]
                                 .max_log10_sig
                                 .max(new_chromosome.target_intervals[i].max_log10_sig),
-                            max_abs_effect: if filtered_chrom.target_intervals[j]
-                                .max_abs_effect
-                                .abs()
-                                > new_chromosome.target_intervals[i].max_abs_effect.abs()
-                            {
-                                filtered_chrom.target_intervals[j].max_abs_effect
-                            } else {
-                                new_chromosome.target_intervals[i].max_abs_effect
-                            },
+                            max_abs_effect: max_abs_effect(
+                                filtered_chrom.target_intervals[j].max_abs_effect,
+                                new_chromosome.target_intervals[i].max_abs_effect,
+                            ),
+                            count_is_lower_bound: filtered_chrom.target_intervals[j]
+                                .count_is_lower_bound
+                                || new_chromosome.target_intervals[i].count_is_lower_bound,
+                            annotations: merge_annotations(
+                                &filtered_chrom.target_intervals[j].annotations,
+                                &new_chromosome.target_intervals[i].annotations,
+                            ),
+                            associated_buckets_truncated: filtered_chrom.target_intervals[j]
+                                .associated_buckets_truncated
+                                || new_chromosome.target_intervals[i].associated_buckets_truncated,
+                            sig_sum: add_sig_sums(
+                                filtered_chrom.target_intervals[j].sig_sum,
+                                new_chromosome.target_intervals[i].sig_sum,
+                            ),
+                            over_threshold_count: filtered_chrom.target_intervals[j]
+                                .over_threshold_count
+                                + new_chromosome.target_intervals[i].over_threshold_count,
                         });
                         i += 1;
                         j += 1;
@@ -168,37 +198,141 @@ fn merge_chromosomes(
 
                 new_chromosome.source_intervals = source_intervals;
                 new_chromosome.target_intervals = target_intervals;
+                new_chromosome.truncated |= filtered_chrom.truncated;
+                new_chromosome.bucket_count =
+                    new_chromosome.bucket_count.max(filtered_chrom.bucket_count);
 
                 break;
             }
         }
-        new_coverage.push(new_chromosome);
+        if let Some(new_chromosome) = merged_chromosome {
+            new_coverage.push(new_chromosome);
+        }
     }
 
     new_coverage
 }
 
+// The smallest range covering both ranges. A missing range is empty.
+fn merge_range(range1: Option<(f32, f32)>, range2: Option<(f32, f32)>) -> Option<(f32, f32)> {
+    match (range1, range2) {
+        (Some(range1), Some(range2)) => Some((range1.0.min(range2.0), range1.1.max(range2.1))),
+        (range1, None) => range1,
+        (None, range2) => range2,
+    }
+}
+
+// Combines the data sets' annotation names into one list, and changes the annotations of
+// their buckets to index into it
+fn merge_annotation_names(result_data: &mut [FilteredData]) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut name_indices: FxHashMap<String, u32> = FxHashMap::default();
+    for data in result_data {
+        let new_indices: Vec<u32> = data
+            .annotation_names
+            .iter()
+            .map(|name| {
+                *name_indices.entry(name.clone()).or_insert_with(|| {
+                    names.push(name.clone());
+                    (names.len() - 1) as u32
+                })
+            })
+            .collect();
+        if new_indices
+            .iter()
+            .enumerate()
+            .all(|(i, idx)| i as u32 == *idx)
+        {
+            continue;
+        }
+
+        for chromosome in &mut data.chromosomes {
+            let buckets = chromosome
+                .source_intervals
+                .iter_mut()
+                .chain(chromosome.target_intervals.iter_mut());
+            for bucket in buckets {
+                for annotation in &mut bucket.annotations {
+                    *annotation = new_indices[*annotation as usize];
+                }
+                bucket.annotations.sort_unstable();
+            }
+        }
+    }
+    names
+}
+
+// There must be at least one data set, and every data set must have the same bucket size
+// and use the same window step (or none use windows). `chromosome_list` picks the
+// chromosomes that are included; they're always in order of their index.
 pub fn merge_filtered_data(
-    result_data: Vec<FilteredData>,
+    mut result_data: Vec<FilteredData>,
     chromosome_list: Vec<String>,
-) -> FilteredData {
-    let chromosomes: Vec<FilteredChromosome> = merge_chromosomes(&result_data, chromosome_list);
+) -> Result<FilteredData, MergeError> {
+    let bucket_size = result_data.first().ok_or(MergeError::NoData)?.bucket_size;
+    if let Some(d) = result_data.iter().find(|d| d.bucket_size != bucket_size) {
+        return Err(MergeError::BucketSizeMismatch(bucket_size, d.bucket_size));
+    }
+    let window_step = result_data[0].window_step;
+    if let Some(d) = result_data.iter().find(|d| d.window_step != window_step) {
+        return Err(MergeError::WindowStepMismatch(window_step, d.window_step));
+    }
+
+    let annotation_names = merge_annotation_names(&mut result_data);
+
+    // Chromosomes that were left out of any of the data sets for being empty are also left
+    // out of the merged data if they're still empty
+    let omitted: FxHashSet<&String> = result_data.iter().flat_map(|d| &d.omitted_empty).collect();
+    let omitted_empty: Vec<String> = chromosome_list
+        .iter()
+        .filter(|chrom| omitted.contains(chrom))
+        .cloned()
+        .collect();
+    let mut chromosomes: Vec<FilteredChromosome> = merge_chromosomes(&result_data, chromosome_list);
+    chromosomes.sort_by_key(|c| c.index);
+    chromosomes.retain(|c| {
+        !(omitted.contains(&c.chrom)
+            && c.source_intervals.is_empty()
+            && c.target_intervals.is_empty())
+    });
+    let omitted_empty: Vec<String> = omitted_empty
+        .into_iter()
+        .filter(|chrom| !chromosomes.iter().any(|c| c.chrom == *chrom))
+        .collect();
+    let mut warnings: Vec<FilterWarning> = Vec::new();
+    for warning in result_data.iter().flat_map(|d| &d.warnings) {
+        if !warnings.contains(warning) {
+            warnings.push(warning.clone());
+        }
+    }
     let numeric_intervals = result_data.iter().map(|d| d.numeric_intervals).fold(
         FilterIntervals {
             effect: (f32::MAX, f32::MIN),
             sig: (f64::MAX, f64::MIN),
+            effect_negative: None,
+            effect_positive: None,
         },
         |acc, d| FilterIntervals {
             effect: (acc.effect.0.min(d.effect.0), acc.effect.1.max(d.effect.1)),
             sig: (acc.sig.0.min(d.sig.0), acc.sig.1.max(d.sig.1)),
+            effect_negative: merge_range(acc.effect_negative, d.effect_negative),
+            effect_positive: merge_range(acc.effect_positive, d.effect_positive),
         },
     );
 
-    FilteredData {
+    Ok(FilteredData {
         chromosomes,
         numeric_intervals,
-        bucket_size: result_data[0].bucket_size,
+        bucket_size,
         reo_count: result_data.iter().map(|f| f.reo_count).sum(),
+        dropped_invalid: result_data.iter().map(|f| f.dropped_invalid).sum(),
+        dropped_buckets: result_data.iter().map(|f| f.dropped_buckets).sum(),
+        window_step,
+        approximate: result_data.iter().any(|f| f.approximate),
+        counts_approximate: result_data.iter().any(|f| f.counts_approximate),
+        annotation_names,
+        omitted_empty,
+        warnings,
         sources: result_data
             .iter()
             .fold(RoaringTreemap::default(), |mut acc, f| {
@@ -211,5 +345,283 @@ pub fn merge_filtered_data(
                 acc.extend(&f.targets);
                 acc
             }),
+    })
+}
+
+// Filters every data set with the same filter and merges the results. The output is the same
+// as filtering each data set and then calling merge_filtered_data with the results in the
+// same order, but the data sets are filtered in parallel and results are merged as they're
+// produced, so not every intermediate result has to be kept around. Each data set's
+// filtering is itself parallel; rayon shares its thread pool between the two levels.
+//
+// As with merge_filtered_data, the filter's output budget applies to each data set's results,
+// not to the merged results, which can have more buckets than the budget allows.
+pub fn filter_coverage_data_multi(
+    filters: &Filter,
+    datasets: &[&CoverageData],
+    included_features: Option<&ExperimentFeatureData>,
+    chromosome_list: &[String],
+) -> Result<FilteredData, MergeError> {
+    let bucket_size = datasets.first().ok_or(MergeError::NoData)?.bucket_size;
+    if let Some(data) = datasets.iter().find(|d| d.bucket_size != bucket_size) {
+        return Err(MergeError::BucketSizeMismatch(
+            bucket_size,
+            data.bucket_size,
+        ));
+    }
+
+    let merged = datasets
+        .par_iter()
+        .with_max_len(1)
+        .map(|data| -> Result<FilteredData, MergeError> {
+            Ok(filter_coverage_data(filters, data, included_features)?)
+        })
+        .try_reduce_with(|data1, data2| {
+            merge_filtered_data(vec![data1, data2], chromosome_list.to_vec())
+        })
+        .ok_or(MergeError::NoData)??;
+
+    // Merging pairwise already puts the chromosomes in order and drops the ones that aren't
+    // in the chromosome list, except when there was only one data set
+    if datasets.len() == 1 {
+        merge_filtered_data(vec![merged], chromosome_list.to_vec())
+    } else {
+        Ok(merged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::{assert_same_results, coverage_data, facet_value_id};
+    use cov_viz_ds::ObservationData;
+
+    fn filtered(seed: u64) -> FilteredData {
+        filter_coverage_data(&Filter::new(), &coverage_data(seed), None).unwrap()
+    }
+
+    #[test]
+    fn test_chromosomes_missing_from_results_are_left_out() {
+        let chromosome_list: Vec<String> = ["chr3", "chrUn", "chr1"]
+            .iter()
+            .map(|chrom| chrom.to_string())
+            .collect();
+
+        for result_data in [vec![filtered(1)], vec![filtered(1), filtered(2)]] {
+            let merged = merge_filtered_data(result_data, chromosome_list.clone()).unwrap();
+            let chromosomes: Vec<(&str, u8, u32)> = merged
+                .chromosomes
+                .iter()
+                .map(|c| (c.chrom.as_str(), c.index, c.bucket_size))
+                .collect();
+            assert_eq!(chromosomes, vec![("chr1", 0, 1000), ("chr3", 2, 1000)]);
+            assert!(merged.omitted_empty.is_empty());
+        }
+    }
+
+    // The data set's observations, split by whether `keep` is true for them
+    fn split(data: &CoverageData, keep: impl Fn(&ObservationData) -> bool) -> [CoverageData; 2] {
+        let mut kept = data.clone();
+        let mut rest = data.clone();
+        kept.significant_observations.retain(&keep);
+        kept.nonsignificant_observations.retain(&keep);
+        rest.significant_observations.retain(|o| !keep(o));
+        rest.nonsignificant_observations.retain(|o| !keep(o));
+        [kept, rest]
+    }
+
+    fn max_abs_effects(data: &FilteredData) -> Vec<(u8, u64, f32, f32)> {
+        let mut effects = Vec::new();
+        for chromosome in &data.chromosomes {
+            // Source and target buckets don't always line up, so they're listed separately
+            for bucket in &chromosome.source_intervals {
+                effects.push((chromosome.index, bucket.start, bucket.max_abs_effect, 0.0));
+            }
+            for bucket in &chromosome.target_intervals {
+                effects.push((chromosome.index, bucket.start, 0.0, bucket.max_abs_effect));
+            }
+        }
+        effects
+    }
+
+    #[test]
+    fn test_merged_max_abs_effect_matches_filtering_union() {
+        for seed in 0..8 {
+            let mut data = coverage_data(seed);
+            // Lots of ties between positive and negative effects
+            for (i, observation) in data.significant_observations.iter_mut().enumerate() {
+                observation.effect_size = if i % 2 == 0 { 2.0 } else { -2.0 };
+            }
+            let chromosome_list: Vec<String> =
+                data.chromosomes.iter().map(|c| c.chrom.clone()).collect();
+            let union = filter_coverage_data(&Filter::new(), &data, None).unwrap();
+
+            let parts = split(&data, |o| (o.reo_id + seed) % 3 == 0);
+            let merged = merge_filtered_data(
+                parts
+                    .iter()
+                    .map(|part| filter_coverage_data(&Filter::new(), part, None).unwrap())
+                    .collect(),
+                chromosome_list,
+            )
+            .unwrap();
+            assert_eq!(max_abs_effects(&merged), max_abs_effects(&union));
+        }
+    }
+
+    #[test]
+    fn test_merge_errors() {
+        let chromosome_list = vec!["chr1".to_string()];
+        assert_eq!(
+            merge_filtered_data(Vec::new(), chromosome_list.clone()).unwrap_err(),
+            MergeError::NoData
+        );
+
+        let data = coverage_data(1);
+        let mut coarse_data = data.clone();
+        coarse_data.bucket_size = 2_000;
+        let coarse = filter_coverage_data(&Filter::new(), &coarse_data, None).unwrap();
+        assert_eq!(
+            merge_filtered_data(vec![filtered(1), coarse], chromosome_list.clone()).unwrap_err(),
+            MergeError::BucketSizeMismatch(1_000, 2_000)
+        );
+
+        let windowed = |window_step| {
+            filter_coverage_data(&Filter::new().window_step(window_step), &data, None).unwrap()
+        };
+        assert_eq!(
+            merge_filtered_data(vec![windowed(250), windowed(500)], chromosome_list.clone())
+                .unwrap_err(),
+            MergeError::WindowStepMismatch(Some(250), Some(500))
+        );
+        assert_eq!(
+            merge_filtered_data(vec![filtered(1), windowed(500)], chromosome_list.clone())
+                .unwrap_err(),
+            MergeError::WindowStepMismatch(None, Some(500))
+        );
+        let merged =
+            merge_filtered_data(vec![windowed(500), windowed(500)], chromosome_list).unwrap();
+        assert_eq!(merged.window_step, Some(500));
+    }
+
+    // Bucket counts are the number of distinct features, which are added up when merging,
+    // so merged results only match filtering the union of data sets without shared features
+    fn with_id_offset(data: &CoverageData, offset: u64) -> CoverageData {
+        let mut data = data.clone();
+        for observation in data
+            .significant_observations
+            .iter_mut()
+            .chain(data.nonsignificant_observations.iter_mut())
+        {
+            observation.reo_id += offset;
+            observation.source_id += offset;
+            observation.target_id = observation.target_id.map(|id| id + offset);
+        }
+        data.feature_buckets = data
+            .feature_buckets
+            .into_iter()
+            .map(|(id, loc)| (id + offset, loc))
+            .collect();
+        data
+    }
+
+    #[test]
+    fn test_multi_matches_filtering_union() {
+        let data1 = coverage_data(3);
+        let data2 = with_id_offset(&coverage_data(4), 1_000_000);
+        let mut union = data1.clone();
+        union
+            .significant_observations
+            .extend(data2.significant_observations.iter().cloned());
+        union
+            .nonsignificant_observations
+            .extend(data2.nonsignificant_observations.iter().cloned());
+        union.feature_buckets.extend(data2.feature_buckets.clone());
+        let chromosome_list: Vec<String> =
+            union.chromosomes.iter().map(|c| c.chrom.clone()).collect();
+
+        let library = facet_value_id(&union, "Library", "Library 1");
+        for filters in [Filter::new(), Filter::new().categorical_facets([library])] {
+            let expected = filter_coverage_data(&filters, &union, None).unwrap();
+            let multi =
+                filter_coverage_data_multi(&filters, &[&data1, &data2], None, &chromosome_list)
+                    .unwrap();
+            assert_same_results(&multi, &expected);
+        }
+
+        let data = data1;
+        assert_eq!(
+            filter_coverage_data_multi(&Filter::new(), &[], None, &chromosome_list).unwrap_err(),
+            MergeError::NoData
+        );
+        let mut coarse_data = data.clone();
+        coarse_data.bucket_size = 2_000;
+        assert_eq!(
+            filter_coverage_data_multi(
+                &Filter::new(),
+                &[&data, &coarse_data],
+                None,
+                &chromosome_list
+            )
+            .unwrap_err(),
+            MergeError::BucketSizeMismatch(1_000, 2_000)
+        );
+    }
+
+    #[test]
+    fn test_multi_budget_matches_filtering_then_merging() {
+        let data = coverage_data(4);
+        let chromosome_list: Vec<String> =
+            data.chromosomes.iter().map(|c| c.chrom.clone()).collect();
+        let parts = split(&data, |o| o.reo_id % 2 == 0);
+        let datasets: Vec<&CoverageData> = parts.iter().chain(parts.iter()).collect();
+
+        let filters = Filter::new()
+            .max_buckets_per_chromosome(5)
+            .max_associated_buckets_per_bucket(3);
+        let multi =
+            filter_coverage_data_multi(&filters, &datasets, None, &chromosome_list).unwrap();
+        let filtered: Vec<FilteredData> = datasets
+            .iter()
+            .map(|data| filter_coverage_data(&filters, data, None).unwrap())
+            .collect();
+        assert!(filtered
+            .iter()
+            .all(|result| result.chromosomes.iter().all(|c| c.truncated)));
+        assert_same_results(
+            &multi,
+            &merge_filtered_data(filtered, chromosome_list.clone()).unwrap(),
+        );
+        assert!(multi.chromosomes.iter().all(|c| c.truncated));
+    }
+
+    #[test]
+    fn test_merged_effect_ranges_by_sign() {
+        let data = coverage_data(5);
+        let chromosome_list: Vec<String> =
+            data.chromosomes.iter().map(|c| c.chrom.clone()).collect();
+        let union = filter_coverage_data(&Filter::new(), &data, None).unwrap();
+        // One part only has negative effect sizes and the other only positive ones
+        let parts = split(&data, |o| o.effect_size < 0.0);
+        let results: Vec<FilteredData> = parts
+            .iter()
+            .map(|part| filter_coverage_data(&Filter::new(), part, None).unwrap())
+            .collect();
+        assert_eq!(results[0].numeric_intervals.effect_positive, None);
+        assert_eq!(results[1].numeric_intervals.effect_negative, None);
+
+        let merged = merge_filtered_data(results, chromosome_list).unwrap();
+        assert_eq!(
+            merged.numeric_intervals.effect_negative,
+            union.numeric_intervals.effect_negative
+        );
+        assert_eq!(
+            merged.numeric_intervals.effect_positive,
+            union.numeric_intervals.effect_positive
+        );
+        assert_eq!(
+            merged.numeric_intervals.effect,
+            union.numeric_intervals.effect
+        );
     }
 }
@@ -0,0 +1,260 @@
+use std::io::{self, Write};
+
+use rayon::prelude::*;
+use rustc_hash::FxHashMap;
+use serde::Serialize;
+
+use crate::error::FilterError;
+use crate::filter::ObservationFilter;
+use crate::filter_data_structures::*;
+use cov_viz_ds::{CoverageData, DbID, ExperimentFeatureData};
+
+// A source-target pair and the statistics of the observations that link them. Features are
+// only located to a bucket, so the coordinates are those of the bucket each feature is in
+// (1-based, inclusive).
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct InteractionPair {
+    pub source_id: DbID,
+    pub target_id: DbID,
+    pub source_chrom: String,
+    pub source_start: u64,
+    pub source_end: u64,
+    pub target_chrom: String,
+    pub target_start: u64,
+    pub target_end: u64,
+    pub observation_count: u64,
+    pub max_log10_sig: f64,
+    pub max_abs_effect: f32,
+}
+
+struct PairStats {
+    observation_count: u64,
+    max_log10_sig: f64,
+    max_abs_effect: f32,
+}
+
+impl PairStats {
+    fn merge(&mut self, other: PairStats) {
+        self.observation_count += other.observation_count;
+        self.max_log10_sig = self.max_log10_sig.max(other.max_log10_sig);
+        self.max_abs_effect = max_abs_effect(self.max_abs_effect, other.max_abs_effect);
+    }
+}
+
+// The (chromosome name, start, end) of the bucket a feature is in
+fn feature_location(data: &CoverageData, feature_id: DbID) -> Option<(String, u64, u64)> {
+    let bucket = data.feature_buckets.get(&feature_id)?;
+    let chromosome = data.chromosomes.iter().find(|c| c.index == bucket.chrom)?;
+    let start = bucket.idx as u64 * data.bucket_size as u64 + 1;
+    Some((
+        chromosome.chrom.clone(),
+        start,
+        start + data.bucket_size as u64 - 1,
+    ))
+}
+
+// The source-target pairs of the observations that pass the filter, most significant first
+// (ties are broken by source id, then target id). If there's a limit only the first `limit`
+// are returned. Observations without a target are left out, as are pairs where either feature
+// isn't on one of the data set's chromosomes. When the filter is restricted to a chromosome,
+// only pairs with a source or target on it are included.
+pub fn significant_pairs(
+    filters: &Filter,
+    data: &CoverageData,
+    included_features: Option<&ExperimentFeatureData>,
+    limit: Option<usize>,
+) -> Result<Vec<InteractionPair>, FilterError> {
+    let chrom = filters.chromosome_index(&data.chromosomes)?;
+    let predicate = ObservationFilter::new(filters, data, included_features);
+    let on_chromosome = |feature_id: &DbID| {
+        data.feature_buckets
+            .get(feature_id)
+            .is_some_and(|bucket| Some(bucket.chrom) == chrom)
+    };
+
+    let pairs = predicate
+        .observations(data)
+        .filter(|observation| predicate.matches(observation))
+        .filter_map(|observation| {
+            let target_id = observation.target_id?;
+            if chrom.is_some()
+                && !on_chromosome(&observation.source_id)
+                && !on_chromosome(&target_id)
+            {
+                return None;
+            }
+            Some((observation, target_id))
+        })
+        .fold(
+            FxHashMap::default,
+            |mut pairs: FxHashMap<(DbID, DbID), PairStats>, (observation, target_id)| {
+                let stats = PairStats {
+                    observation_count: 1,
+                    max_log10_sig: predicate.significance(observation),
+                    max_abs_effect: observation.effect_size,
+                };
+                match pairs.get_mut(&(observation.source_id, target_id)) {
+                    Some(pair_stats) => pair_stats.merge(stats),
+                    None => {
+                        pairs.insert((observation.source_id, target_id), stats);
+                    }
+                }
+                pairs
+            },
+        )
+        .reduce(FxHashMap::default, |mut pairs1, pairs2| {
+            for (pair, stats) in pairs2 {
+                match pairs1.get_mut(&pair) {
+                    Some(pair_stats) => pair_stats.merge(stats),
+                    None => {
+                        pairs1.insert(pair, stats);
+                    }
+                }
+            }
+            pairs1
+        });
+
+    let mut pairs: Vec<((DbID, DbID), PairStats)> = pairs.into_iter().collect();
+    pairs.par_sort_unstable_by(|(pair1, stats1), (pair2, stats2)| {
+        stats2
+            .max_log10_sig
+            .total_cmp(&stats1.max_log10_sig)
+            .then(pair1.cmp(pair2))
+    });
+
+    let interaction_pairs = pairs
+        .into_iter()
+        .filter_map(|((source_id, target_id), stats)| {
+            let (source_chrom, source_start, source_end) = feature_location(data, source_id)?;
+            let (target_chrom, target_start, target_end) = feature_location(data, target_id)?;
+            Some(InteractionPair {
+                source_id,
+                target_id,
+                source_chrom,
+                source_start,
+                source_end,
+                target_chrom,
+                target_start,
+                target_end,
+                observation_count: stats.observation_count,
+                max_log10_sig: stats.max_log10_sig,
+                max_abs_effect: stats.max_abs_effect,
+            })
+        });
+
+    Ok(match limit {
+        Some(limit) => interaction_pairs.take(limit).collect(),
+        None => interaction_pairs.collect(),
+    })
+}
+
+// Writes the pairs as tab-separated values, with a header line
+pub fn write_tsv<W: Write>(pairs: &[InteractionPair], mut writer: W) -> io::Result<()> {
+    writeln!(
+        writer,
+        "source_id\ttarget_id\tsource_chrom\tsource_start\tsource_end\ttarget_chrom\ttarget_start\ttarget_end\tobservation_count\tmax_log10_sig\tmax_abs_effect"
+    )?;
+    for pair in pairs {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            pair.source_id,
+            pair.target_id,
+            pair.source_chrom,
+            pair.source_start,
+            pair.source_end,
+            pair.target_chrom,
+            pair.target_start,
+            pair.target_end,
+            pair.observation_count,
+            pair.max_log10_sig,
+            pair.max_abs_effect
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::coverage_data;
+    use cov_viz_ds::ObservationData;
+
+    #[test]
+    fn test_pair_counts() {
+        let mut data = coverage_data(20);
+        // A pair with many observations
+        let paired = data
+            .significant_observations
+            .iter()
+            .find(|o| o.target_id.is_some())
+            .unwrap()
+            .clone();
+        for i in 0..30 {
+            data.significant_observations.push(ObservationData {
+                reo_id: 1_000_000 + i,
+                ..paired.clone()
+            });
+        }
+        let observations = || {
+            data.significant_observations
+                .iter()
+                .chain(data.nonsignificant_observations.iter())
+        };
+
+        let pairs = significant_pairs(&Filter::new(), &data, None, None).unwrap();
+        let mut expected: FxHashMap<(DbID, DbID), u64> = FxHashMap::default();
+        for observation in observations() {
+            if let Some(target_id) = observation.target_id {
+                *expected
+                    .entry((observation.source_id, target_id))
+                    .or_default() += 1;
+            }
+        }
+        assert_eq!(pairs.len(), expected.len());
+        for pair in &pairs {
+            assert_eq!(
+                pair.observation_count,
+                expected[&(pair.source_id, pair.target_id)]
+            );
+        }
+        // Observations without targets are left out
+        let with_targets = observations().filter(|o| o.target_id.is_some()).count() as u64;
+        assert!(with_targets < observations().count() as u64);
+        assert_eq!(
+            pairs.iter().map(|p| p.observation_count).sum::<u64>(),
+            with_targets
+        );
+
+        let pair = pairs
+            .iter()
+            .find(|p| p.source_id == paired.source_id && Some(p.target_id) == paired.target_id)
+            .unwrap();
+        assert!(pair.observation_count > 30);
+        let bucket = data.feature_buckets[&pair.source_id];
+        assert_eq!(pair.source_start, bucket.idx as u64 * 1_000 + 1);
+        assert_eq!(pair.source_end, pair.source_start + 999);
+
+        assert!(pairs
+            .windows(2)
+            .all(|w| w[0].max_log10_sig >= w[1].max_log10_sig));
+        let limited = significant_pairs(&Filter::new(), &data, None, Some(10)).unwrap();
+        assert_eq!(limited, pairs[..10]);
+    }
+
+    #[test]
+    fn test_write_tsv() {
+        let data = coverage_data(20);
+        let pairs = significant_pairs(&Filter::new(), &data, None, Some(3)).unwrap();
+        let mut tsv = Vec::new();
+        write_tsv(&pairs, &mut tsv).unwrap();
+        let tsv = String::from_utf8(tsv).unwrap();
+        let lines: Vec<&str> = tsv.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("source_id\ttarget_id\t"));
+        let fields: Vec<&str> = lines[1].split('\t').collect();
+        assert_eq!(fields.len(), 11);
+        assert_eq!(fields[0], pairs[0].source_id.to_string());
+        assert_eq!(fields[2], pairs[0].source_chrom);
+    }
+}
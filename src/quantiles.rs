@@ -0,0 +1,164 @@
+use rayon::prelude::*;
+use serde::Serialize;
+
+use cov_viz_ds::CoverageData;
+
+// Quantiles of the observations' effect sizes and significances, in the same order as the
+// requested quantiles. A quantile is None when there are no finite values.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct QuantileReport {
+    pub quantiles: Vec<f64>,
+    pub effect_size: Vec<Option<f32>>,
+    pub neg_log_significance: Vec<Option<f64>>,
+    // Values that aren't finite are left out of the quantiles
+    pub non_finite_effect_sizes: u64,
+    pub non_finite_significances: u64,
+}
+
+// The quantile of sorted values, linearly interpolating between the two closest values.
+// Quantiles outside [0, 1] are clamped.
+fn quantile(sorted: &[f64], q: f64) -> Option<f64> {
+    if sorted.is_empty() || q.is_nan() {
+        return None;
+    }
+    let position = q.clamp(0.0, 1.0) * (sorted.len() - 1) as f64;
+    let lower = position.floor() as usize;
+    let upper = position.ceil() as usize;
+    Some(sorted[lower] + (sorted[upper] - sorted[lower]) * (position - lower as f64))
+}
+
+// The finite values, sorted, and how many values weren't finite
+fn sorted_finite<I>(values: I) -> (Vec<f64>, u64)
+where
+    I: ParallelIterator<Item = f64>,
+{
+    let (mut finite, non_finite): (Vec<f64>, Vec<f64>) =
+        values.partition(|value| value.is_finite());
+    finite.par_sort_unstable_by(f64::total_cmp);
+    (finite, non_finite.len() as u64)
+}
+
+// Computes exact quantiles (between 0 and 1) of the effect sizes and significances. Every
+// value is collected and sorted, so this needs 16 bytes of memory per observation.
+pub fn numeric_quantiles(
+    data: &CoverageData,
+    quantiles: &[f64],
+    include_nonsignificant: bool,
+) -> QuantileReport {
+    let nonsignificant_observations = if include_nonsignificant {
+        &data.nonsignificant_observations[..]
+    } else {
+        &[]
+    };
+    let observations = || {
+        data.significant_observations
+            .par_iter()
+            .chain(nonsignificant_observations.par_iter())
+    };
+
+    let (effect_sizes, non_finite_effect_sizes) =
+        sorted_finite(observations().map(|observation| observation.effect_size as f64));
+    let (significances, non_finite_significances) =
+        sorted_finite(observations().map(|observation| observation.neg_log_significance));
+
+    QuantileReport {
+        quantiles: quantiles.to_vec(),
+        effect_size: quantiles
+            .iter()
+            .map(|q| quantile(&effect_sizes, *q).map(|effect_size| effect_size as f32))
+            .collect(),
+        neg_log_significance: quantiles
+            .iter()
+            .map(|q| quantile(&significances, *q))
+            .collect(),
+        non_finite_effect_sizes,
+        non_finite_significances,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::{add_nan_values, coverage_data};
+
+    // Sorts every finite value and interpolates by hand
+    fn naive_quantile(values: &[f64], q: f64) -> f64 {
+        let mut values: Vec<f64> = values.iter().copied().filter(|v| v.is_finite()).collect();
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let position = q * (values.len() - 1) as f64;
+        let lower = position as usize;
+        let upper = (lower + 1).min(values.len() - 1);
+        let fraction = position - lower as f64;
+        values[lower] * (1.0 - fraction) + values[upper] * fraction
+    }
+
+    #[test]
+    fn test_quantiles_match_naive_values() {
+        let mut data = coverage_data(2);
+        add_nan_values(&mut data, 7, 11);
+        data.significant_observations[1].effect_size = f32::INFINITY;
+        data.significant_observations[2].neg_log_significance = f64::NEG_INFINITY;
+        let quantiles = [0.0, 0.1, 0.25, 0.5, 0.9, 1.0];
+
+        for include_nonsignificant in [false, true] {
+            let mut observations = data.significant_observations.clone();
+            if include_nonsignificant {
+                observations.extend(data.nonsignificant_observations.iter().cloned());
+            }
+            let effect_sizes: Vec<f64> =
+                observations.iter().map(|o| o.effect_size as f64).collect();
+            let significances: Vec<f64> = observations
+                .iter()
+                .map(|o| o.neg_log_significance)
+                .collect();
+
+            let report = numeric_quantiles(&data, &quantiles, include_nonsignificant);
+            assert_eq!(report.quantiles, quantiles);
+            for (i, q) in quantiles.iter().enumerate() {
+                let effect_size = report.effect_size[i].unwrap();
+                let expected = naive_quantile(&effect_sizes, *q) as f32;
+                assert!((effect_size - expected).abs() <= 1e-6, "{q}");
+                let significance = report.neg_log_significance[i].unwrap();
+                let expected = naive_quantile(&significances, *q);
+                assert!((significance - expected).abs() <= 1e-9, "{q}");
+            }
+
+            // 0 and 1 are exactly the smallest and largest finite values
+            let finite_effect_sizes = effect_sizes.iter().filter(|v| v.is_finite());
+            assert_eq!(
+                report.effect_size[0],
+                finite_effect_sizes
+                    .clone()
+                    .copied()
+                    .reduce(f64::min)
+                    .map(|v| v as f32)
+            );
+            assert_eq!(
+                report.effect_size[5],
+                finite_effect_sizes
+                    .copied()
+                    .reduce(f64::max)
+                    .map(|v| v as f32)
+            );
+
+            let non_finite = |values: &[f64]| values.iter().filter(|v| !v.is_finite()).count();
+            assert_eq!(
+                report.non_finite_effect_sizes as usize,
+                non_finite(&effect_sizes)
+            );
+            assert_eq!(
+                report.non_finite_significances as usize,
+                non_finite(&significances)
+            );
+            assert!(report.non_finite_effect_sizes > 1);
+            assert!(report.non_finite_significances > 1);
+        }
+
+        // Without any finite values there are no quantiles
+        let mut data = coverage_data(2);
+        add_nan_values(&mut data, 1, 1);
+        let report = numeric_quantiles(&data, &[0.0, 0.5], true);
+        assert_eq!(report.effect_size, vec![None, None]);
+        assert_eq!(report.neg_log_significance, vec![None, None]);
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+
+use rustc_hash::FxHashMap;
+
+use crate::error::RebucketError;
+use crate::filter_data_structures::*;
+
+fn check_multiple(bucket_size: u32, target_bucket_size: u32) -> Result<u32, RebucketError> {
+    if bucket_size == 0 || target_bucket_size == 0 || target_bucket_size % bucket_size != 0 {
+        return Err(RebucketError::NotAMultiple {
+            bucket_size,
+            target_bucket_size,
+        });
+    }
+    Ok(target_bucket_size / bucket_size)
+}
+
+// Combines the buckets in each `target_bucket_size` region into a single bucket. Every feature
+// is in exactly one bucket, so the combined feature count is the sum of the counts.
+// `factors` is how many buckets of each chromosome go into a new bucket, for converting
+// associated buckets; chromosomes that aren't in it use `default_factor`.
+fn rebucket_intervals(
+    intervals: &[FilteredBucket],
+    target_bucket_size: u32,
+    factors: &FxHashMap<u32, u32>,
+    default_factor: u32,
+) -> Vec<FilteredBucket> {
+    let mut buckets: BTreeMap<u64, FilteredBucket> = BTreeMap::new();
+    for bucket in intervals {
+        let idx = (bucket.start - 1) / (target_bucket_size as u64);
+        let associated_buckets = bucket.associated_buckets.chunks_exact(2).flat_map(|loc| {
+            let factor = factors.get(&loc[0]).copied().unwrap_or(default_factor);
+            [loc[0], loc[1] / factor]
+        });
+
+        match buckets.get_mut(&idx) {
+            Some(new_bucket) => {
+                new_bucket.count += bucket.count;
+                new_bucket.associated_buckets.extend(associated_buckets);
+                new_bucket.max_log10_sig = new_bucket.max_log10_sig.max(bucket.max_log10_sig);
+                new_bucket.max_abs_effect =
+                    max_abs_effect(new_bucket.max_abs_effect, bucket.max_abs_effect);
+                new_bucket.count_is_lower_bound |= bucket.count_is_lower_bound;
+                new_bucket.associated_buckets_truncated |= bucket.associated_buckets_truncated;
+                new_bucket.sig_sum = add_sig_sums(new_bucket.sig_sum, bucket.sig_sum);
+                new_bucket.over_threshold_count += bucket.over_threshold_count;
+                new_bucket.annotations =
+                    merge_annotations(&new_bucket.annotations, &bucket.annotations);
+            }
+            None => {
+                buckets.insert(
+                    idx,
+                    FilteredBucket {
+                        start: idx * target_bucket_size as u64 + 1,
+                        count: bucket.count,
+                        associated_buckets: associated_buckets.collect(),
+                        max_log10_sig: bucket.max_log10_sig,
+                        max_abs_effect: bucket.max_abs_effect,
+                        count_is_lower_bound: bucket.count_is_lower_bound,
+                        annotations: bucket.annotations.clone(),
+                        associated_buckets_truncated: bucket.associated_buckets_truncated,
+                        sig_sum: bucket.sig_sum,
+                        over_threshold_count: bucket.over_threshold_count,
+                    },
+                );
+            }
+        }
+    }
+
+    buckets
+        .into_values()
+        .map(|mut bucket| {
+            // Several of the smaller associated buckets may now be the same bucket
+            bucket.associated_buckets = sorted_associated_buckets(
+                bucket
+                    .associated_buckets
+                    .chunks_exact(2)
+                    .map(|loc| (loc[0], loc[1])),
+            );
+            bucket
+        })
+        .collect()
+}
+
+// Aggregates filtered data into coarser buckets, for zoom levels that weren't precomputed.
+// `target_bucket_size` must be a multiple of the data's bucket size. Counts are summed, the
+// significance is the max, and the effect size follows the usual max_abs_effect rule.
+// Associated bucket indexes are converted to the new size and deduplicated. Sliding window
+// data can't be rebucketed, since summing overlapping windows would count features more
+// than once.
+pub fn rebucket_filtered_data(
+    data: &FilteredData,
+    target_bucket_size: u32,
+) -> Result<FilteredData, RebucketError> {
+    if data.window_step.is_some() {
+        return Err(RebucketError::WindowedData);
+    }
+    let data_factor = check_multiple(data.bucket_size, target_bucket_size)?;
+
+    // How many of a chromosome's buckets go into each new bucket, by chromosome index.
+    // Associated buckets on chromosomes that aren't in the data use the data's bucket size.
+    let mut factors: FxHashMap<u32, u32> = FxHashMap::default();
+    for chromosome in &data.chromosomes {
+        factors.insert(
+            chromosome.index as u32,
+            check_multiple(chromosome.bucket_size, target_bucket_size)?,
+        );
+    }
+
+    let chromosomes = data
+        .chromosomes
+        .iter()
+        .map(|chromosome| FilteredChromosome {
+            chrom: chromosome.chrom.clone(),
+            index: chromosome.index,
+            bucket_size: target_bucket_size,
+            source_intervals: rebucket_intervals(
+                &chromosome.source_intervals,
+                target_bucket_size,
+                &factors,
+                data_factor,
+            ),
+            target_intervals: rebucket_intervals(
+                &chromosome.target_intervals,
+                target_bucket_size,
+                &factors,
+                data_factor,
+            ),
+            truncated: chromosome.truncated,
+            bucket_count: chromosome
+                .bucket_count
+                .div_ceil(factors[&(chromosome.index as u32)]),
+        })
+        .collect();
+
+    Ok(FilteredData {
+        chromosomes,
+        bucket_size: target_bucket_size,
+        numeric_intervals: data.numeric_intervals,
+        reo_count: data.reo_count,
+        dropped_invalid: data.dropped_invalid,
+        dropped_buckets: data.dropped_buckets,
+        window_step: None,
+        approximate: data.approximate,
+        counts_approximate: data.counts_approximate,
+        annotation_names: data.annotation_names.clone(),
+        omitted_empty: data.omitted_empty.clone(),
+        warnings: data.warnings.clone(),
+        sources: data.sources.clone(),
+        targets: data.targets.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::filter_coverage_data;
+    use crate::test_data::{assert_same_results, coverage_data, facet_value_id};
+    use cov_viz_ds::CoverageData;
+
+    // The same data with buckets `factor` times as large
+    fn coarse_data(data: &CoverageData, factor: u32) -> CoverageData {
+        let mut coarse = data.clone();
+        coarse.bucket_size *= factor;
+        for chromosome in &mut coarse.chromosomes {
+            chromosome.bucket_size *= factor;
+        }
+        for bucket in coarse.feature_buckets.values_mut() {
+            bucket.idx /= factor;
+        }
+        coarse
+    }
+
+    #[test]
+    fn test_rebucketing_matches_coarse_data() {
+        let data = coverage_data(12);
+        let coarse = coarse_data(&data, 10);
+        let mut filters = Filter::new();
+        filters.categorical_facets = [facet_value_id(&data, "Library", "Library 2")]
+            .into_iter()
+            .collect();
+        filters.sig_threshold = Some(5.0);
+
+        for filters in [Filter::new(), filters] {
+            let fine = filter_coverage_data(&filters, &data, None).unwrap();
+            let rebucketed = rebucket_filtered_data(&fine, 10_000).unwrap();
+            assert_same_results(
+                &rebucketed,
+                &filter_coverage_data(&filters, &coarse, None).unwrap(),
+            );
+            // The chromosomes' 50 buckets aren't a multiple of 3, so the last coarse bucket
+            // is only partly covered
+            assert_same_results(
+                &rebucket_filtered_data(&fine, 3_000).unwrap(),
+                &filter_coverage_data(&filters, &coarse_data(&data, 3), None).unwrap(),
+            );
+            // Rebucketing to the same size changes nothing
+            assert_same_results(&rebucket_filtered_data(&fine, 1_000).unwrap(), &fine);
+        }
+    }
+
+    #[test]
+    fn test_rebucketing_errors() {
+        let data = coverage_data(12);
+        let filtered = filter_coverage_data(&Filter::new(), &data, None).unwrap();
+        for target_bucket_size in [0, 1_500, 500] {
+            assert_eq!(
+                rebucket_filtered_data(&filtered, target_bucket_size).unwrap_err(),
+                RebucketError::NotAMultiple {
+                    bucket_size: 1_000,
+                    target_bucket_size
+                }
+            );
+        }
+
+        let windowed = filter_coverage_data(&Filter::new().window_step(500), &data, None).unwrap();
+        assert_eq!(
+            rebucket_filtered_data(&windowed, 10_000).unwrap_err(),
+            RebucketError::WindowedData
+        );
+    }
+}
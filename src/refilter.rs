@@ -0,0 +1,355 @@
+use rustc_hash::FxHashSet;
+
+use crate::filter_data_structures::*;
+
+fn bucket_passes(
+    bucket: &FilteredBucket,
+    sig_min: Option<f64>,
+    effect_abs_min: Option<f32>,
+) -> bool {
+    sig_min.is_none_or(|sig_min| bucket.max_log10_sig >= sig_min)
+        && effect_abs_min.is_none_or(|effect_abs_min| bucket.max_abs_effect.abs() >= effect_abs_min)
+}
+
+// The (chromosome index, bucket index) of every bucket in the intervals. A sliding window
+// that doesn't start on a bucket boundary covers two buckets.
+fn bucket_locations<'a, I>(chromosomes: I) -> FxHashSet<(u32, u32)>
+where
+    I: IntoIterator<Item = (&'a FilteredChromosome, &'a Vec<FilteredBucket>)>,
+{
+    chromosomes
+        .into_iter()
+        .flat_map(|(chromosome, intervals)| {
+            intervals.iter().flat_map(move |bucket| {
+                let offset = bucket.start - 1;
+                let bucket_size = chromosome.bucket_size as u64;
+                let first_bucket = offset / bucket_size;
+                let last_bucket = if offset % bucket_size == 0 {
+                    first_bucket
+                } else {
+                    first_bucket + 1
+                };
+                (first_bucket..=last_bucket).map(move |idx| (chromosome.index as u32, idx as u32))
+            })
+        })
+        .collect()
+}
+
+// Removes associated buckets on the chromosomes in `chromosomes` that aren't in `remaining`.
+// Buckets on other chromosomes (e.g., when the data was filtered to a single chromosome) are
+// kept. Associated buckets are stored as a flat list of (chromosome index, bucket index) pairs.
+fn prune_associated_buckets(
+    intervals: &mut [FilteredBucket],
+    chromosomes: &FxHashSet<u32>,
+    remaining: &FxHashSet<(u32, u32)>,
+) {
+    for bucket in intervals {
+        bucket.associated_buckets = bucket
+            .associated_buckets
+            .chunks_exact(2)
+            .filter(|loc| !chromosomes.contains(&loc[0]) || remaining.contains(&(loc[0], loc[1])))
+            .flatten()
+            .cloned()
+            .collect();
+    }
+}
+
+// Re-thresholds already filtered data without going back to the observations: only buckets
+// whose most significant observation has a significance of at least `sig_min` and whose
+// largest absolute effect size is at least `effect_abs_min` are kept. This is
+// FilteredData::refilter with both thresholds; see it for how the result differs from
+// filtering the original data with the same thresholds.
+pub fn refilter_filtered_data(
+    data: &FilteredData,
+    sig_min: f64,
+    effect_abs_min: f32,
+) -> FilteredData {
+    data.refilter(Some(sig_min), Some(effect_abs_min))
+}
+
+fn refilter_buckets(
+    data: &FilteredData,
+    sig_min: Option<f64>,
+    effect_abs_min: Option<f32>,
+) -> FilteredData {
+    let mut chromosomes: Vec<FilteredChromosome> = data
+        .chromosomes
+        .iter()
+        .map(|chromosome| FilteredChromosome {
+            chrom: chromosome.chrom.clone(),
+            index: chromosome.index,
+            bucket_size: chromosome.bucket_size,
+            source_intervals: chromosome
+                .source_intervals
+                .iter()
+                .filter(|bucket| bucket_passes(bucket, sig_min, effect_abs_min))
+                .cloned()
+                .collect(),
+            target_intervals: chromosome
+                .target_intervals
+                .iter()
+                .filter(|bucket| bucket_passes(bucket, sig_min, effect_abs_min))
+                .cloned()
+                .collect(),
+            truncated: chromosome.truncated,
+            bucket_count: chromosome.bucket_count,
+        })
+        .collect();
+
+    let sources = bucket_locations(chromosomes.iter().map(|c| (c, &c.source_intervals)));
+    let targets = bucket_locations(chromosomes.iter().map(|c| (c, &c.target_intervals)));
+    let chromosome_indices: FxHashSet<u32> = chromosomes.iter().map(|c| c.index as u32).collect();
+    for chromosome in &mut chromosomes {
+        // Source buckets are associated with target buckets, and vice versa
+        prune_associated_buckets(
+            &mut chromosome.source_intervals,
+            &chromosome_indices,
+            &targets,
+        );
+        prune_associated_buckets(
+            &mut chromosome.target_intervals,
+            &chromosome_indices,
+            &sources,
+        );
+    }
+
+    FilteredData {
+        chromosomes,
+        bucket_size: data.bucket_size,
+        numeric_intervals: data.numeric_intervals,
+        reo_count: data.reo_count,
+        dropped_invalid: data.dropped_invalid,
+        dropped_buckets: data.dropped_buckets,
+        window_step: data.window_step,
+        approximate: data.approximate,
+        counts_approximate: data.counts_approximate,
+        annotation_names: data.annotation_names.clone(),
+        omitted_empty: data.omitted_empty.clone(),
+        warnings: data.warnings.clone(),
+        sources: data.sources.clone(),
+        targets: data.targets.clone(),
+    }
+}
+
+// The numeric intervals of the buckets' most significant observations and largest effect
+// sizes, or None if there aren't any buckets
+fn bucket_intervals(chromosomes: &[FilteredChromosome]) -> Option<FilterIntervals> {
+    let buckets = chromosomes
+        .iter()
+        .flat_map(|c| c.source_intervals.iter().chain(c.target_intervals.iter()));
+    let mut intervals: Option<FilterIntervals> = None;
+    for bucket in buckets {
+        let effect = bucket.max_abs_effect;
+        let sig = bucket.max_log10_sig;
+        let intervals = intervals.get_or_insert(FilterIntervals {
+            effect: (effect, effect),
+            sig: (sig, sig),
+            effect_negative: None,
+            effect_positive: None,
+        });
+        intervals.effect = (
+            intervals.effect.0.min(effect),
+            intervals.effect.1.max(effect),
+        );
+        intervals.sig = (intervals.sig.0.min(sig), intervals.sig.1.max(sig));
+        let sign_range = if effect < 0.0 {
+            &mut intervals.effect_negative
+        } else if effect > 0.0 {
+            &mut intervals.effect_positive
+        } else {
+            continue;
+        };
+        *sign_range = Some(match sign_range {
+            Some((min, max)) => (min.min(effect), max.max(effect)),
+            None => (effect, effect),
+        });
+    }
+    intervals
+}
+
+impl FilteredData {
+    // Re-thresholds the data without going back to the observations: only buckets whose most
+    // significant observation has a significance of at least `min_sig` and whose largest
+    // absolute effect size is at least `min_abs_effect` are kept, and associated buckets that
+    // no longer exist are dropped. Either threshold can be left out.
+    //
+    // This is an upper bound of filtering the original data with the same thresholds, never
+    // a lower one. With one threshold, the same buckets are kept. With both, a bucket is kept
+    // when its most significant observation passes `min_sig` and its largest effect passes
+    // `min_abs_effect`, even if no single observation in it passes both.
+    //
+    // The numeric intervals are recomputed from the buckets that are left (if any), so they
+    // are the ranges of the buckets' most significant values and largest effect sizes rather
+    // than of every observation. Buckets don't keep track of their features, so the counts,
+    // sources, and targets can't be updated, and `counts_approximate` is set. The REO count
+    // and dropped observation and bucket counts are left as they were. Without either
+    // threshold the data is returned as it is.
+    pub fn refilter(&self, min_sig: Option<f64>, min_abs_effect: Option<f32>) -> FilteredData {
+        if min_sig.is_none() && min_abs_effect.is_none() {
+            return self.clone();
+        }
+
+        let mut refiltered = refilter_buckets(self, min_sig, min_abs_effect);
+        if let Some(intervals) = bucket_intervals(&refiltered.chromosomes) {
+            refiltered.numeric_intervals = intervals;
+        }
+        refiltered.counts_approximate = true;
+        refiltered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::filter_coverage_data;
+    use crate::test_data::{add_nan_values, assert_same_results, coverage_data};
+    use cov_viz_ds::CoverageData;
+
+    // (chromosome index, is a source, start) of every bucket, with the bucket
+    fn buckets(data: &FilteredData) -> Vec<((u8, bool, u64), &FilteredBucket)> {
+        let mut buckets = Vec::new();
+        for chromosome in &data.chromosomes {
+            for (is_source, intervals) in [
+                (true, &chromosome.source_intervals),
+                (false, &chromosome.target_intervals),
+            ] {
+                for bucket in intervals {
+                    buckets.push(((chromosome.index, is_source, bucket.start), bucket));
+                }
+            }
+        }
+        buckets
+    }
+
+    // Checks that `refiltered` keeps every bucket `full` has, with at least as many features
+    // and associated buckets, and returns whether it kept exactly the same buckets
+    fn is_upper_bound(refiltered: &FilteredData, full: &FilteredData) -> bool {
+        let refiltered_buckets = buckets(refiltered);
+        let full_buckets = buckets(full);
+        for (loc, bucket) in &full_buckets {
+            let (_, refiltered_bucket) = refiltered_buckets
+                .iter()
+                .find(|(refiltered_loc, _)| refiltered_loc == loc)
+                .unwrap();
+            assert!(refiltered_bucket.count >= bucket.count);
+            for associated_bucket in bucket.associated_buckets.chunks_exact(2) {
+                assert!(refiltered_bucket
+                    .associated_buckets
+                    .chunks_exact(2)
+                    .any(|loc| loc == associated_bucket));
+            }
+        }
+        refiltered_buckets.len() == full_buckets.len()
+    }
+
+    fn filtered(data: &CoverageData, sig: f64, effect: f32) -> FilteredData {
+        let filters = Filter::new().numeric_intervals(FilterIntervals {
+            effect: (effect, f32::INFINITY),
+            sig: (sig, f64::INFINITY),
+            ..FilterIntervals::new()
+        });
+        filter_coverage_data(&filters, data, None).unwrap()
+    }
+
+    #[test]
+    fn test_refilter_matches_full_filter() {
+        let mut data = coverage_data(15);
+        add_nan_values(&mut data, 53, 59);
+        // With only positive effect sizes, a lower bound on the effect size is the same as a
+        // lower bound on the absolute effect size
+        for observation in data
+            .significant_observations
+            .iter_mut()
+            .chain(data.nonsignificant_observations.iter_mut())
+        {
+            observation.effect_size = observation.effect_size.abs();
+        }
+        let unfiltered = filter_coverage_data(&Filter::new(), &data, None).unwrap();
+
+        // With one threshold, refiltering keeps the same buckets as filtering the data
+        for min_sig in [1.0, 4.0, 8.0] {
+            let refiltered = unfiltered.refilter(Some(min_sig), None);
+            let full = filtered(&data, min_sig, f32::NEG_INFINITY);
+            assert!(buckets(&refiltered).len() < buckets(&unfiltered).len());
+            assert!(is_upper_bound(&refiltered, &full));
+            assert!(refiltered.numeric_intervals.sig.0 >= min_sig);
+            assert!(refiltered.counts_approximate);
+            assert!(!refiltered.approximate);
+        }
+        for min_abs_effect in [0.5, 1.5, 3.0] {
+            let refiltered = unfiltered.refilter(None, Some(min_abs_effect));
+            let full = filtered(&data, f64::NEG_INFINITY, min_abs_effect);
+            assert!(is_upper_bound(&refiltered, &full));
+            assert_eq!(
+                refiltered.numeric_intervals.effect_positive.unwrap().1,
+                full.numeric_intervals.effect.1
+            );
+        }
+
+        // With both, it can keep buckets where no observation passes both thresholds
+        let mut supersets = 0;
+        for (min_sig, min_abs_effect) in [(1.0, 0.5), (4.0, 1.5), (8.0, 3.0)] {
+            let refiltered = unfiltered.refilter(Some(min_sig), Some(min_abs_effect));
+            let full = filtered(&data, min_sig, min_abs_effect);
+            supersets += !is_upper_bound(&refiltered, &full) as usize;
+            assert_same_results(
+                &refilter_filtered_data(&unfiltered, min_sig, min_abs_effect),
+                &refiltered,
+            );
+        }
+        assert!(supersets > 0);
+
+        // Without any thresholds nothing changes
+        let unchanged = unfiltered.refilter(None, None);
+        assert!(!unchanged.counts_approximate);
+        assert_same_results(&unchanged, &unfiltered);
+    }
+
+    #[test]
+    fn test_refiltered_associated_buckets_exist() {
+        let unfiltered = filter_coverage_data(&Filter::new(), &coverage_data(16), None).unwrap();
+        let refiltered = unfiltered.refilter(Some(5.0), Some(1.0));
+        let sources = bucket_locations(
+            refiltered
+                .chromosomes
+                .iter()
+                .map(|c| (c, &c.source_intervals)),
+        );
+        let targets = bucket_locations(
+            refiltered
+                .chromosomes
+                .iter()
+                .map(|c| (c, &c.target_intervals)),
+        );
+        for chromosome in &refiltered.chromosomes {
+            for (intervals, associated) in [
+                (&chromosome.source_intervals, &targets),
+                (&chromosome.target_intervals, &sources),
+            ] {
+                for bucket in intervals {
+                    for loc in bucket.associated_buckets.chunks_exact(2) {
+                        assert!(associated.contains(&(loc[0], loc[1])));
+                    }
+                }
+            }
+        }
+
+        // The flag survives serialization and merging
+        let json = serde_json::to_string(&refiltered).unwrap();
+        let round_trip: FilteredData = serde_json::from_str(&json).unwrap();
+        assert!(round_trip.counts_approximate);
+        let bytes = bincode::serialize(&refiltered).unwrap();
+        let round_trip: FilteredData = bincode::deserialize(&bytes).unwrap();
+        assert!(round_trip.counts_approximate);
+        let chromosome_list = unfiltered
+            .chromosomes
+            .iter()
+            .map(|c| c.chrom.clone())
+            .collect();
+        let merged =
+            crate::merge::merge_filtered_data(vec![unfiltered, refiltered], chromosome_list)
+                .unwrap();
+        assert!(merged.counts_approximate);
+        assert!(!merged.approximate);
+    }
+}
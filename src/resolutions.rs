@@ -0,0 +1,168 @@
+use rustc_hash::FxHashMap;
+
+use crate::error::FilterError;
+use crate::filter::{
+    bucket_matching_observations, build_filtered_data_with, coarsen_bucket_results,
+    empty_chromosomes, BucketScratch, ObservationFilter,
+};
+use crate::filter_data_structures::*;
+use cov_viz_ds::{BucketLoc, CoverageData, DbID, ExperimentFeatureData};
+
+// The number of the data's buckets in each bucket of `resolution`
+fn resolution_factor(bucket_size: u32, resolution: u32) -> Result<u32, FilterError> {
+    if bucket_size == 0 || resolution == 0 || resolution % bucket_size != 0 {
+        return Err(FilterError::InvalidResolution {
+            bucket_size,
+            resolution,
+        });
+    }
+    Ok(resolution / bucket_size)
+}
+
+// The location of each feature in buckets `factor` times as large as the data's
+fn coarsen_feature_buckets(
+    feature_buckets: &FxHashMap<DbID, BucketLoc>,
+    factor: u32,
+) -> FxHashMap<DbID, BucketLoc> {
+    feature_buckets
+        .iter()
+        .map(|(id, loc)| {
+            (
+                *id,
+                BucketLoc {
+                    chrom: loc.chrom,
+                    idx: loc.idx / factor,
+                },
+            )
+        })
+        .collect()
+}
+
+// filter_coverage_data at several resolutions (bucket sizes) at once, e.g., a genome-wide
+// overview and a finer view of a region. Each resolution must be a multiple of the data's
+// bucket size. The observations are only checked against the filter once; the larger
+// buckets are built by combining the data's buckets. The results are in the same order as
+// `resolutions`, and each is the same as filtering a copy of the data with that bucket size.
+pub fn filter_coverage_data_resolutions(
+    filters: &Filter,
+    data: &CoverageData,
+    included_features: Option<&ExperimentFeatureData>,
+    resolutions: &[u32],
+) -> Result<Vec<FilteredData>, FilterError> {
+    let chrom = filters.chromosome_index(&data.chromosomes)?;
+    let window_step = filters.checked_window_step(data.bucket_size)?;
+    let factors = resolutions
+        .iter()
+        .map(|resolution| resolution_factor(data.bucket_size, *resolution))
+        .collect::<Result<Vec<u32>, FilterError>>()?;
+    let predicate = ObservationFilter::new(filters, data, included_features);
+
+    let bucket_results = bucket_matching_observations(
+        predicate.observations(data),
+        &predicate,
+        |observation| predicate.matches_non_numeric(observation),
+        &data.feature_buckets,
+    );
+
+    Ok(resolutions
+        .iter()
+        .zip(factors)
+        .map(|(resolution, factor)| {
+            let mut chromosomes = empty_chromosomes(chrom, data);
+            for chromosome in &mut chromosomes {
+                chromosome.bucket_size = *resolution;
+            }
+            let coarse_feature_buckets;
+            let feature_buckets = if factor == 1 {
+                &data.feature_buckets
+            } else {
+                coarse_feature_buckets = coarsen_feature_buckets(&data.feature_buckets, factor);
+                &coarse_feature_buckets
+            };
+            build_filtered_data_with(
+                data,
+                &predicate,
+                coarsen_bucket_results(&bucket_results, factor),
+                chromosomes,
+                window_step,
+                *resolution,
+                feature_buckets,
+                &mut BucketScratch::default(),
+            )
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::filter_coverage_data;
+    use crate::test_data::{add_nan_values, assert_same_results, coverage_data, facet_value_id};
+
+    // The data with buckets `resolution` bases wide
+    fn with_bucket_size(data: &CoverageData, resolution: u32) -> CoverageData {
+        let factor = resolution / data.bucket_size;
+        let mut data = data.clone();
+        data.bucket_size = resolution;
+        for chromosome in &mut data.chromosomes {
+            chromosome.bucket_size = resolution;
+        }
+        data.feature_buckets = coarsen_feature_buckets(&data.feature_buckets, factor);
+        data
+    }
+
+    #[test]
+    fn test_resolutions_match_coarser_data() {
+        let mut data = coverage_data(14);
+        add_nan_values(&mut data, 19, 23);
+        let library = facet_value_id(&data, "Library", "Library 0");
+        let resolutions = [10_000, 1_000, 7_000, 50_000];
+
+        for filters in [
+            Filter::new(),
+            Filter::new().categorical_facets([library]),
+            Filter::new().chrom(3).numeric_intervals(FilterIntervals {
+                effect: (-1.0, 3.0),
+                sig: (0.5, 9.0),
+                ..FilterIntervals::new()
+            }),
+        ] {
+            let results =
+                filter_coverage_data_resolutions(&filters, &data, None, &resolutions).unwrap();
+            assert_eq!(results.len(), resolutions.len());
+            for (result, resolution) in results.iter().zip(resolutions) {
+                assert_eq!(result.bucket_size, resolution);
+                assert_same_results(
+                    result,
+                    &filter_coverage_data(&filters, &with_bucket_size(&data, resolution), None)
+                        .unwrap(),
+                );
+            }
+        }
+        assert!(
+            filter_coverage_data_resolutions(&Filter::new(), &data, None, &[])
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_invalid_resolutions() {
+        let data = coverage_data(14);
+        for resolution in [0, 500, 1_500] {
+            assert_eq!(
+                filter_coverage_data_resolutions(&Filter::new(), &data, None, &[1_000, resolution])
+                    .unwrap_err(),
+                FilterError::InvalidResolution {
+                    bucket_size: 1_000,
+                    resolution,
+                }
+            );
+        }
+        let mut no_buckets = data.clone();
+        no_buckets.bucket_size = 0;
+        assert!(
+            filter_coverage_data_resolutions(&Filter::new(), &no_buckets, None, &[1_000]).is_err()
+        );
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::BinaryHeap;
+
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::error::FilterError;
+use crate::filter::{reo_hash, ObservationFilter};
+use crate::filter_data_structures::*;
+use cov_viz_ds::{CoverageData, DbID, ExperimentFeatureData, ObservationData};
+
+// An observation from sample_filtered_observations, located by its source's bucket
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SampledObservation {
+    pub reo_id: DbID,
+    // The index of the source's chromosome
+    pub chrom: u8,
+    // The start of the source's bucket
+    pub start: u64,
+    pub effect_size: f32,
+    // Clamped the same way as a bucket's max_log10_sig
+    pub neg_log_significance: f64,
+}
+
+// Each observation gets a random key, and the sample is the `n` observations with the
+// smallest keys. That's a uniform sample without replacement, and since the keys only
+// depend on the seed and the observation, it doesn't matter how the work is split up.
+// (key, observation index) pairs, so ties are broken the same way every time.
+type SampleHeap = BinaryHeap<(u64, usize)>;
+
+fn add_to_sample(sample: &mut SampleHeap, entry: (u64, usize), n: usize) {
+    if sample.len() < n {
+        sample.push(entry);
+    } else if sample.peek().is_some_and(|largest| entry < *largest) {
+        sample.pop();
+        sample.push(entry);
+    }
+}
+
+// A uniform random sample of `n` of the observations that pass the filter, e.g., for
+// drawing individual observations on top of the buckets. If fewer than `n` pass, they're
+// all returned. The sample is the same for the same seed, and is in order of chromosome
+// index, start, and REO id.
+//
+// Observations are placed by their source, so ones whose source isn't in any bucket aren't
+// sampled. When the filter is restricted to a chromosome, only observations with a source
+// on it are.
+pub fn sample_filtered_observations(
+    filters: &Filter,
+    data: &CoverageData,
+    included_features: Option<&ExperimentFeatureData>,
+    n: usize,
+    seed: u64,
+) -> Result<Vec<SampledObservation>, FilterError> {
+    let chrom = filters.chromosome_index(&data.chromosomes)?;
+    let predicate = ObservationFilter::new(filters, data, included_features);
+    if n == 0 {
+        return Ok(Vec::new());
+    }
+
+    let on_chromosome = |observation: &ObservationData| {
+        data.feature_buckets
+            .get(&observation.source_id)
+            .is_some_and(|bucket| chrom.is_none_or(|chrom| bucket.chrom == chrom))
+    };
+    let sample = predicate
+        .observations(data)
+        .enumerate()
+        .filter(|(_, observation)| predicate.matches(observation) && on_chromosome(observation))
+        .fold(SampleHeap::new, |mut sample, (i, _)| {
+            add_to_sample(&mut sample, (reo_hash(seed ^ reo_hash(i as u64)), i), n);
+            sample
+        })
+        .reduce(SampleHeap::new, |mut sample1, sample2| {
+            for entry in sample2 {
+                add_to_sample(&mut sample1, entry, n);
+            }
+            sample1
+        });
+
+    // Only the indexes are kept while sampling, so the sampled observations are found in a
+    // second pass
+    let mut indexes: Vec<usize> = sample.into_iter().map(|(_, i)| i).collect();
+    indexes.sort_unstable();
+    let mut sampled: Vec<_> = predicate
+        .observations(data)
+        .enumerate()
+        .filter(|(i, _)| indexes.binary_search(i).is_ok())
+        .map(|(_, observation)| {
+            let bucket = data.feature_buckets[&observation.source_id];
+            SampledObservation {
+                reo_id: observation.reo_id,
+                chrom: bucket.chrom,
+                start: bucket.idx as u64 * data.bucket_size as u64 + 1,
+                effect_size: observation.effect_size,
+                neg_log_significance: predicate.significance(observation),
+            }
+        })
+        .collect();
+    sampled.sort_by(|a, b| {
+        (a.chrom, a.start, a.reo_id)
+            .cmp(&(b.chrom, b.start, b.reo_id))
+            .then(a.neg_log_significance.total_cmp(&b.neg_log_significance))
+            .then(a.effect_size.total_cmp(&b.effect_size))
+    });
+    Ok(sampled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::filter_coverage_data;
+    use crate::test_data::coverage_data;
+
+    fn filters() -> Filter {
+        Filter::new().numeric_intervals(FilterIntervals {
+            effect: (-3.0, 3.0),
+            sig: (1.0, 15.0),
+            ..FilterIntervals::new()
+        })
+    }
+
+    #[test]
+    fn test_sample_everything() {
+        let data = coverage_data(17);
+        let filters = filters();
+        // Each synthetic observation has its own REO
+        let passing = filter_coverage_data(&filters, &data, None)
+            .unwrap()
+            .reo_count as usize;
+
+        let sample = sample_filtered_observations(&filters, &data, None, passing + 10, 1).unwrap();
+        assert_eq!(sample.len(), passing);
+        let mut reo_ids: Vec<DbID> = sample.iter().map(|o| o.reo_id).collect();
+        reo_ids.sort_unstable();
+        reo_ids.dedup();
+        assert_eq!(reo_ids.len(), passing);
+        assert!(sample.iter().all(|o| {
+            (-3.0..=3.0).contains(&o.effect_size) && (1.0..=15.0).contains(&o.neg_log_significance)
+        }));
+        assert!(sample
+            .windows(2)
+            .all(|pair| (pair[0].chrom, pair[0].start) <= (pair[1].chrom, pair[1].start)));
+
+        assert!(sample_filtered_observations(&filters, &data, None, 0, 1)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_sample_is_reproducible() {
+        let data = coverage_data(18);
+        let filters = filters();
+        let sample = sample_filtered_observations(&filters, &data, None, 100, 7).unwrap();
+        assert_eq!(sample.len(), 100);
+        for threads in [1, 3, 8] {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            let pool_sample =
+                pool.install(|| sample_filtered_observations(&filters, &data, None, 100, 7));
+            assert_eq!(pool_sample.unwrap(), sample);
+        }
+        assert_ne!(
+            sample_filtered_observations(&filters, &data, None, 100, 8).unwrap(),
+            sample
+        );
+    }
+
+    #[test]
+    fn test_sample_distribution() {
+        let data = coverage_data(19);
+        let filters = Filter::new();
+        let everything =
+            sample_filtered_observations(&filters, &data, None, usize::MAX, 1).unwrap();
+        let n = everything.len() / 4;
+        let chromosome_fractions = |sample: &Vec<SampledObservation>| {
+            let mut counts = [0usize; 4];
+            for observation in sample {
+                counts[observation.chrom as usize] += 1;
+            }
+            counts.map(|count| count as f64 / sample.len() as f64)
+        };
+
+        // Each chromosome's share of the sample is close to its share of all the observations
+        let expected = chromosome_fractions(&everything);
+        for seed in 0..5 {
+            let sample = sample_filtered_observations(&filters, &data, None, n, seed).unwrap();
+            assert_eq!(sample.len(), n);
+            for (fraction, expected) in chromosome_fractions(&sample).iter().zip(expected) {
+                assert!(
+                    (fraction - expected).abs() < 0.05,
+                    "{} vs {}",
+                    fraction,
+                    expected
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::error::FilterError;
+use crate::filter::{
+    bucket_matching_observations_with, build_filtered_data, empty_chromosomes, has_invalid_values,
+    merge_bucket_results, ObservationFilter,
+};
+use crate::filter_data_structures::*;
+use cov_viz_ds::{CoverageData, ExperimentFeatureData};
+
+// What happened during a filter call. Each observation count only includes observations that
+// passed the earlier stages, so the counts never increase from one stage to the next.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct FilterStats {
+    pub significant_scanned: u64,
+    pub nonsignificant_scanned: u64,
+    pub passed_categorical: u64,
+    // Passed the numeric intervals, and have no NaN values
+    pub passed_numeric: u64,
+    pub passed_included_features: u64,
+    pub source_buckets: u64,
+    pub target_buckets: u64,
+    // Checking and bucketing the observations, including merging the buckets
+    pub predicate_pass: Duration,
+    // The time spent merging per-thread buckets, summed over all threads, so it can be longer
+    // than predicate_pass
+    pub bucket_merge: Duration,
+    // Turning the buckets into FilteredData
+    pub gen_filtered_data: Duration,
+}
+
+// filter_coverage_data, but also counts how many observations made it through each stage of
+// the filter and times each phase. The counting makes this slower than filter_coverage_data,
+// so it's only meant for investigating slow filters.
+pub fn filter_coverage_data_instrumented(
+    filters: &Filter,
+    data: &CoverageData,
+    included_features: Option<&ExperimentFeatureData>,
+) -> Result<(FilteredData, FilterStats), FilterError> {
+    let chrom = filters.chromosome_index(&data.chromosomes)?;
+    let window_step = filters.checked_window_step(data.bucket_size)?;
+    let predicate = ObservationFilter::new(filters, data, included_features);
+
+    let passed_categorical = AtomicU64::new(0);
+    let passed_numeric = AtomicU64::new(0);
+    let passed_included_features = AtomicU64::new(0);
+    let merge_nanos = AtomicU64::new(0);
+
+    let predicate_start = Instant::now();
+    let bucket_results = bucket_matching_observations_with(
+        predicate.observations(data),
+        &predicate,
+        |observation| {
+            if !predicate.matches_categorical(observation) {
+                return false;
+            }
+            passed_categorical.fetch_add(1, Ordering::Relaxed);
+            let included = predicate.matches_included_features(observation);
+            if !has_invalid_values(observation) && predicate.matches_numeric(observation) {
+                passed_numeric.fetch_add(1, Ordering::Relaxed);
+                if included {
+                    passed_included_features.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            included
+        },
+        &data.feature_buckets,
+        |results1, results2| {
+            let merge_start = Instant::now();
+            let results = merge_bucket_results(results1, results2);
+            merge_nanos.fetch_add(merge_start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+            results
+        },
+    );
+    let predicate_pass = predicate_start.elapsed();
+
+    let gen_start = Instant::now();
+    let filtered_data = build_filtered_data(
+        data,
+        &predicate,
+        bucket_results,
+        empty_chromosomes(chrom, data),
+        window_step,
+    );
+    let gen_filtered_data = gen_start.elapsed();
+
+    let stats = FilterStats {
+        significant_scanned: data.significant_observations.len() as u64,
+        nonsignificant_scanned: if predicate.skip_nonsignificants {
+            0
+        } else {
+            data.nonsignificant_observations.len() as u64
+        },
+        passed_categorical: passed_categorical.into_inner(),
+        passed_numeric: passed_numeric.into_inner(),
+        passed_included_features: passed_included_features.into_inner(),
+        source_buckets: filtered_data
+            .chromosomes
+            .iter()
+            .map(|c| c.source_intervals.len() as u64)
+            .sum(),
+        target_buckets: filtered_data
+            .chromosomes
+            .iter()
+            .map(|c| c.target_intervals.len() as u64)
+            .sum(),
+        predicate_pass,
+        bucket_merge: Duration::from_nanos(merge_nanos.into_inner()),
+        gen_filtered_data,
+    };
+
+    Ok((filtered_data, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::filter_coverage_data;
+    use crate::test_data::{add_nan_values, assert_same_results, coverage_data, facet_value_id};
+    use cov_viz_ds::ObservationData;
+
+    #[test]
+    fn test_stage_counts() {
+        let mut data = coverage_data(6);
+        add_nan_values(&mut data, 13, 17);
+        let library = facet_value_id(&data, "Library", "Library 2");
+        let filters = Filter::new()
+            .categorical_facets([library])
+            .numeric_intervals(FilterIntervals {
+                effect: (-2.0, 3.0),
+                sig: (1.0, 10.0),
+                ..FilterIntervals::new()
+            });
+        let included_features = ExperimentFeatureData {
+            sources: (0..500).step_by(2).collect(),
+            targets: (0..500).step_by(3).collect(),
+        };
+
+        // Counted by hand
+        let observations: Vec<&ObservationData> = data
+            .significant_observations
+            .iter()
+            .chain(&data.nonsignificant_observations)
+            .collect();
+        let categorical: Vec<&&ObservationData> = observations
+            .iter()
+            .filter(|o| o.facet_value_ids.contains(&library))
+            .collect();
+        let numeric: Vec<&&&ObservationData> = categorical
+            .iter()
+            .filter(|o| {
+                (-2.0..=3.0).contains(&o.effect_size)
+                    && (1.0..=10.0).contains(&o.neg_log_significance)
+            })
+            .collect();
+        let included = numeric
+            .iter()
+            .filter(|o| match o.target_id {
+                Some(target_id) => included_features.targets.contains(target_id),
+                None => included_features.sources.contains(o.source_id),
+            })
+            .count();
+
+        for included_features in [None, Some(&included_features)] {
+            let (filtered, stats) =
+                filter_coverage_data_instrumented(&filters, &data, included_features).unwrap();
+            assert_same_results(
+                &filtered,
+                &filter_coverage_data(&filters, &data, included_features).unwrap(),
+            );
+
+            assert_eq!(
+                stats.significant_scanned,
+                data.significant_observations.len() as u64
+            );
+            assert_eq!(
+                stats.nonsignificant_scanned,
+                data.nonsignificant_observations.len() as u64
+            );
+            assert_eq!(stats.passed_categorical, categorical.len() as u64);
+            assert_eq!(stats.passed_numeric, numeric.len() as u64);
+            let expected_included = match included_features {
+                Some(_) => included,
+                None => numeric.len(),
+            };
+            assert_eq!(stats.passed_included_features, expected_included as u64);
+
+            let counts = [
+                stats.significant_scanned + stats.nonsignificant_scanned,
+                stats.passed_categorical,
+                stats.passed_numeric,
+                stats.passed_included_features,
+            ];
+            assert!(counts.windows(2).all(|w| w[0] >= w[1]), "{counts:?}");
+            assert!(counts[3] > 0 && counts[3] < counts[0]);
+
+            let intervals = |side: fn(&FilteredChromosome) -> usize| {
+                filtered.chromosomes.iter().map(side).sum::<usize>() as u64
+            };
+            assert_eq!(
+                stats.source_buckets,
+                intervals(|c| c.source_intervals.len())
+            );
+            assert_eq!(
+                stats.target_buckets,
+                intervals(|c| c.target_intervals.len())
+            );
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use rayon::prelude::*;
+use roaring::RoaringTreemap;
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::Serialize;
+
+use crate::filter::{has_invalid_values, DataFacets};
+use crate::filter_data_structures::*;
+use cov_viz_ds::{CoverageData, ObservationData};
+
+// Totals for a single chromosome, counting every observation with a source or target on it
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ChromosomeSummary {
+    pub chrom: String,
+    pub index: u8,
+    pub observation_count: u64,
+    pub source_count: u64,
+    pub target_count: u64,
+    // The number of buckets with at least one source or target in them
+    pub source_bucket_count: u64,
+    pub target_bucket_count: u64,
+}
+
+// An overview of a data set before any filter is applied. The totals and ranges match what
+// filter_coverage_data produces with an empty filter, without building any buckets.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CoverageSummary {
+    pub chromosomes: Vec<ChromosomeSummary>,
+    pub reo_count: u64,
+    // Observations with a NaN effect size or significance
+    pub dropped_invalid: u64,
+    pub source_count: u64,
+    pub target_count: u64,
+    pub min_effect: f32,
+    pub max_effect: f32,
+    pub min_sig: f64,
+    pub max_sig: f64,
+}
+
+#[derive(Default)]
+struct ChromosomeTotals {
+    observation_count: u64,
+    sources: RoaringTreemap,
+    targets: RoaringTreemap,
+    source_buckets: FxHashSet<u32>,
+    target_buckets: FxHashSet<u32>,
+}
+
+impl ChromosomeTotals {
+    fn merge(&mut self, other: ChromosomeTotals) {
+        self.observation_count += other.observation_count;
+        self.sources |= other.sources;
+        self.targets |= other.targets;
+        self.source_buckets.extend(other.source_buckets);
+        self.target_buckets.extend(other.target_buckets);
+    }
+}
+
+struct Totals {
+    chromosomes: FxHashMap<u8, ChromosomeTotals>,
+    reos: RoaringTreemap,
+    dropped_invalid: u64,
+    min_effect: f32,
+    max_effect: f32,
+    min_sig: f64,
+    max_sig: f64,
+}
+
+impl Totals {
+    fn new() -> Self {
+        Totals {
+            chromosomes: FxHashMap::default(),
+            reos: RoaringTreemap::new(),
+            dropped_invalid: 0,
+            min_effect: f32::INFINITY,
+            max_effect: f32::NEG_INFINITY,
+            min_sig: f64::INFINITY,
+            max_sig: f64::NEG_INFINITY,
+        }
+    }
+
+    fn add(
+        &mut self,
+        observation: &ObservationData,
+        data: &CoverageData,
+        data_chromosomes: &FxHashSet<u8>,
+        sig_ceiling: f64,
+    ) {
+        if has_invalid_values(observation) {
+            self.dropped_invalid += 1;
+            return;
+        }
+
+        self.reos.insert(observation.reo_id);
+
+        let feature_bucket = |feature_id| {
+            data.feature_buckets
+                .get(&feature_id)
+                .filter(|bucket| data_chromosomes.contains(&bucket.chrom))
+        };
+        let source_bucket = feature_bucket(observation.source_id);
+        let target_bucket = observation.target_id.and_then(feature_bucket);
+        if source_bucket.is_none() && target_bucket.is_none() {
+            return;
+        }
+
+        if let Some(bucket) = source_bucket {
+            let totals = self.chromosomes.entry(bucket.chrom).or_default();
+            totals.observation_count += 1;
+            totals.sources.insert(observation.source_id);
+            totals.source_buckets.insert(bucket.idx);
+        }
+        if let (Some(target_id), Some(bucket)) = (observation.target_id, target_bucket) {
+            let totals = self.chromosomes.entry(bucket.chrom).or_default();
+            // Only count cis observations once
+            if source_bucket.is_none_or(|source_bucket| source_bucket.chrom != bucket.chrom) {
+                totals.observation_count += 1;
+            }
+            totals.targets.insert(target_id);
+            totals.target_buckets.insert(bucket.idx);
+        }
+
+        let sig = observation.neg_log_significance.min(sig_ceiling);
+        self.min_effect = self.min_effect.min(observation.effect_size);
+        self.max_effect = self.max_effect.max(observation.effect_size);
+        self.min_sig = self.min_sig.min(sig);
+        self.max_sig = self.max_sig.max(sig);
+    }
+
+    fn merge(mut self, other: Totals) -> Totals {
+        for (chrom, totals) in other.chromosomes {
+            self.chromosomes.entry(chrom).or_default().merge(totals);
+        }
+        self.reos |= other.reos;
+        self.dropped_invalid += other.dropped_invalid;
+        self.min_effect = self.min_effect.min(other.min_effect);
+        self.max_effect = self.max_effect.max(other.max_effect);
+        self.min_sig = self.min_sig.min(other.min_sig);
+        self.max_sig = self.max_sig.max(other.max_sig);
+        self
+    }
+}
+
+// Summarizes every observation in the data set in a single pass. Features on chromosomes that
+// aren't in the data set are left out, the same way filter_coverage_data leaves out their
+// buckets.
+pub fn coverage_summary(data: &CoverageData) -> CoverageSummary {
+    let sig_ceiling = -MIN_SIG.log10();
+    let data_chromosomes: FxHashSet<u8> = data.chromosomes.iter().map(|c| c.index).collect();
+    let mut totals = data
+        .significant_observations
+        .par_iter()
+        .chain(data.nonsignificant_observations.par_iter())
+        .fold(Totals::new, |mut totals, observation| {
+            totals.add(observation, data, &data_chromosomes, sig_ceiling);
+            totals
+        })
+        .reduce(Totals::new, Totals::merge);
+
+    // Like filter_coverage_data, fall back to the facet ranges when there aren't any
+    // observations in buckets
+    if totals.min_effect > totals.max_effect {
+        let facets = DataFacets::new(data);
+        totals.min_effect = facets.effect_size_range.0;
+        totals.max_effect = facets.effect_size_range.1;
+        totals.min_sig = facets.sig_range.0.min(sig_ceiling);
+        totals.max_sig = facets.sig_range.1.min(sig_ceiling);
+    }
+
+    let mut sources = RoaringTreemap::new();
+    let mut targets = RoaringTreemap::new();
+    let chromosomes = data
+        .chromosomes
+        .iter()
+        .map(|chromosome| {
+            let chrom_totals = totals
+                .chromosomes
+                .remove(&chromosome.index)
+                .unwrap_or_default();
+            sources |= &chrom_totals.sources;
+            targets |= &chrom_totals.targets;
+            ChromosomeSummary {
+                chrom: chromosome.chrom.clone(),
+                index: chromosome.index,
+                observation_count: chrom_totals.observation_count,
+                source_count: chrom_totals.sources.len(),
+                target_count: chrom_totals.targets.len(),
+                source_bucket_count: chrom_totals.source_buckets.len() as u64,
+                target_bucket_count: chrom_totals.target_buckets.len() as u64,
+            }
+        })
+        .collect();
+
+    CoverageSummary {
+        chromosomes,
+        reo_count: totals.reos.len(),
+        dropped_invalid: totals.dropped_invalid,
+        source_count: sources.len(),
+        target_count: targets.len(),
+        min_effect: totals.min_effect,
+        max_effect: totals.max_effect,
+        min_sig: totals.min_sig,
+        max_sig: totals.max_sig,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::filter_coverage_data;
+    use crate::test_data::{add_nan_values, coverage_data};
+
+    fn assert_matches_empty_filter(data: &CoverageData) {
+        let summary = coverage_summary(data);
+        let filtered = filter_coverage_data(&Filter::new(), data, None).unwrap();
+
+        assert_eq!(summary.reo_count, filtered.reo_count);
+        assert_eq!(summary.dropped_invalid, filtered.dropped_invalid);
+        assert_eq!(summary.source_count, filtered.sources.len());
+        assert_eq!(summary.target_count, filtered.targets.len());
+        assert_eq!(
+            (summary.min_effect, summary.max_effect),
+            filtered.numeric_intervals.effect
+        );
+        assert_eq!(
+            (summary.min_sig, summary.max_sig),
+            filtered.numeric_intervals.sig
+        );
+
+        assert_eq!(summary.chromosomes.len(), filtered.chromosomes.len());
+        for (chrom_summary, chromosome) in summary.chromosomes.iter().zip(&filtered.chromosomes) {
+            assert_eq!(chrom_summary.index, chromosome.index);
+            assert_eq!(
+                chrom_summary.source_bucket_count,
+                chromosome.source_intervals.len() as u64
+            );
+            assert_eq!(
+                chrom_summary.target_bucket_count,
+                chromosome.target_intervals.len() as u64
+            );
+            // Every feature is in one bucket
+            let feature_count = |intervals: &[FilteredBucket]| -> u64 {
+                intervals.iter().map(|bucket| bucket.count as u64).sum()
+            };
+            assert_eq!(
+                chrom_summary.source_count,
+                feature_count(&chromosome.source_intervals)
+            );
+            assert_eq!(
+                chrom_summary.target_count,
+                feature_count(&chromosome.target_intervals)
+            );
+        }
+    }
+
+    #[test]
+    fn test_summary_matches_empty_filter() {
+        let data = coverage_data(16);
+        assert_matches_empty_filter(&data);
+        let summary = coverage_summary(&data);
+        let observation_count: u64 = summary
+            .chromosomes
+            .iter()
+            .map(|c| c.observation_count)
+            .sum();
+        // Trans observations are counted on both of their chromosomes
+        assert!(observation_count >= summary.reo_count);
+
+        let mut with_nans = data.clone();
+        add_nan_values(&mut with_nans, 13, 17);
+        with_nans.significant_observations[1].neg_log_significance = f64::INFINITY;
+        assert_matches_empty_filter(&with_nans);
+        assert!(coverage_summary(&with_nans).dropped_invalid > 0);
+
+        let mut empty = data.clone();
+        empty.significant_observations.clear();
+        empty.nonsignificant_observations.clear();
+        assert_matches_empty_filter(&empty);
+    }
+}
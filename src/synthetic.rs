@@ -0,0 +1,319 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustc_hash::FxHashMap;
+
+use cov_viz_ds::{
+    BucketLoc, ChromosomeData, CoverageData, DbID, Facet, FacetRange, FacetRange64, ObservationData,
+};
+
+// Observations with a neg_log_significance below this are non-significant (p > 0.05)
+const SIGNIFICANCE_THRESHOLD: f64 = 1.3;
+
+// Builds randomly generated, but reproducible, CoverageData for benchmarks and tests.
+//
+// The data always has the "Direction", "Effect Size", and "Significance" facets the filter
+// relies on, plus any number of additional categorical facets. Every observation gets exactly
+// one value of each categorical facet. Facet and facet value ids are assigned sequentially,
+// starting at 1, in the order the facets appear in `CoverageData.facets`.
+//
+// Counts that can't be 0 (chromosomes, buckets per chromosome, bucket size, features, and
+// facet values) are raised to 1, and fractions are clamped to [0, 1], with NaN treated as 0.
+pub struct SyntheticCoverageDataBuilder {
+    seed: u64,
+    chromosome_count: u8,
+    buckets_per_chromosome: u32,
+    bucket_size: u32,
+    feature_count: u64,
+    observation_count: usize,
+    nonsignificant_fraction: f64,
+    target_fraction: f64,
+    categorical_facets: Vec<(String, usize)>,
+}
+
+fn clamp_fraction(fraction: f64) -> f64 {
+    if fraction.is_nan() {
+        0.0
+    } else {
+        fraction.clamp(0.0, 1.0)
+    }
+}
+
+impl Default for SyntheticCoverageDataBuilder {
+    fn default() -> Self {
+        SyntheticCoverageDataBuilder {
+            seed: 0,
+            chromosome_count: 23,
+            buckets_per_chromosome: 100,
+            bucket_size: 2_000_000,
+            feature_count: 10_000,
+            observation_count: 100_000,
+            nonsignificant_fraction: 0.5,
+            target_fraction: 0.5,
+            categorical_facets: Vec::new(),
+        }
+    }
+}
+
+impl SyntheticCoverageDataBuilder {
+    pub fn new() -> Self {
+        SyntheticCoverageDataBuilder::default()
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn chromosomes(mut self, chromosome_count: u8) -> Self {
+        self.chromosome_count = chromosome_count.max(1);
+        self
+    }
+
+    pub fn buckets_per_chromosome(mut self, buckets_per_chromosome: u32) -> Self {
+        self.buckets_per_chromosome = buckets_per_chromosome.max(1);
+        self
+    }
+
+    pub fn bucket_size(mut self, bucket_size: u32) -> Self {
+        self.bucket_size = bucket_size.max(1);
+        self
+    }
+
+    pub fn features(mut self, feature_count: u64) -> Self {
+        self.feature_count = feature_count.max(1);
+        self
+    }
+
+    pub fn observations(mut self, observation_count: usize) -> Self {
+        self.observation_count = observation_count;
+        self
+    }
+
+    // The fraction of observations that are non-significant
+    pub fn nonsignificant_fraction(mut self, fraction: f64) -> Self {
+        self.nonsignificant_fraction = clamp_fraction(fraction);
+        self
+    }
+
+    // The fraction of observations that have a target feature
+    pub fn target_fraction(mut self, fraction: f64) -> Self {
+        self.target_fraction = clamp_fraction(fraction);
+        self
+    }
+
+    // Adds a categorical facet with `value_count` values
+    pub fn categorical_facet(mut self, name: &str, value_count: usize) -> Self {
+        self.categorical_facets
+            .push((name.to_string(), value_count.max(1)));
+        self
+    }
+
+    pub fn build(&self) -> CoverageData {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+
+        let chromosomes: Vec<ChromosomeData> = (0..self.chromosome_count)
+            .map(|index| ChromosomeData {
+                chrom: format!("chr{}", index + 1),
+                index,
+                bucket_size: self.bucket_size,
+            })
+            .collect();
+
+        let feature_buckets: FxHashMap<DbID, BucketLoc> = (0..self.feature_count)
+            .map(|feature_id| {
+                (
+                    feature_id,
+                    BucketLoc {
+                        chrom: rng.gen_range(0..self.chromosome_count),
+                        idx: rng.gen_range(0..self.buckets_per_chromosome),
+                    },
+                )
+            })
+            .collect();
+
+        let mut next_id: DbID = 1;
+        let mut next_id = || {
+            let id = next_id;
+            next_id += 1;
+            id
+        };
+
+        let direction_id = next_id();
+        let enriched_id = next_id();
+        let depleted_id = next_id();
+        let nonsignificant_id = next_id();
+        let mut categorical_facets = vec![categorical_facet(
+            direction_id,
+            "Direction",
+            vec![
+                (enriched_id, "Enriched Only".to_string()),
+                (depleted_id, "Depleted Only".to_string()),
+                (nonsignificant_id, "Non-significant".to_string()),
+            ],
+        )];
+
+        // The value ids of each additional categorical facet
+        let mut extra_facet_values: Vec<Vec<DbID>> = Vec::new();
+        for (name, value_count) in &self.categorical_facets {
+            let facet_id = next_id();
+            let values: Vec<(DbID, String)> = (0..*value_count)
+                .map(|i| (next_id(), format!("{} {}", name, i)))
+                .collect();
+            extra_facet_values.push(values.iter().map(|(id, _)| *id).collect());
+            categorical_facets.push(categorical_facet(facet_id, name, values));
+        }
+
+        let mut significant_observations = Vec::new();
+        let mut nonsignificant_observations = Vec::new();
+        for reo_id in 0..self.observation_count as DbID {
+            let significant = !rng.gen_bool(self.nonsignificant_fraction);
+            let effect_size: f32 = rng.gen_range(-5.0..5.0);
+            let neg_log_significance = if significant {
+                rng.gen_range(SIGNIFICANCE_THRESHOLD..20.0)
+            } else {
+                rng.gen_range(0.0..SIGNIFICANCE_THRESHOLD)
+            };
+
+            let mut facet_value_ids = vec![if !significant {
+                nonsignificant_id
+            } else if effect_size > 0.0 {
+                enriched_id
+            } else {
+                depleted_id
+            }];
+            for values in &extra_facet_values {
+                facet_value_ids.push(values[rng.gen_range(0..values.len())]);
+            }
+
+            let observation = ObservationData {
+                reo_id,
+                facet_value_ids,
+                source_id: rng.gen_range(0..self.feature_count),
+                target_id: if rng.gen_bool(self.target_fraction) {
+                    Some(rng.gen_range(0..self.feature_count))
+                } else {
+                    None
+                },
+                effect_size,
+                neg_log_significance,
+            };
+
+            if significant {
+                significant_observations.push(observation);
+            } else {
+                nonsignificant_observations.push(observation);
+            }
+        }
+
+        let (min_effect, max_effect, min_sig, max_sig) = significant_observations
+            .iter()
+            .chain(nonsignificant_observations.iter())
+            .fold(
+                (
+                    f32::INFINITY,
+                    f32::NEG_INFINITY,
+                    f64::INFINITY,
+                    f64::NEG_INFINITY,
+                ),
+                |acc, o| {
+                    (
+                        acc.0.min(o.effect_size),
+                        acc.1.max(o.effect_size),
+                        acc.2.min(o.neg_log_significance),
+                        acc.3.max(o.neg_log_significance),
+                    )
+                },
+            );
+
+        let mut facets = categorical_facets;
+        facets.push(Facet {
+            id: next_id(),
+            name: "Effect Size".to_string(),
+            description: String::new(),
+            facet_type: "FacetType.NUMERIC".to_string(),
+            coverage: None,
+            range: Some(FacetRange(min_effect, max_effect)),
+            range64: None,
+            values: None,
+        });
+        facets.push(Facet {
+            id: next_id(),
+            name: "Significance".to_string(),
+            description: String::new(),
+            facet_type: "FacetType.NUMERIC".to_string(),
+            coverage: None,
+            range: None,
+            range64: Some(FacetRange64(min_sig, max_sig)),
+            values: None,
+        });
+
+        CoverageData {
+            facets,
+            chromosomes,
+            bucket_size: self.bucket_size,
+            significant_observations,
+            nonsignificant_observations,
+            feature_buckets,
+        }
+    }
+}
+
+fn categorical_facet(id: DbID, name: &str, values: Vec<(DbID, String)>) -> Facet {
+    Facet {
+        id,
+        name: name.to_string(),
+        description: String::new(),
+        facet_type: "FacetType.CATEGORICAL".to_string(),
+        coverage: None,
+        range: None,
+        range64: None,
+        values: Some(values.into_iter().collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zero_counts_are_raised() {
+        let data = SyntheticCoverageDataBuilder::new()
+            .chromosomes(0)
+            .buckets_per_chromosome(0)
+            .bucket_size(0)
+            .features(0)
+            .observations(100)
+            .categorical_facet("Library", 0)
+            .build();
+        assert_eq!(data.chromosomes.len(), 1);
+        assert_eq!(data.bucket_size, 1);
+        assert_eq!(data.feature_buckets.len(), 1);
+        let library = data.facets.iter().find(|f| f.name == "Library").unwrap();
+        assert_eq!(library.values.as_ref().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_fractions_are_clamped() {
+        let build = |nonsignificant_fraction: f64, target_fraction: f64| {
+            SyntheticCoverageDataBuilder::new()
+                .observations(1_000)
+                .nonsignificant_fraction(nonsignificant_fraction)
+                .target_fraction(target_fraction)
+                .build()
+        };
+
+        let data = build(2.0, -1.0);
+        assert!(data.significant_observations.is_empty());
+        assert!(data
+            .nonsignificant_observations
+            .iter()
+            .all(|observation| observation.target_id.is_none()));
+
+        let data = build(f64::NAN, f64::INFINITY);
+        assert!(data.nonsignificant_observations.is_empty());
+        assert!(data
+            .significant_observations
+            .iter()
+            .all(|observation| observation.target_id.is_some()));
+    }
+}
@@ -0,0 +1,56 @@
+use cov_viz_ds::{CoverageData, DbID};
+
+use crate::filter_data_structures::FilteredData;
+use crate::synthetic::SyntheticCoverageDataBuilder;
+
+// Data and helpers shared by the unit tests
+
+// A small data set with 4 chromosomes of 50 buckets and a "Library" facet with 3 values
+pub(crate) fn coverage_data(seed: u64) -> CoverageData {
+    SyntheticCoverageDataBuilder::new()
+        .seed(seed)
+        .chromosomes(4)
+        .buckets_per_chromosome(50)
+        .bucket_size(1_000)
+        .features(500)
+        .observations(5_000)
+        .categorical_facet("Library", 3)
+        .build()
+}
+
+pub(crate) fn facet_value_id(data: &CoverageData, facet_name: &str, value_name: &str) -> DbID {
+    let facet = data.facets.iter().find(|f| f.name == facet_name).unwrap();
+    *facet
+        .values
+        .as_ref()
+        .unwrap()
+        .iter()
+        .find(|(_, name)| *name == value_name)
+        .unwrap()
+        .0
+}
+
+// Sets the effect size of every `effect_every`th observation and the significance of every
+// `sig_every`th observation to NaN
+pub(crate) fn add_nan_values(data: &mut CoverageData, effect_every: usize, sig_every: usize) {
+    let observations = data
+        .significant_observations
+        .iter_mut()
+        .chain(data.nonsignificant_observations.iter_mut());
+    for (i, observation) in observations.enumerate() {
+        if i % effect_every == 0 {
+            observation.effect_size = f32::NAN;
+        }
+        if i % sig_every == 0 {
+            observation.neg_log_significance = f64::NAN;
+        }
+    }
+}
+
+// Results are compared by their serialized form, which only depends on their contents
+pub(crate) fn assert_same_results(data1: &FilteredData, data2: &FilteredData) {
+    assert_eq!(
+        serde_json::to_value(data1).unwrap(),
+        serde_json::to_value(data2).unwrap()
+    );
+}
@@ -0,0 +1,357 @@
+use proptest::collection::{btree_set, vec};
+use proptest::option;
+use proptest::prelude::*;
+use roaring::RoaringTreemap;
+
+use crate::filter_data_structures::*;
+use crate::synthetic::SyntheticCoverageDataBuilder;
+use cov_viz_ds::{CoverageData, ExperimentFeatureData};
+
+// proptest strategies for the crate's types, for property tests of this crate and of the
+// crates that use it.
+//
+// The generated values are valid, i.e., they're what filtering could actually produce:
+// intervals are sorted by start, associated buckets and annotations are sorted without
+// duplicates, annotations refer to existing names, and chromosomes are in order of their
+// index. Floating point values are always finite.
+
+const BUCKET_SIZES: [u32; 3] = [100, 1_000, 2_000];
+
+fn bucket_size() -> impl Strategy<Value = u32> {
+    prop::sample::select(BUCKET_SIZES.to_vec())
+}
+
+fn treemap(max_id: u64, max_len: usize) -> impl Strategy<Value = RoaringTreemap> {
+    vec(0..max_id, 0..=max_len).prop_map(|ids| ids.into_iter().collect())
+}
+
+// A (min, max) pair from `range`
+fn f32_interval(min: f32, max: f32) -> impl Strategy<Value = (f32, f32)> {
+    (min..max, min..max).prop_map(|(a, b)| (a.min(b), a.max(b)))
+}
+
+fn f64_interval(min: f64, max: f64) -> impl Strategy<Value = (f64, f64)> {
+    (min..max, min..max).prop_map(|(a, b)| (a.min(b), a.max(b)))
+}
+
+// Synthetic coverage data of up to 500 observations. Besides the "Direction" facet (value ids
+// 2 to 4) there's a "Library" facet with up to 3 values (value ids 6 to 8).
+pub fn coverage_data() -> impl Strategy<Value = CoverageData> {
+    (
+        any::<u64>(),
+        1u8..=4,
+        1u32..=20,
+        bucket_size(),
+        1u64..=200,
+        0usize..=500,
+        (0.0..=1.0f64, 0.0..=1.0f64),
+        1usize..=3,
+    )
+        .prop_map(
+            |(
+                seed,
+                chromosomes,
+                buckets_per_chromosome,
+                bucket_size,
+                features,
+                observations,
+                (nonsignificant_fraction, target_fraction),
+                library_values,
+            )| {
+                SyntheticCoverageDataBuilder::new()
+                    .seed(seed)
+                    .chromosomes(chromosomes)
+                    .buckets_per_chromosome(buckets_per_chromosome)
+                    .bucket_size(bucket_size)
+                    .features(features)
+                    .observations(observations)
+                    .nonsignificant_fraction(nonsignificant_fraction)
+                    .target_fraction(target_fraction)
+                    .categorical_facet("Library", library_values)
+                    .build()
+            },
+        )
+}
+
+// Feature sets with ids in the range coverage_data uses
+pub fn experiment_feature_data() -> impl Strategy<Value = ExperimentFeatureData> {
+    (treemap(200, 100), treemap(200, 100))
+        .prop_map(|(sources, targets)| ExperimentFeatureData { sources, targets })
+}
+
+// Numeric intervals for filtering, so the per-sign effect ranges are left out
+pub fn filter_intervals() -> impl Strategy<Value = FilterIntervals> {
+    (f32_interval(-10.0, 10.0), f64_interval(0.0, 25.0)).prop_map(|(effect, sig)| FilterIntervals {
+        effect,
+        sig,
+        ..FilterIntervals::new()
+    })
+}
+
+// Numeric intervals as they appear in filter results, with the per-sign effect ranges
+pub fn result_intervals() -> impl Strategy<Value = FilterIntervals> {
+    (
+        f32_interval(-10.0, 10.0),
+        f64_interval(0.0, 25.0),
+        option::of(f32_interval(-10.0, -0.001)),
+        option::of(f32_interval(0.001, 10.0)),
+    )
+        .prop_map(
+            |(effect, sig, effect_negative, effect_positive)| FilterIntervals {
+                effect,
+                sig,
+                effect_negative,
+                effect_positive,
+            },
+        )
+}
+
+// Filters for data from coverage_data. The chromosome is chosen by index or by name, but not
+// both. The window step is left unset, since it has to divide the data's bucket size.
+pub fn filter() -> impl Strategy<Value = Filter> {
+    let chromosome = prop_oneof![
+        Just((None::<u8>, None::<String>)),
+        (0u8..4).prop_map(|index| (Some(index), None)),
+        (1u8..=4).prop_map(|number| (None, Some(format!("chr{}", number)))),
+    ];
+    let output_sides = prop_oneof![
+        Just(OutputSides::Both),
+        Just(OutputSides::SourcesOnly),
+        Just(OutputSides::TargetsOnly),
+    ];
+    let feature_match_mode = prop_oneof![
+        Just(FeatureMatchMode::TargetOrUnpairedSource),
+        Just(FeatureMatchMode::TargetOnly),
+    ];
+    let preview = option::of((option::of(1u64..=10), option::of(0.1..=1.0f64)).prop_map(
+        |(feature_cap, sample_rate)| PreviewOptions {
+            feature_cap,
+            sample_rate,
+        },
+    ));
+
+    (
+        (chromosome, btree_set(2u64..=8, 0..=3)),
+        (
+            option::of(filter_intervals()),
+            option::of(5.0..=25.0f64),
+            option::of(0.0..=25.0f64),
+        ),
+        (any::<bool>(), option::of(1u32..=3), any::<bool>()),
+        (preview, output_sides, any::<bool>(), feature_match_mode),
+        (option::of(1usize..=8), option::of(1usize..=20)),
+    )
+        .prop_map(
+            |(
+                ((chrom, chrom_name), categorical_facets),
+                (numeric_intervals, sig_ceiling, sig_threshold),
+                (dedup_reos, min_pair_support, pair_support_excludes_unpaired),
+                (preview, output_sides, omit_empty_chromosomes, feature_match_mode),
+                (max_associated_buckets_per_bucket, max_buckets_per_chromosome),
+            )| Filter {
+                chrom,
+                chrom_name,
+                categorical_facets: categorical_facets.into_iter().collect(),
+                numeric_intervals,
+                sig_ceiling,
+                sig_threshold,
+                dedup_reos,
+                min_pair_support,
+                pair_support_excludes_unpaired,
+                preview,
+                output_sides,
+                omit_empty_chromosomes,
+                feature_match_mode,
+                max_associated_buckets_per_bucket,
+                max_buckets_per_chromosome,
+                ..Filter::new()
+            },
+        )
+}
+
+// A bucket whose associated buckets are on chromosomes 0 to 3 and whose annotations are
+// indexes into a list of `annotation_count` names
+pub fn filtered_bucket(start: u64, annotation_count: u32) -> impl Strategy<Value = FilteredBucket> {
+    (
+        1usize..=100,
+        btree_set((0u32..4, 0u32..100), 0..=8),
+        0.0..=25.0f64,
+        -10.0..=10.0f32,
+        any::<bool>(),
+        btree_set(
+            0..annotation_count.max(1),
+            0..=annotation_count.min(3) as usize,
+        ),
+        // Sums of observations' significances are multiples of the fixed point step
+        (0u32..=10_000).prop_map(|quarters| quarters as f64 / 4.0),
+        0u64..=100,
+    )
+        .prop_map(
+            move |(
+                count,
+                associated_buckets,
+                max_log10_sig,
+                max_abs_effect,
+                count_is_lower_bound,
+                annotations,
+                sig_sum,
+                over_threshold_count,
+            )| FilteredBucket {
+                start,
+                count,
+                associated_buckets: associated_buckets
+                    .into_iter()
+                    .flat_map(|(chrom, idx)| [chrom, idx])
+                    .collect(),
+                max_log10_sig,
+                max_abs_effect,
+                count_is_lower_bound,
+                annotations: annotations.into_iter().collect(),
+                associated_buckets_truncated: false,
+                sig_sum,
+                over_threshold_count,
+            },
+        )
+}
+
+// Up to 20 of the first 100 buckets of a chromosome
+fn intervals(
+    bucket_size: u32,
+    annotation_count: u32,
+) -> impl Strategy<Value = Vec<FilteredBucket>> {
+    btree_set(0u64..100, 0..=20).prop_flat_map(move |indexes| {
+        indexes
+            .into_iter()
+            .map(|idx| filtered_bucket(idx * bucket_size as u64 + 1, annotation_count))
+            .collect::<Vec<_>>()
+    })
+}
+
+pub fn filtered_chromosome(
+    index: u8,
+    bucket_size: u32,
+    annotation_count: u32,
+) -> impl Strategy<Value = FilteredChromosome> {
+    (
+        intervals(bucket_size, annotation_count),
+        intervals(bucket_size, annotation_count),
+    )
+        .prop_map(
+            move |(source_intervals, target_intervals)| FilteredChromosome {
+                chrom: format!("chr{}", index + 1),
+                index,
+                bucket_size,
+                target_intervals,
+                source_intervals,
+                truncated: false,
+                bucket_count: 100,
+            },
+        )
+}
+
+// Filter results with between 1 and 4 chromosomes, a subset of chromosomes 0 to 3
+pub fn filtered_data() -> impl Strategy<Value = FilteredData> {
+    bucket_size().prop_flat_map(filtered_data_with_bucket_size)
+}
+
+// filtered_data, but always with buckets of `bucket_size`, e.g., for results that are merged
+// together
+pub fn filtered_data_with_bucket_size(bucket_size: u32) -> impl Strategy<Value = FilteredData> {
+    (btree_set(0u8..4, 1..=4), 0u32..=3)
+        .prop_flat_map(move |(indexes, annotation_count)| {
+            let chromosomes = indexes
+                .into_iter()
+                .map(|index| filtered_chromosome(index, bucket_size, annotation_count))
+                .collect::<Vec<_>>();
+            (
+                chromosomes,
+                Just(bucket_size),
+                Just(annotation_count),
+                result_intervals(),
+                (
+                    0u64..10_000,
+                    0u64..100,
+                    0u64..100,
+                    any::<bool>(),
+                    any::<bool>(),
+                ),
+                (treemap(10_000, 200), treemap(10_000, 200)),
+            )
+        })
+        .prop_map(
+            |(
+                chromosomes,
+                bucket_size,
+                annotation_count,
+                numeric_intervals,
+                (reo_count, dropped_invalid, dropped_buckets, approximate, counts_approximate),
+                (sources, targets),
+            )| FilteredData {
+                chromosomes,
+                bucket_size,
+                numeric_intervals,
+                reo_count,
+                dropped_invalid,
+                dropped_buckets,
+                window_step: None,
+                approximate,
+                counts_approximate,
+                annotation_names: (0..annotation_count)
+                    .map(|i| format!("region {}", i))
+                    .collect(),
+                omitted_empty: Vec::new(),
+                warnings: Vec::new(),
+                sources,
+                targets,
+            },
+        )
+}
+
+impl Arbitrary for Filter {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Filter>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        filter().boxed()
+    }
+}
+
+impl Arbitrary for FilterIntervals {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<FilterIntervals>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        result_intervals().boxed()
+    }
+}
+
+impl Arbitrary for FilteredBucket {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<FilteredBucket>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        (0u64..100, bucket_size())
+            .prop_flat_map(|(idx, bucket_size)| filtered_bucket(idx * bucket_size as u64 + 1, 0))
+            .boxed()
+    }
+}
+
+impl Arbitrary for FilteredChromosome {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<FilteredChromosome>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        (0u8..4, bucket_size())
+            .prop_flat_map(|(index, bucket_size)| filtered_chromosome(index, bucket_size, 0))
+            .boxed()
+    }
+}
+
+impl Arbitrary for FilteredData {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<FilteredData>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        filtered_data().boxed()
+    }
+}
@@ -0,0 +1,274 @@
+use rayon::prelude::*;
+use roaring::RoaringTreemap;
+
+use crate::error::FilterError;
+use crate::filter::{
+    bucket_matching_observations, build_filtered_data, empty_chromosomes, DataFacets,
+    ObservationFilter,
+};
+use crate::filter_data_structures::*;
+use cov_viz_ds::{CoverageData, ExperimentFeatureData, ObservationData};
+
+// The observations that pass everything but the numeric part of a filter, so the numeric
+// intervals can be changed (e.g., while dragging a slider) without re-checking the
+// categorical facets, included features, REO deduplication, or preview sampling of every
+// observation.
+//
+// The selection keeps the rest of the filter it was made with, so it can only be used to
+// produce results for that filter. Use `is_for` to check whether a cached selection can be
+// reused for a new filter.
+pub struct CategoricalSelection<'a> {
+    data: &'a CoverageData,
+    included_features: Option<&'a ExperimentFeatureData>,
+    // The filter the selection was made with, without its numeric intervals
+    filters: Filter,
+    chrom: Option<u8>,
+    window_step: Option<u32>,
+    facets: DataFacets,
+    // Indexes into data.significant_observations and data.nonsignificant_observations.
+    // Observations with NaN values are included so they can be counted.
+    significant: RoaringTreemap,
+    nonsignificant: RoaringTreemap,
+}
+
+impl<'a> CategoricalSelection<'a> {
+    // Whether the selection can be used to filter `data` with `filters`, i.e., everything
+    // but the numeric intervals is the same as when the selection was made
+    pub fn is_for(
+        &self,
+        filters: &Filter,
+        data: &CoverageData,
+        included_features: Option<&ExperimentFeatureData>,
+    ) -> bool {
+        std::ptr::eq(self.data, data)
+            && self.included_features.map(|f| f as *const _)
+                == included_features.map(|f| f as *const _)
+            && self.filters == categorical_part(filters)
+    }
+
+    // The number of selected observations
+    pub fn len(&self) -> u64 {
+        self.significant.len() + self.nonsignificant.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn categorical_part(filters: &Filter) -> Filter {
+    Filter {
+        numeric_intervals: None,
+        ..filters.clone()
+    }
+}
+
+// The indexes of the observations that pass the non-numeric part of the filter
+fn select(observations: &[ObservationData], predicate: &ObservationFilter) -> RoaringTreemap {
+    let positions: Vec<u64> = observations
+        .par_iter()
+        .enumerate()
+        .filter(|(_, observation)| {
+            predicate.matches_dedup(observation)
+                && predicate.matches_sample(observation)
+                && predicate.matches_non_numeric(observation)
+        })
+        .map(|(i, _)| i as u64)
+        .collect();
+    positions.into_iter().collect()
+}
+
+// The first phase of two-phase filtering: finds the observations that pass everything but
+// the numeric intervals of `filters`. Any numeric intervals in `filters` are ignored.
+pub fn prefilter_categorical<'a>(
+    filters: &Filter,
+    data: &'a CoverageData,
+    included_features: Option<&'a ExperimentFeatureData>,
+) -> Result<CategoricalSelection<'a>, FilterError> {
+    let chrom = filters.chromosome_index(&data.chromosomes)?;
+    let window_step = filters.checked_window_step(data.bucket_size)?;
+    let filters = categorical_part(filters);
+    let facets = DataFacets::new(data);
+
+    let mut predicate = ObservationFilter::from_facets(&filters, &facets, included_features);
+    if filters.dedup_reos {
+        predicate.dedup_reos(data);
+    }
+
+    let significant = select(&data.significant_observations, &predicate);
+    let nonsignificant = if predicate.skip_nonsignificants {
+        RoaringTreemap::new()
+    } else {
+        select(&data.nonsignificant_observations, &predicate)
+    };
+
+    Ok(CategoricalSelection {
+        data,
+        included_features,
+        filters,
+        chrom,
+        window_step,
+        facets,
+        significant,
+        nonsignificant,
+    })
+}
+
+// The second phase of two-phase filtering: filters the selected observations by `intervals`
+// and buckets them. The results are the same as filter_coverage_data with the selection's
+// filter and `intervals` as the numeric intervals.
+//
+// When the filter has a minimum pair support, the support is counted among the selected
+// observations.
+pub fn apply_numeric_filter(
+    selection: &CategoricalSelection,
+    data: &CoverageData,
+    intervals: &FilterIntervals,
+) -> Result<FilteredData, FilterError> {
+    if !std::ptr::eq(selection.data, data) {
+        return Err(FilterError::SelectionMismatch);
+    }
+
+    let filters = Filter {
+        numeric_intervals: Some(*intervals),
+        ..selection.filters.clone()
+    };
+    // The selection is already deduplicated, so the predicate doesn't need to do it again
+    let mut predicate =
+        ObservationFilter::from_facets(&filters, &selection.facets, selection.included_features);
+
+    let significant: Vec<u64> = selection.significant.iter().collect();
+    let nonsignificant: Vec<u64> = selection.nonsignificant.iter().collect();
+    let observations = || {
+        significant
+            .par_iter()
+            .map(|i| &data.significant_observations[*i as usize])
+            .chain(
+                nonsignificant
+                    .par_iter()
+                    .map(|i| &data.nonsignificant_observations[*i as usize]),
+            )
+    };
+
+    if let Some(min_support) = filters.min_pair_support {
+        predicate.min_pair_support_in(
+            observations(),
+            min_support,
+            filters.pair_support_excludes_unpaired,
+        );
+    }
+    predicate.restrict_to_chromosome(selection.chrom, data);
+
+    let bucket_results =
+        bucket_matching_observations(observations(), &predicate, |_| true, &data.feature_buckets);
+
+    Ok(build_filtered_data(
+        data,
+        &predicate,
+        bucket_results,
+        empty_chromosomes(selection.chrom, data),
+        selection.window_step,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::filter_coverage_data;
+    use crate::test_data::{add_nan_values, assert_same_results, coverage_data, facet_value_id};
+
+    #[test]
+    fn test_two_phases_match_one_pass() {
+        let mut data = coverage_data(8);
+        add_nan_values(&mut data, 31, 37);
+        let library = facet_value_id(&data, "Library", "Library 0");
+        let included_features = ExperimentFeatureData {
+            sources: (0..500).step_by(2).collect(),
+            targets: (0..500).step_by(2).collect(),
+        };
+        let interval_list = [
+            FilterIntervals::new(),
+            FilterIntervals {
+                effect: (-1.0, 2.0),
+                sig: (0.5, 8.0),
+                ..FilterIntervals::new()
+            },
+            FilterIntervals {
+                effect: (0.0, 0.0),
+                sig: (100.0, 200.0),
+                ..FilterIntervals::new()
+            },
+        ];
+
+        for (filters, included_features) in [
+            (Filter::new(), None),
+            (Filter::new().categorical_facets([library]), None),
+            (Filter::new(), Some(&included_features)),
+            (Filter::new().dedup_reos(true).min_pair_support(2), None),
+            (Filter::new().chrom(1).window_step(500), None),
+            (
+                Filter::new().preview(PreviewOptions {
+                    feature_cap: None,
+                    sample_rate: Some(0.5),
+                }),
+                None,
+            ),
+        ] {
+            // The numeric intervals are ignored by the first phase
+            let selection = prefilter_categorical(
+                &filters.clone().numeric_intervals(interval_list[2]),
+                &data,
+                included_features,
+            )
+            .unwrap();
+            assert!(!selection.is_empty());
+            for intervals in &interval_list {
+                let one_pass = filter_coverage_data(
+                    &filters.clone().numeric_intervals(*intervals),
+                    &data,
+                    included_features,
+                )
+                .unwrap();
+                let two_phase = apply_numeric_filter(&selection, &data, intervals).unwrap();
+                assert_same_results(&two_phase, &one_pass);
+            }
+        }
+    }
+
+    #[test]
+    fn test_selection_reuse() {
+        let data = coverage_data(8);
+        let other_data = coverage_data(9);
+        let library = facet_value_id(&data, "Library", "Library 1");
+        let filters = Filter::new().categorical_facets([library]);
+        let selection = prefilter_categorical(&filters, &data, None).unwrap();
+        let everything = prefilter_categorical(&Filter::new(), &data, None).unwrap();
+        assert!(selection.len() < everything.len());
+        assert_eq!(
+            everything.len(),
+            (data.significant_observations.len() + data.nonsignificant_observations.len()) as u64
+        );
+
+        assert!(selection.is_for(&filters, &data, None));
+        assert!(selection.is_for(
+            &filters.clone().numeric_intervals(FilterIntervals::new()),
+            &data,
+            None
+        ));
+        assert!(!selection.is_for(&Filter::new(), &data, None));
+        assert!(!selection.is_for(&filters.clone().dedup_reos(true), &data, None));
+        assert!(!selection.is_for(&filters, &data.clone(), None));
+        assert!(!selection.is_for(&filters, &data, Some(&ExperimentFeatureData::default())));
+
+        assert_eq!(
+            apply_numeric_filter(&selection, &other_data, &FilterIntervals::new()).unwrap_err(),
+            FilterError::SelectionMismatch
+        );
+        assert_eq!(
+            prefilter_categorical(&Filter::new().chrom_name("chrX"), &data, None)
+                .err()
+                .unwrap(),
+            FilterError::UnknownChromosome("chrX".to_string())
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use rayon::prelude::*;
+use rustc_hash::FxHashSet;
+use serde::Serialize;
+
+use cov_viz_ds::CoverageData;
+
+// The facets filtering relies on
+const DIRECTION_FACET: &str = "Direction";
+pub(crate) const EFFECT_SIZE_FACET: &str = "Effect Size";
+pub(crate) const SIGNIFICANCE_FACET: &str = "Significance";
+
+// Problems found in a CoverageData. Counts are of the items with the problem.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct CoverageDataReport {
+    // Observations whose source or target feature isn't in feature_buckets. These
+    // observations are counted when filtering, but are missing from some or all buckets.
+    pub dangling_feature_references: u64,
+    // feature_buckets entries on a chromosome that isn't in `chromosomes`. These buckets
+    // never show up in filter results.
+    pub unknown_chromosome_buckets: u64,
+    // Observations with a non-finite effect size or significance. An infinite significance
+    // (a p-value of 0) is clamped when filtering, NaNs are dropped.
+    pub non_finite_observations: u64,
+    // The names of well-known facets that aren't in the data
+    pub missing_facets: Vec<String>,
+    // The names of numeric facets without a range. Filtering computes the range from the
+    // observations instead.
+    pub missing_facet_ranges: Vec<String>,
+    // The names of categorical facets whose values are missing (None)
+    pub categorical_facets_without_values: Vec<String>,
+    // The names of categorical facets whose list of values is empty. Filtering works, but
+    // no observation can be selected by these facets.
+    pub categorical_facets_with_empty_values: Vec<String>,
+}
+
+impl CoverageDataReport {
+    pub fn is_valid(&self) -> bool {
+        *self == CoverageDataReport::default()
+    }
+
+    // Whether the data can be filtered. Data that isn't usable makes filtering panic; data
+    // that is usable but not valid can be filtered, but some of it will be left out.
+    pub fn is_usable(&self) -> bool {
+        !self
+            .missing_facets
+            .iter()
+            .any(|name| name == DIRECTION_FACET)
+            && self.categorical_facets_without_values.is_empty()
+    }
+}
+
+// Checks `data` for the inconsistencies that otherwise show up as panics or silently
+// missing buckets when filtering.
+pub fn validate_coverage_data(data: &CoverageData) -> CoverageDataReport {
+    let (dangling_feature_references, non_finite_observations) = data
+        .significant_observations
+        .par_iter()
+        .chain(data.nonsignificant_observations.par_iter())
+        .map(|observation| {
+            let dangling = !data.feature_buckets.contains_key(&observation.source_id)
+                || observation
+                    .target_id
+                    .is_some_and(|id| !data.feature_buckets.contains_key(&id));
+            let non_finite = !observation.effect_size.is_finite()
+                || !observation.neg_log_significance.is_finite();
+            (dangling as u64, non_finite as u64)
+        })
+        .reduce(|| (0, 0), |a, b| (a.0 + b.0, a.1 + b.1));
+
+    let chromosomes: FxHashSet<u8> = data.chromosomes.iter().map(|c| c.index).collect();
+    let unknown_chromosome_buckets = data
+        .feature_buckets
+        .values()
+        .filter(|bucket| !chromosomes.contains(&bucket.chrom))
+        .count() as u64;
+
+    let find_facet = |name: &str| data.facets.iter().find(|f| f.name == name);
+    let missing_facets = [DIRECTION_FACET, EFFECT_SIZE_FACET, SIGNIFICANCE_FACET]
+        .iter()
+        .filter(|name| find_facet(name).is_none())
+        .map(|name| name.to_string())
+        .collect();
+
+    let mut missing_facet_ranges = Vec::new();
+    if find_facet(EFFECT_SIZE_FACET).is_some_and(|f| f.range.is_none()) {
+        missing_facet_ranges.push(EFFECT_SIZE_FACET.to_string());
+    }
+    if find_facet(SIGNIFICANCE_FACET).is_some_and(|f| f.range64.is_none()) {
+        missing_facet_ranges.push(SIGNIFICANCE_FACET.to_string());
+    }
+
+    let categorical_facets = || {
+        data.facets
+            .iter()
+            .filter(|f| f.facet_type == "FacetType.CATEGORICAL" || f.name == DIRECTION_FACET)
+    };
+    let categorical_facets_without_values = categorical_facets()
+        .filter(|f| f.values.is_none())
+        .map(|f| f.name.clone())
+        .collect();
+    let categorical_facets_with_empty_values = categorical_facets()
+        .filter(|f| f.values.as_ref().is_some_and(|values| values.is_empty()))
+        .map(|f| f.name.clone())
+        .collect();
+
+    CoverageDataReport {
+        dangling_feature_references,
+        unknown_chromosome_buckets,
+        non_finite_observations,
+        missing_facets,
+        missing_facet_ranges,
+        categorical_facets_without_values,
+        categorical_facets_with_empty_values,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::filter_coverage_data;
+    use crate::filter_data_structures::Filter;
+    use crate::test_data::coverage_data;
+    use cov_viz_ds::BucketLoc;
+
+    #[test]
+    fn test_valid_data() {
+        let report = validate_coverage_data(&coverage_data(1));
+        assert!(report.is_valid());
+        assert!(report.is_usable());
+    }
+
+    #[test]
+    fn test_dangling_feature_references() {
+        let mut data = coverage_data(1);
+        let observation = &data.significant_observations[0];
+        let source_id = observation.source_id;
+        // Every observation with the feature as its source or target
+        let expected = data
+            .significant_observations
+            .iter()
+            .chain(data.nonsignificant_observations.iter())
+            .filter(|o| o.source_id == source_id || o.target_id == Some(source_id))
+            .count() as u64;
+        data.feature_buckets.remove(&source_id);
+
+        let report = validate_coverage_data(&data);
+        assert_eq!(report.dangling_feature_references, expected);
+        assert!(!report.is_valid());
+        assert!(report.is_usable());
+    }
+
+    #[test]
+    fn test_unknown_chromosome_buckets() {
+        let mut data = coverage_data(1);
+        data.feature_buckets
+            .insert(1_000_000, BucketLoc { chrom: 99, idx: 0 });
+        data.feature_buckets
+            .insert(1_000_001, BucketLoc { chrom: 99, idx: 1 });
+
+        let report = validate_coverage_data(&data);
+        assert_eq!(report.unknown_chromosome_buckets, 2);
+        assert!(report.is_usable());
+    }
+
+    #[test]
+    fn test_non_finite_observations() {
+        let mut data = coverage_data(1);
+        data.significant_observations[0].effect_size = f32::NAN;
+        data.significant_observations[1].neg_log_significance = f64::INFINITY;
+        data.nonsignificant_observations[0].effect_size = f32::NEG_INFINITY;
+
+        let report = validate_coverage_data(&data);
+        assert_eq!(report.non_finite_observations, 3);
+        assert!(report.is_usable());
+    }
+
+    #[test]
+    fn test_missing_facets() {
+        let mut data = coverage_data(1);
+        data.facets.retain(|f| f.name != SIGNIFICANCE_FACET);
+        let report = validate_coverage_data(&data);
+        assert_eq!(report.missing_facets, vec![SIGNIFICANCE_FACET.to_string()]);
+        assert!(report.is_usable());
+
+        data.facets.retain(|f| f.name != DIRECTION_FACET);
+        let report = validate_coverage_data(&data);
+        assert_eq!(
+            report.missing_facets,
+            vec![DIRECTION_FACET.to_string(), SIGNIFICANCE_FACET.to_string()]
+        );
+        assert!(!report.is_usable());
+    }
+
+    #[test]
+    fn test_missing_facet_ranges() {
+        let mut data = coverage_data(1);
+        for facet in &mut data.facets {
+            facet.range = None;
+            facet.range64 = None;
+        }
+        let report = validate_coverage_data(&data);
+        assert_eq!(
+            report.missing_facet_ranges,
+            vec![
+                EFFECT_SIZE_FACET.to_string(),
+                SIGNIFICANCE_FACET.to_string()
+            ]
+        );
+        assert!(report.is_usable());
+    }
+
+    #[test]
+    fn test_categorical_facets_without_values() {
+        let mut data = coverage_data(1);
+        for facet in &mut data.facets {
+            if facet.name == "Library" {
+                facet.values = None;
+            }
+        }
+        let report = validate_coverage_data(&data);
+        assert_eq!(
+            report.categorical_facets_without_values,
+            vec!["Library".to_string()]
+        );
+        assert!(report.categorical_facets_with_empty_values.is_empty());
+        assert!(!report.is_usable());
+    }
+
+    #[test]
+    fn test_categorical_facets_with_empty_values() {
+        let mut data = coverage_data(1);
+        for facet in &mut data.facets {
+            if facet.name == "Library" {
+                facet.values = Some(Default::default());
+            }
+        }
+        let report = validate_coverage_data(&data);
+        assert_eq!(
+            report.categorical_facets_with_empty_values,
+            vec!["Library".to_string()]
+        );
+        assert!(report.categorical_facets_without_values.is_empty());
+        assert!(!report.is_valid());
+        assert!(report.is_usable());
+        // Usable data can be filtered
+        filter_coverage_data(&Filter::new(), &data, None).unwrap();
+    }
+}
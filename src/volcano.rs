@@ -0,0 +1,214 @@
+use std::sync::Arc;
+
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::error::FilterError;
+use crate::filter::{canonical_observations, DataFacets, ObservationFilter};
+use crate::filter_data_structures::*;
+use cov_viz_ds::{CoverageData, ExperimentFeatureData};
+
+// Counts of the filtered observations in a grid over effect size and significance, for
+// drawing a volcano plot without sending every observation to the browser.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct VolcanoHistogram {
+    // The bin edges, one more than the number of bins. Each bin includes its lower edge; the
+    // last bin also includes its upper edge.
+    pub effect_edges: Vec<f32>,
+    pub sig_edges: Vec<f64>,
+    // The counts, row by row: the count for effect bin `e` and significance bin `s` is
+    // counts[s * effect bin count + e]
+    pub counts: Vec<u64>,
+}
+
+// `bin_count` evenly spaced bins from `min` to `max`
+fn bin_edges(min: f64, max: f64, bin_count: usize) -> Vec<f64> {
+    let width = (max - min) / bin_count as f64;
+    (0..=bin_count)
+        .map(|i| {
+            if i == bin_count {
+                max
+            } else {
+                min + width * i as f64
+            }
+        })
+        .collect()
+}
+
+// The bin a value falls in. Values outside of the edges go in the first or last bin.
+fn bin_index<T: PartialOrd>(edges: &[T], value: T) -> usize {
+    edges[1..edges.len() - 1].partition_point(|edge| *edge <= value)
+}
+
+// Bins the observations that pass the filter by effect size and (clamped) significance.
+// `bins` is the number of (effect size, significance) bins, and the bins evenly divide the
+// ranges of the data set's "Effect Size" and "Significance" facets. When the filter is
+// restricted to a chromosome only observations with a source or target on that chromosome
+// are counted.
+pub fn volcano_histogram(
+    filters: &Filter,
+    data: &CoverageData,
+    included_features: Option<&ExperimentFeatureData>,
+    bins: (usize, usize),
+) -> Result<VolcanoHistogram, FilterError> {
+    let chrom = filters.chromosome_index(&data.chromosomes)?;
+    let facets = DataFacets::new(data);
+    let mut predicate = ObservationFilter::from_facets(filters, &facets, included_features);
+    predicate.prepare_observations(filters, data, chrom, || {
+        Arc::new(canonical_observations(data))
+    });
+
+    let (effect_bins, sig_bins) = bins;
+    let effect_edges: Vec<f32> = bin_edges(
+        facets.effect_size_range.0 as f64,
+        facets.effect_size_range.1 as f64,
+        effect_bins,
+    )
+    .into_iter()
+    .map(|edge| edge as f32)
+    .collect();
+    let sig_edges = bin_edges(
+        facets.sig_range.0.min(predicate.sig_ceiling),
+        facets.sig_range.1.min(predicate.sig_ceiling),
+        sig_bins,
+    );
+
+    let bin_count = effect_bins * sig_bins;
+    if bin_count == 0 {
+        return Ok(VolcanoHistogram {
+            effect_edges,
+            sig_edges,
+            counts: Vec::new(),
+        });
+    }
+
+    let counts = predicate
+        .observations(data)
+        .filter(|observation| {
+            predicate.matches(observation) && predicate.on_included_chromosome(observation, data)
+        })
+        .fold(
+            || vec![0u64; bin_count],
+            |mut counts, observation| {
+                let effect_bin = bin_index(&effect_edges, observation.effect_size);
+                let sig_bin = bin_index(&sig_edges, predicate.significance(observation));
+                counts[sig_bin * effect_bins + effect_bin] += 1;
+                counts
+            },
+        )
+        .reduce(
+            || vec![0u64; bin_count],
+            |mut counts1, counts2| {
+                counts1
+                    .iter_mut()
+                    .zip(counts2)
+                    .for_each(|(count1, count2)| *count1 += count2);
+                counts1
+            },
+        );
+
+    Ok(VolcanoHistogram {
+        effect_edges,
+        sig_edges,
+        counts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::coverage_data;
+    use crate::validate::{EFFECT_SIZE_FACET, SIGNIFICANCE_FACET};
+    use cov_viz_ds::{FacetRange, FacetRange64};
+
+    // Data whose effect sizes range from -4 to 4 and significances from 0 to 8, with one
+    // observation for each (effect size, significance)
+    fn volcano_data(values: &[(f32, f64)]) -> CoverageData {
+        let mut data = coverage_data(15);
+        data.significant_observations.truncate(values.len());
+        data.nonsignificant_observations.clear();
+        for (observation, (effect_size, sig)) in
+            data.significant_observations.iter_mut().zip(values)
+        {
+            observation.effect_size = *effect_size;
+            observation.neg_log_significance = *sig;
+        }
+        for facet in &mut data.facets {
+            if facet.name == EFFECT_SIZE_FACET {
+                facet.range = Some(FacetRange(-4.0, 4.0));
+            } else if facet.name == SIGNIFICANCE_FACET {
+                facet.range64 = Some(FacetRange64(0.0, 8.0));
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_known_bins() {
+        let data = volcano_data(&[
+            (-4.0, 0.0),
+            // On the lower edges of bins
+            (-2.0, 2.0),
+            (0.0, 4.0),
+            // On the upper edges of the last bins
+            (4.0, 8.0),
+            (1.99, 7.99),
+            // Outside of the ranges
+            (10.0, 50.0),
+            (-10.0, f64::INFINITY),
+            // Never counted
+            (f32::NAN, 1.0),
+        ]);
+        let histogram = volcano_histogram(&Filter::new(), &data, None, (4, 4)).unwrap();
+        assert_eq!(histogram.effect_edges, vec![-4.0, -2.0, 0.0, 2.0, 4.0]);
+        assert_eq!(histogram.sig_edges, vec![0.0, 2.0, 4.0, 6.0, 8.0]);
+        #[rustfmt::skip]
+        let expected = vec![
+            1, 0, 0, 0,
+            0, 1, 0, 0,
+            0, 0, 1, 0,
+            1, 0, 1, 2,
+        ];
+        assert_eq!(histogram.counts, expected);
+
+        // Fewer bins
+        let histogram = volcano_histogram(&Filter::new(), &data, None, (1, 2)).unwrap();
+        assert_eq!(histogram.counts, vec![2, 5]);
+        let histogram = volcano_histogram(&Filter::new(), &data, None, (0, 2)).unwrap();
+        assert!(histogram.counts.is_empty());
+    }
+
+    #[test]
+    fn test_filtered_observations() {
+        let data = coverage_data(15);
+        let observations = || {
+            data.significant_observations
+                .iter()
+                .chain(data.nonsignificant_observations.iter())
+        };
+        let total = |histogram: &VolcanoHistogram| histogram.counts.iter().sum::<u64>();
+
+        let histogram = volcano_histogram(&Filter::new(), &data, None, (10, 10)).unwrap();
+        assert_eq!(total(&histogram), observations().count() as u64);
+
+        let mut filters = Filter::new().chrom(2);
+        let histogram = volcano_histogram(&filters, &data, None, (10, 10)).unwrap();
+        let on_chromosome = observations()
+            .filter(|o| {
+                std::iter::once(o.source_id)
+                    .chain(o.target_id)
+                    .any(|id| data.feature_buckets[&id].chrom == 2)
+            })
+            .count() as u64;
+        assert_eq!(total(&histogram), on_chromosome);
+
+        filters.numeric_intervals = Some(FilterIntervals {
+            effect: (0.0, 5.0),
+            ..FilterIntervals::new()
+        });
+        let histogram = volcano_histogram(&filters, &data, None, (2, 1)).unwrap();
+        // Only the positive effect sizes
+        assert_eq!(histogram.counts[0], 0);
+        assert!(histogram.counts[1] > 0);
+    }
+}
@@ -0,0 +1,130 @@
+use wasm_bindgen::prelude::*;
+
+use crate::filter_data_structures::*;
+use crate::merge::merge_filtered_data;
+use crate::refilter::refilter_filtered_data;
+
+// JavaScript bindings for the parts of the crate that work on already filtered data.
+// FilteredData is passed back and forth in its bincode serialized form.
+//
+// The crate isn't built as a cdylib by default, so build the module with
+// `cargo rustc --release --target wasm32-unknown-unknown --features wasm --crate-type cdylib`
+// and generate the JavaScript bindings with `wasm-bindgen --target web` on the result.
+
+fn decode(bytes: &[u8]) -> Result<FilteredData, JsError> {
+    bincode::deserialize(bytes).map_err(|e| JsError::new(&e.to_string()))
+}
+
+fn encode(data: &FilteredData) -> Result<Vec<u8>, JsError> {
+    bincode::serialize(data).map_err(|e| JsError::new(&e.to_string()))
+}
+
+// Merges two filtered data sets. Every chromosome in either data set is included, in order of
+// chromosome index.
+#[wasm_bindgen]
+pub fn merge(data1: &[u8], data2: &[u8]) -> Result<Vec<u8>, JsError> {
+    let data1 = decode(data1)?;
+    let data2 = decode(data2)?;
+
+    let mut chromosome_list: Vec<String> =
+        data1.chromosomes.iter().map(|c| c.chrom.clone()).collect();
+    for chromosome in &data2.chromosomes {
+        if !chromosome_list.contains(&chromosome.chrom) {
+            chromosome_list.push(chromosome.chrom.clone());
+        }
+    }
+
+    let merged = merge_filtered_data(vec![data1, data2], chromosome_list)
+        .map_err(|e| JsError::new(&e.to_string()))?;
+    encode(&merged)
+}
+
+#[wasm_bindgen]
+pub fn refilter(data: &[u8], sig_min: f64, effect_abs_min: f32) -> Result<Vec<u8>, JsError> {
+    encode(&refilter_filtered_data(
+        &decode(data)?,
+        sig_min,
+        effect_abs_min,
+    ))
+}
+
+// wasm-pack test --node --features wasm runs these in Node. They also run natively, as long
+// as nothing creates a JsError.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::filter_coverage_data;
+    use crate::test_data::{assert_same_results, coverage_data};
+    #[cfg(target_arch = "wasm32")]
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+    #[cfg_attr(not(target_arch = "wasm32"), test)]
+    fn test_merge_round_trip() {
+        let data1 = filter_coverage_data(&Filter::new(), &coverage_data(1), None).unwrap();
+        let data2 = filter_coverage_data(&Filter::new(), &coverage_data(2), None).unwrap();
+
+        let merged = decode(
+            &merge(&encode(&data1).unwrap(), &encode(&data2).unwrap())
+                .map_err(|_| "merge failed")
+                .unwrap(),
+        )
+        .map_err(|_| "decode failed")
+        .unwrap();
+
+        let chromosome_list = data1.chromosomes.iter().map(|c| c.chrom.clone()).collect();
+        assert_same_results(
+            &merged,
+            &merge_filtered_data(vec![data1, data2], chromosome_list).unwrap(),
+        );
+    }
+
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+    #[cfg_attr(not(target_arch = "wasm32"), test)]
+    fn test_merge_chromosome_order() {
+        let filtered = |seed, chroms: &[&str]| {
+            let mut data =
+                filter_coverage_data(&Filter::new(), &coverage_data(seed), None).unwrap();
+            // Out of order, the way older versions could store them
+            data.chromosomes = chroms
+                .iter()
+                .map(|chrom| {
+                    data.chromosomes
+                        .iter()
+                        .find(|c| c.chrom == *chrom)
+                        .unwrap()
+                        .clone()
+                })
+                .collect();
+            encode(&data).unwrap()
+        };
+
+        for (chroms1, chroms2) in [
+            (vec!["chr4", "chr2"], vec!["chr3", "chr1"]),
+            (vec!["chr3"], vec!["chr3", "chr2", "chr1"]),
+            (vec!["chr2", "chr1"], vec![]),
+        ] {
+            let merged = decode(
+                &merge(&filtered(1, &chroms1), &filtered(2, &chroms2))
+                    .map_err(|_| "merge failed")
+                    .unwrap(),
+            )
+            .map_err(|_| "decode failed")
+            .unwrap();
+
+            let mut expected: Vec<&str> = chroms1.iter().chain(&chroms2).copied().collect();
+            expected.sort_unstable();
+            expected.dedup();
+            let chroms: Vec<&str> = merged
+                .chromosomes
+                .iter()
+                .map(|c| c.chrom.as_str())
+                .collect();
+            assert_eq!(chroms, expected);
+            assert!(merged
+                .chromosomes
+                .windows(2)
+                .all(|w| w[0].index < w[1].index));
+        }
+    }
+}
@@ -0,0 +1,337 @@
+// Uses the C bindings the way a C program would, through the functions declared in
+// include/exp_viz.h
+
+use std::ffi::{c_char, CStr, CString};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::Barrier;
+use std::thread;
+
+use cov_viz_ds::CoverageData;
+use exp_viz::{
+    filter_coverage_data, merge_filtered_data, Filter, FilterIntervals, FilteredData,
+    SyntheticCoverageDataBuilder,
+};
+
+#[repr(C)]
+struct CoverageHandle {
+    _private: [u8; 0],
+}
+
+extern "C" {
+    fn exp_viz_load(path: *const c_char, error: *mut *mut c_char) -> *mut CoverageHandle;
+    fn exp_viz_filter(
+        handle: *const CoverageHandle,
+        filter_json: *const c_char,
+        out_buf: *mut *mut u8,
+        out_len: *mut usize,
+        error: *mut *mut c_char,
+    ) -> i32;
+    fn exp_viz_merge(
+        bufs: *const *const u8,
+        lens: *const usize,
+        count: usize,
+        chromosomes_json: *const c_char,
+        out_buf: *mut *mut u8,
+        out_len: *mut usize,
+        error: *mut *mut c_char,
+    ) -> i32;
+    fn exp_viz_mem_usage(handle: *const CoverageHandle) -> usize;
+    fn exp_viz_clone_handle(handle: *const CoverageHandle) -> *mut CoverageHandle;
+    fn exp_viz_free(handle: *mut CoverageHandle);
+    fn exp_viz_free_bytes(buf: *mut u8, len: usize);
+    fn exp_viz_free_string(s: *mut c_char);
+}
+
+fn coverage_data(seed: u64) -> CoverageData {
+    SyntheticCoverageDataBuilder::new()
+        .seed(seed)
+        .chromosomes(4)
+        .buckets_per_chromosome(50)
+        .bucket_size(1_000)
+        .features(500)
+        .observations(5_000)
+        .build()
+}
+
+// Writes the data to a file only this test uses
+fn write_data(data: &CoverageData, name: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("exp_viz_ffi_{}_{}.bin", name, std::process::id()));
+    bincode::serialize_into(File::create(&path).unwrap(), data).unwrap();
+    path
+}
+
+// Takes ownership of an error message from the bindings
+unsafe fn take_error(error: *mut c_char) -> String {
+    assert!(!error.is_null());
+    let message = CStr::from_ptr(error).to_str().unwrap().to_string();
+    exp_viz_free_string(error);
+    message
+}
+
+unsafe fn load(path: &Path) -> *mut CoverageHandle {
+    let path = CString::new(path.to_str().unwrap()).unwrap();
+    let mut error = ptr::null_mut();
+    let handle = exp_viz_load(path.as_ptr(), &mut error);
+    assert!(!handle.is_null(), "{}", take_error(error));
+    handle
+}
+
+// The bincode serialized results of filtering, or the error message
+unsafe fn filter(handle: *const CoverageHandle, filter_json: &str) -> Result<Vec<u8>, String> {
+    let filter_json = CString::new(filter_json).unwrap();
+    let mut buf = ptr::null_mut();
+    let mut len = 0;
+    let mut error = ptr::null_mut();
+    if exp_viz_filter(handle, filter_json.as_ptr(), &mut buf, &mut len, &mut error) != 0 {
+        return Err(take_error(error));
+    }
+    let bytes = std::slice::from_raw_parts(buf, len).to_vec();
+    exp_viz_free_bytes(buf, len);
+    Ok(bytes)
+}
+
+// The bincode serialized results of merging the serialized results, or the error message
+unsafe fn merge(results: &[Vec<u8>], chromosomes_json: &str) -> Result<Vec<u8>, String> {
+    let bufs: Vec<*const u8> = results.iter().map(|result| result.as_ptr()).collect();
+    let lens: Vec<usize> = results.iter().map(|result| result.len()).collect();
+    let chromosomes_json = CString::new(chromosomes_json).unwrap();
+    let mut buf = ptr::null_mut();
+    let mut len = 0;
+    let mut error = ptr::null_mut();
+    if exp_viz_merge(
+        bufs.as_ptr(),
+        lens.as_ptr(),
+        results.len(),
+        chromosomes_json.as_ptr(),
+        &mut buf,
+        &mut len,
+        &mut error,
+    ) != 0
+    {
+        return Err(take_error(error));
+    }
+    let bytes = std::slice::from_raw_parts(buf, len).to_vec();
+    exp_viz_free_bytes(buf, len);
+    Ok(bytes)
+}
+
+#[test]
+fn test_filter_matches_rust_api() {
+    let data = coverage_data(1);
+    let path = write_data(&data, "filter");
+    unsafe {
+        let handle = load(&path);
+        assert!(exp_viz_mem_usage(handle) > 0);
+
+        let expected = |filters: &Filter| {
+            bincode::serialize(&filter_coverage_data(filters, &data, None).unwrap()).unwrap()
+        };
+        assert_eq!(filter(handle, "{}").unwrap(), expected(&Filter::new()));
+
+        let mut filters = Filter::new();
+        filters.chrom_name = Some("2".to_string());
+        filters.categorical_facets.insert(2);
+        filters.numeric_intervals = Some(FilterIntervals {
+            effect: (-1.0, 1.0),
+            sig: (2.0, 10.0),
+            ..FilterIntervals::new()
+        });
+        assert_eq!(
+            filter(
+                handle,
+                r#"{"chrom_name": "2", "categorical_facets": [2],
+                    "numeric_intervals": {"effect": [-1, 1], "sig": [2, 10]}}"#
+            )
+            .unwrap(),
+            expected(&filters)
+        );
+        // The JSON form of a Filter
+        assert_eq!(
+            filter(handle, &serde_json::to_string(&filters).unwrap()).unwrap(),
+            expected(&filters)
+        );
+
+        exp_viz_free(handle);
+    }
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_errors() {
+    let path = write_data(&coverage_data(2), "errors");
+    unsafe {
+        let missing = CString::new("/nonexistent/coverage.bin").unwrap();
+        let mut error = ptr::null_mut();
+        assert!(exp_viz_load(missing.as_ptr(), &mut error).is_null());
+        assert!(take_error(error).starts_with("/nonexistent/coverage.bin"));
+        // The error is optional
+        assert!(exp_viz_load(missing.as_ptr(), ptr::null_mut()).is_null());
+        assert!(exp_viz_load(ptr::null(), ptr::null_mut()).is_null());
+
+        let handle = load(&path);
+        assert!(filter(handle, "not json")
+            .unwrap_err()
+            .starts_with("Invalid filter"));
+        assert!(filter(handle, r#"{"chrom_name": "chrZ"}"#).is_err());
+        assert!(filter(ptr::null(), "{}").is_err());
+        assert_eq!(exp_viz_mem_usage(ptr::null()), 0);
+        assert!(exp_viz_clone_handle(ptr::null()).is_null());
+        exp_viz_free(handle);
+
+        // Freeing null does nothing
+        exp_viz_free(ptr::null_mut());
+        exp_viz_free_bytes(ptr::null_mut(), 0);
+        exp_viz_free_string(ptr::null_mut());
+    }
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_cloned_handle_outlives_original() {
+    let path = write_data(&coverage_data(3), "clone");
+    unsafe {
+        let handle = load(&path);
+        let expected = filter(handle, "{}").unwrap();
+        let clone = exp_viz_clone_handle(handle);
+        exp_viz_free(handle);
+        assert_eq!(filter(clone, "{}").unwrap(), expected);
+        exp_viz_free(clone);
+    }
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_concurrent_filtering() {
+    let data = coverage_data(4);
+    let path = write_data(&data, "concurrent");
+    let filters: Vec<Filter> = (0..8u8)
+        .map(|i| {
+            let mut filters = Filter::new();
+            if i % 2 == 0 {
+                filters.chrom = Some(i / 2);
+            }
+            filters.numeric_intervals = Some(FilterIntervals {
+                effect: (-1.0 - i as f32 / 2.0, 1.0 + i as f32 / 2.0),
+                sig: (i as f64, 20.0),
+                ..FilterIntervals::new()
+            });
+            filters
+        })
+        .collect();
+    let expected: Vec<Vec<u8>> = filters
+        .iter()
+        .map(|filters| {
+            bincode::serialize(&filter_coverage_data(filters, &data, None).unwrap()).unwrap()
+        })
+        .collect();
+
+    unsafe {
+        let handle = load(&path);
+        // Raw pointers can't be sent to other threads, so each thread gets its handle as an
+        // address
+        let clones: Vec<usize> = (0..8)
+            .map(|_| exp_viz_clone_handle(handle) as usize)
+            .collect();
+        exp_viz_free(handle);
+
+        // All the threads filter the one copy of the data at the same time
+        let barrier = Barrier::new(8);
+        let results: Vec<Vec<u8>> = thread::scope(|scope| {
+            let threads: Vec<_> = clones
+                .iter()
+                .zip(&filters)
+                .map(|(clone, filters)| {
+                    let barrier = &barrier;
+                    let filter_json = serde_json::to_string(filters).unwrap();
+                    scope.spawn(move || {
+                        barrier.wait();
+                        let clone = *clone as *mut CoverageHandle;
+                        let result = filter(clone, &filter_json).unwrap();
+                        exp_viz_free(clone);
+                        result
+                    })
+                })
+                .collect();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .collect()
+        });
+        assert_eq!(results, expected);
+    }
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_merge_matches_rust_api() {
+    let data = [coverage_data(5), coverage_data(6)];
+    let paths = [
+        write_data(&data[0], "merge_0"),
+        write_data(&data[1], "merge_1"),
+    ];
+    let chromosome_list: Vec<String> = data[0]
+        .chromosomes
+        .iter()
+        .map(|chromosome| chromosome.chrom.clone())
+        .collect();
+    let chromosomes_json = serde_json::to_string(&chromosome_list).unwrap();
+    let filtered_data = data
+        .iter()
+        .map(|data| filter_coverage_data(&Filter::new(), data, None).unwrap())
+        .collect();
+    let expected =
+        bincode::serialize(&merge_filtered_data(filtered_data, chromosome_list).unwrap()).unwrap();
+
+    unsafe {
+        let handles = [load(&paths[0]), load(&paths[1])];
+        let results: Vec<Vec<u8>> = handles
+            .iter()
+            .map(|handle| filter(*handle, "{}").unwrap())
+            .collect();
+        let merged = merge(&results, &chromosomes_json).unwrap();
+        assert_eq!(merged, expected);
+        // The merged results are FilteredData, so they can be merged again
+        let remerged = merge(&[merged], &chromosomes_json).unwrap();
+        let _: FilteredData = bincode::deserialize(&remerged).unwrap();
+
+        assert_eq!(
+            merge(&[], &chromosomes_json).unwrap_err(),
+            "No data to merge"
+        );
+        assert!(merge(&results, "not json")
+            .unwrap_err()
+            .starts_with("Invalid chromosome list"));
+        assert!(
+            merge(&[results[0].clone(), vec![1, 2, 3]], &chromosomes_json)
+                .unwrap_err()
+                .starts_with("Invalid result 1")
+        );
+        let mut error = ptr::null_mut();
+        assert_ne!(
+            exp_viz_merge(
+                ptr::null(),
+                ptr::null(),
+                1,
+                ptr::null(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &mut error,
+            ),
+            0
+        );
+        assert_eq!(
+            take_error(error),
+            "bufs, lens, out_buf, and out_len must not be null"
+        );
+
+        for handle in handles {
+            exp_viz_free(handle);
+        }
+    }
+    for path in paths {
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+// Property tests of crate-level invariants, using the strategies in exp_viz::testing
+
+use cov_viz_ds::{CoverageData, ExperimentFeatureData};
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use exp_viz::testing::{
+    coverage_data, experiment_feature_data, filter, filter_intervals,
+    filtered_data_with_bucket_size,
+};
+use exp_viz::{
+    filter_coverage_data, intersect_coverage_data_features, merge_filtered_data,
+    union_coverage_data_features, Filter, FilterIntervals, FilteredData,
+};
+
+fn chromosome_list() -> Vec<String> {
+    (1..=4).map(|number| format!("chr{}", number)).collect()
+}
+
+// Results are compared by their serialized form, which only depends on their contents
+fn json(data: &FilteredData) -> serde_json::Value {
+    serde_json::to_value(data).unwrap()
+}
+
+fn merge(data: Vec<FilteredData>) -> FilteredData {
+    merge_filtered_data(data, chromosome_list()).unwrap()
+}
+
+// `count` results with the same bucket size, so they can be merged
+fn mergeable_data(count: usize) -> impl Strategy<Value = Vec<FilteredData>> {
+    prop::sample::select(vec![100u32, 1_000, 2_000])
+        .prop_flat_map(move |bucket_size| vec(filtered_data_with_bucket_size(bucket_size), count))
+}
+
+// `intervals` widened by up to `widen` on each side
+fn wider(intervals: FilterIntervals, widen: (f32, f32, f64, f64)) -> FilterIntervals {
+    FilterIntervals {
+        effect: (intervals.effect.0 - widen.0, intervals.effect.1 + widen.1),
+        sig: (intervals.sig.0 - widen.2, intervals.sig.1 + widen.3),
+        ..intervals
+    }
+}
+
+fn copy(feature_data: &[ExperimentFeatureData]) -> Vec<ExperimentFeatureData> {
+    feature_data
+        .iter()
+        .map(|features| ExperimentFeatureData {
+            sources: features.sources.clone(),
+            targets: features.targets.clone(),
+        })
+        .collect()
+}
+
+fn is_subset(subset: &ExperimentFeatureData, superset: &ExperimentFeatureData) -> bool {
+    subset.sources.is_subset(&superset.sources) && subset.targets.is_subset(&superset.targets)
+}
+
+proptest! {
+    #[test]
+    fn json_round_trip(data in any::<FilteredData>()) {
+        let serialized = serde_json::to_string(&data).unwrap();
+        let from_json: FilteredData = serde_json::from_str(&serialized).unwrap();
+        prop_assert_eq!(serde_json::to_string(&from_json).unwrap(), serialized);
+    }
+
+    #[test]
+    fn bincode_round_trip(data in any::<FilteredData>()) {
+        let serialized = bincode::serialize(&data).unwrap();
+        let from_bincode: FilteredData = bincode::deserialize(&serialized).unwrap();
+        prop_assert_eq!(bincode::serialize(&from_bincode).unwrap(), serialized);
+    }
+
+    #[test]
+    fn merge_is_commutative(data in mergeable_data(2)) {
+        let reversed = data.iter().rev().cloned().collect();
+        prop_assert_eq!(json(&merge(data)), json(&merge(reversed)));
+    }
+
+    #[test]
+    fn merge_is_associative(data in mergeable_data(3)) {
+        let left = merge(vec![merge(data[..2].to_vec()), data[2].clone()]);
+        let right = merge(vec![data[0].clone(), merge(data[1..].to_vec())]);
+        prop_assert_eq!(json(&left), json(&right));
+        prop_assert_eq!(json(&left), json(&merge(data)));
+    }
+
+    #[test]
+    fn intersect_is_subset_of_union(feature_data in vec(experiment_feature_data(), 0..=4)) {
+        let intersection = intersect_coverage_data_features(copy(&feature_data));
+        let union = union_coverage_data_features(copy(&feature_data));
+        prop_assert!(is_subset(&intersection, &union));
+        for features in &feature_data {
+            prop_assert!(is_subset(&intersection, features));
+            prop_assert!(is_subset(features, &union));
+        }
+    }
+
+    #[test]
+    fn reo_count_grows_as_intervals_widen(
+        data in coverage_data(),
+        filters in filter(),
+        intervals in filter_intervals(),
+        widen in (0.0..5.0f32, 0.0..5.0f32, 0.0..5.0f64, 0.0..5.0f64),
+    ) {
+        // Previews sample observations and cap the work per bucket, so they're left out
+        let narrow = Filter {
+            numeric_intervals: Some(intervals),
+            preview: None,
+            ..filters
+        };
+        let wide = Filter {
+            numeric_intervals: Some(wider(intervals, widen)),
+            ..narrow.clone()
+        };
+        let unfiltered = Filter {
+            numeric_intervals: None,
+            ..narrow.clone()
+        };
+        let count = |filters: &Filter, data: &CoverageData| {
+            filter_coverage_data(filters, data, None).map(|result| result.reo_count)
+        };
+        if let Ok(narrow_count) = count(&narrow, &data) {
+            let wide_count = count(&wide, &data).unwrap();
+            prop_assert!(narrow_count <= wide_count);
+            prop_assert!(wide_count <= count(&unfiltered, &data).unwrap());
+        }
+    }
+}